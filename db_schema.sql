@@ -5,4 +5,58 @@ CREATE TABLE IF NOT EXISTS ExExBackfill (
     BlockNumber INT UNIQUE,
     ArweaveHash LONGTEXT,
     BlockHash LONTEXT
+);
+
+DROP TABLE IF EXISTS ExExBackfillCheckpoint;
+
+CREATE TABLE IF NOT EXISTS ExExBackfillCheckpoint (
+    ScanKey VARCHAR(255) PRIMARY KEY,
+    BlockNumber INT,
+    Cursor LONGTEXT,
+    Archived LONGTEXT
+);
+
+DROP TABLE IF EXISTS ExExBackfillConflicts;
+
+CREATE TABLE IF NOT EXISTS ExExBackfillConflicts (
+    Id INT AUTO_INCREMENT PRIMARY KEY,
+    BlockNumber INT,
+    ArweaveHash VARCHAR(64) UNIQUE,
+    ArchivedBlockHash LONGTEXT,
+    CanonicalBlockHash LONGTEXT
+);
+
+DROP TABLE IF EXISTS ExExBackfillRetries;
+
+CREATE TABLE IF NOT EXISTS ExExBackfillRetries (
+    BlockNumber INT PRIMARY KEY,
+    Attempts INT,
+    LastError LONGTEXT,
+    NextAttemptAt BIGINT
+);
+
+DROP TABLE IF EXISTS ExExBackfillDeadLetters;
+
+CREATE TABLE IF NOT EXISTS ExExBackfillDeadLetters (
+    BlockNumber INT PRIMARY KEY,
+    Attempts INT,
+    LastError LONGTEXT,
+    FailedAt BIGINT
+);
+
+DROP TABLE IF EXISTS ExExBackfillUploads;
+
+CREATE TABLE IF NOT EXISTS ExExBackfillUploads (
+    UploadKey VARCHAR(255) PRIMARY KEY,
+    ArweaveHash VARCHAR(64),
+    ResumeState LONGTEXT
+);
+
+DROP TABLE IF EXISTS ExExBackfillReceipts;
+
+CREATE TABLE IF NOT EXISTS ExExBackfillReceipts (
+    ArweaveHash VARCHAR(64) PRIMARY KEY,
+    DeadlineHeight BIGINT,
+    Timestamp BIGINT,
+    Receipt LONGTEXT
 );
\ No newline at end of file
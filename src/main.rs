@@ -1,6 +1,8 @@
 use crate::utils::backfill::backfill_blocks;
+use crate::utils::codec_benchmark::{benchmark_codecs, benchmark_range_from_env};
 use crate::utils::server_handlers::{
-    handle_get_block_by_hash, handle_get_block_by_id, handle_weave_gm,
+    handle_get_block_by_hash, handle_get_block_by_id, handle_get_receipt_by_block_id,
+    handle_weave_gm,
 };
 use axum::{routing::get, Router};
 use http::Method;
@@ -20,10 +22,24 @@ async fn main() -> shuttle_axum::ShuttleAxum {
         .layer(cors)
         .route("/", get(handle_weave_gm))
         .route("/block/id/:id", get(handle_get_block_by_id))
-        .route("/block/hash/:hash", get(handle_get_block_by_hash));
+        .route("/block/hash/:hash", get(handle_get_block_by_hash))
+        .route("/block/receipt/:id", get(handle_get_receipt_by_block_id));
 
+    let backfill = task::spawn(async move {
+        let result = match benchmark_range_from_env() {
+            Ok(Some(range)) => benchmark_codecs(range).await,
+            Ok(None) => backfill_blocks(5000).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("backfill run aborted: {}", e);
+        }
+    });
+    // a panic would otherwise end the backfill without a trace
     task::spawn(async move {
-        let _ = backfill_blocks(5000).await;
+        if let Err(e) = backfill.await {
+            eprintln!("backfill task crashed: {}", e);
+        }
     });
 
     Ok(router.into())
@@ -0,0 +1,176 @@
+use crate::utils::archive_envelope::{self, CURRENT_SCHEMA_VERSION};
+use crate::utils::block::{Block, BlockPayload, DecodeError, Receipt};
+use crate::utils::block_compact::{CompactBlock, CompactBlockPayload};
+use crate::utils::compression::Compressor;
+use crate::utils::env_var::get_env_var;
+use anyhow::Error;
+use borsh::to_vec;
+
+/// Payload layout of an archive, stored in its `WeaveVM:Encoding` tag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveEncoding {
+    BorshBrotli,
+    BorshBrotliReceipts,
+    CompactBrotli,
+    CompactBrotliReceipts,
+    /// canonical block RLP, see `Block::to_rlp`
+    RlpBrotli,
+}
+
+impl ArchiveEncoding {
+    /// Encoding new archives are written with, from the `archive_encoding`
+    /// env var (`borsh`, `compact` or `rlp`, defaults to `borsh`)
+    pub fn from_env(archive_receipts: bool) -> Result<ArchiveEncoding, Error> {
+        let config = get_env_var("archive_encoding").unwrap_or_else(|_| "borsh".to_string());
+
+        let encoding = match (config.trim(), archive_receipts) {
+            ("borsh", false) => ArchiveEncoding::BorshBrotli,
+            ("borsh", true) => ArchiveEncoding::BorshBrotliReceipts,
+            ("compact", false) => ArchiveEncoding::CompactBrotli,
+            ("compact", true) => ArchiveEncoding::CompactBrotliReceipts,
+            ("rlp", false) => ArchiveEncoding::RlpBrotli,
+            // a chain file only holds blocks
+            ("rlp", true) => {
+                return Err(Error::msg(
+                    "archive_receipts is not supported by the rlp encoding",
+                ))
+            }
+            (other, _) => {
+                return Err(Error::msg(format!(
+                    "invalid archive_encoding value: {}",
+                    other
+                )))
+            }
+        };
+
+        Ok(encoding)
+    }
+
+    /// archives without the tag predate it and are Borsh-Brotli
+    pub fn from_tag(tag: Option<&str>) -> Result<ArchiveEncoding, DecodeError> {
+        match tag {
+            None | Some("Borsh-Brotli") => Ok(ArchiveEncoding::BorshBrotli),
+            Some("Borsh-Brotli-Receipts") => Ok(ArchiveEncoding::BorshBrotliReceipts),
+            Some("Compact-Brotli") => Ok(ArchiveEncoding::CompactBrotli),
+            Some("Compact-Brotli-Receipts") => Ok(ArchiveEncoding::CompactBrotliReceipts),
+            Some("RLP-Brotli") => Ok(ArchiveEncoding::RlpBrotli),
+            Some(other) => Err(DecodeError::UnsupportedEncoding(other.to_string())),
+        }
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ArchiveEncoding::BorshBrotli => "Borsh-Brotli",
+            ArchiveEncoding::BorshBrotliReceipts => "Borsh-Brotli-Receipts",
+            ArchiveEncoding::CompactBrotli => "Compact-Brotli",
+            ArchiveEncoding::CompactBrotliReceipts => "Compact-Brotli-Receipts",
+            ArchiveEncoding::RlpBrotli => "RLP-Brotli",
+        }
+    }
+
+    pub fn includes_receipts(&self) -> bool {
+        matches!(
+            self,
+            ArchiveEncoding::BorshBrotliReceipts | ArchiveEncoding::CompactBrotliReceipts
+        )
+    }
+
+    /// whether decoding gives back the JSON-RPC block field for field. RLP
+    /// only keeps the consensus fields, compare `to_rlp` encodings instead
+    pub fn is_lossless(&self) -> bool {
+        !matches!(self, ArchiveEncoding::RlpBrotli)
+    }
+
+    /// Encodes the block, and its receipts for the `*-Receipts` encodings,
+    /// checking the payload decodes back to exactly what was encoded
+    pub fn encode(
+        &self,
+        block: Block,
+        receipts: Vec<Receipt>,
+        compressor: &dyn Compressor,
+    ) -> Result<Vec<u8>, Error> {
+        let payload = match self {
+            ArchiveEncoding::BorshBrotli => block.to_verified_payload(compressor)?,
+            ArchiveEncoding::BorshBrotliReceipts => {
+                BlockPayload { block, receipts }.to_verified_payload(compressor)?
+            }
+            ArchiveEncoding::CompactBrotli => {
+                CompactBlock::try_from(&block)?.to_verified_payload(compressor)?
+            }
+            ArchiveEncoding::CompactBrotliReceipts => CompactBlockPayload {
+                block: CompactBlock::try_from(&block)?,
+                receipts,
+            }
+            .to_verified_payload(compressor)?,
+            ArchiveEncoding::RlpBrotli => rlp_payload(&block, compressor)?,
+        };
+
+        Ok(payload)
+    }
+
+    /// uncompressed body `encode` puts in the envelope
+    pub fn body(&self, block: Block, receipts: Vec<Receipt>) -> Result<Vec<u8>, Error> {
+        let body = match self {
+            ArchiveEncoding::BorshBrotli => to_vec(&block)?,
+            ArchiveEncoding::BorshBrotliReceipts => to_vec(&BlockPayload { block, receipts })?,
+            ArchiveEncoding::CompactBrotli => to_vec(&CompactBlock::try_from(&block)?)?,
+            ArchiveEncoding::CompactBrotliReceipts => to_vec(&CompactBlockPayload {
+                block: CompactBlock::try_from(&block)?,
+                receipts,
+            })?,
+            ArchiveEncoding::RlpBrotli => block.to_rlp()?,
+        };
+
+        Ok(body)
+    }
+
+    /// decodes the block of a payload in this encoding, receipts are dropped
+    pub fn decode_block(&self, payload: &[u8]) -> Result<Block, DecodeError> {
+        match self {
+            ArchiveEncoding::BorshBrotli => Block::from_payload(payload),
+            ArchiveEncoding::BorshBrotliReceipts => Ok(BlockPayload::from_payload(payload)?.block),
+            ArchiveEncoding::CompactBrotli => {
+                Ok(Block::from(&CompactBlock::from_payload(payload)?))
+            }
+            ArchiveEncoding::CompactBrotliReceipts => Ok(Block::from(
+                &CompactBlockPayload::from_payload(payload)?.block,
+            )),
+            ArchiveEncoding::RlpBrotli => match archive_envelope::open(payload)? {
+                (1, body) => Block::from_rlp(&body),
+                (version, _) => Err(DecodeError::UnsupportedVersion(version)),
+            },
+        }
+    }
+}
+
+/// enveloped, compressed block RLP. The RLP has to decode back to the
+/// same block hash and senders and re-encode to the same bytes
+fn rlp_payload(block: &Block, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+    let rlp = block.to_rlp()?;
+    let decoded = Block::from_rlp(&rlp)?;
+
+    let senders = |block: &Block| -> Vec<Option<String>> {
+        block
+            .transactions
+            .iter()
+            .flatten()
+            .map(|transaction| transaction.from.clone())
+            .collect()
+    };
+    if decoded.hash != block.hash || senders(&decoded) != senders(block) {
+        return Err(Error::msg(format!(
+            "block {:?} RLP does not decode to the same block hash and senders",
+            block.number
+        )));
+    }
+    if decoded.to_rlp()? != rlp {
+        return Err(DecodeError::RoundtripMismatch.into());
+    }
+
+    let payload = archive_envelope::seal(CURRENT_SCHEMA_VERSION, compressor, &rlp)?;
+    if archive_envelope::open(&payload)? != (CURRENT_SCHEMA_VERSION, rlp) {
+        return Err(DecodeError::RoundtripMismatch.into());
+    }
+
+    Ok(payload)
+}
@@ -0,0 +1,134 @@
+use crate::utils::block::{Block, DecodeError};
+use crate::utils::compression::{zstd_decompress, Compressor};
+use anyhow::Error;
+
+/// prefix of enveloped archive payloads. Payloads archived before the
+/// envelope start with a brotli window byte (low nibble `1011` for the
+/// 22 bit window they use), so they can't be mistaken for it
+pub const PAYLOAD_MAGIC: [u8; 4] = *b"WVMA";
+
+/// schema version of the Borsh layout written today, bumped on any change to
+/// the archived models. Version 0 is the un-enveloped baseline layout.
+/// Decoders match the versions they read explicitly, so a bump keeps the
+/// older payloads readable once their layout gets its own arm
+pub const CURRENT_SCHEMA_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = PAYLOAD_MAGIC.len() + 2;
+
+/// Compression applied to the payload body, stored as a single byte id
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    Brotli,
+    Zstd,
+    /// zstd with a trained dictionary, identified in the zstd frame header
+    ZstdDictionary,
+}
+
+impl Codec {
+    pub fn id(&self) -> u8 {
+        match self {
+            Codec::Brotli => 1,
+            Codec::Zstd => 2,
+            Codec::ZstdDictionary => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Codec, DecodeError> {
+        match id {
+            1 => Ok(Codec::Brotli),
+            2 => Ok(Codec::Zstd),
+            3 => Ok(Codec::ZstdDictionary),
+            other => Err(DecodeError::UnsupportedCodec(other)),
+        }
+    }
+
+    pub fn decompress(&self, input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Codec::Brotli => Block::brotli_decompress(input),
+            Codec::Zstd | Codec::ZstdDictionary => zstd_decompress(input),
+        }
+    }
+}
+
+/// `magic | schema version | codec id | compressed body`
+pub fn seal(
+    schema_version: u8,
+    compressor: &dyn Compressor,
+    body: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let compressed = compressor.compress(body)?;
+    let mut payload = Vec::with_capacity(HEADER_LEN + compressed.len());
+    payload.extend_from_slice(&PAYLOAD_MAGIC);
+    payload.push(schema_version);
+    payload.push(compressor.codec().id());
+    payload.extend_from_slice(&compressed);
+    Ok(payload)
+}
+
+/// schema version of a payload, read from its envelope header
+pub fn schema_version(payload: &[u8]) -> u8 {
+    match payload.get(PAYLOAD_MAGIC.len()) {
+        Some(version) if payload.starts_with(&PAYLOAD_MAGIC) => *version,
+        _ => 0,
+    }
+}
+
+/// Schema version and decompressed body of an archive payload.
+/// Payloads without the envelope are schema version 0, brotli compressed
+pub fn open(payload: &[u8]) -> Result<(u8, Vec<u8>), DecodeError> {
+    if !payload.starts_with(&PAYLOAD_MAGIC) {
+        return Ok((0, Codec::Brotli.decompress(payload)?));
+    }
+    if payload.len() < HEADER_LEN {
+        return Err(DecodeError::Truncated);
+    }
+
+    let schema_version = payload[PAYLOAD_MAGIC.len()];
+    let codec = Codec::from_id(payload[PAYLOAD_MAGIC.len() + 1])?;
+
+    Ok((schema_version, codec.decompress(&payload[HEADER_LEN..])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::{BrotliCompressor, ZstdCompressor};
+    use crate::utils::test_fixtures::fixture_bytes;
+
+    #[test]
+    fn seal_open_roundtrip() {
+        let body = b"block body".repeat(100);
+        for compressor in [
+            Box::new(BrotliCompressor::default()) as Box<dyn Compressor>,
+            Box::new(ZstdCompressor::new(3).unwrap()),
+        ] {
+            let payload = seal(CURRENT_SCHEMA_VERSION, compressor.as_ref(), &body).unwrap();
+            assert!(payload.starts_with(&PAYLOAD_MAGIC));
+            assert_eq!(payload[HEADER_LEN - 1], compressor.codec().id());
+            assert_eq!(schema_version(&payload), CURRENT_SCHEMA_VERSION);
+            assert_eq!(
+                open(&payload).unwrap(),
+                (CURRENT_SCHEMA_VERSION, body.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn unenveloped_payload_is_version_0() {
+        let payload = fixture_bytes("blocks/eip1559.v0.bin");
+
+        assert_eq!(schema_version(&payload), 0);
+        let (version, body) = open(&payload).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(body, Block::brotli_decompress(&payload).unwrap());
+    }
+
+    #[test]
+    fn truncated_or_unknown_header() {
+        assert_eq!(open(&PAYLOAD_MAGIC), Err(DecodeError::Truncated));
+
+        let mut payload = PAYLOAD_MAGIC.to_vec();
+        payload.extend([CURRENT_SCHEMA_VERSION, 0xff]);
+        assert_eq!(open(&payload), Err(DecodeError::UnsupportedCodec(0xff)));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::utils::block::{borsh_from_slice, DecodeError};
+use crate::utils::block_compact::fixed_bytes;
+use crate::utils::block_ranges::BlockRange;
+use crate::utils::block_rlp::to_hex;
+use anyhow::Error;
+use borsh::to_vec;
+use borsh_derive::{BorshDeserialize, BorshSerialize};
+
+/// prefix of range archives, distinct from the single block envelope magic
+pub const RANGE_MAGIC: [u8; 4] = *b"WVMR";
+
+/// version of the range archive layout
+pub const RANGE_FORMAT_VERSION: u8 = 1;
+
+// magic, format version, then the Borsh `RangeIndex` header fields
+const RANGE_PREFIX_LEN: usize = RANGE_MAGIC.len() + 1;
+const INDEX_FIXED_LEN: usize = 4 + 4;
+const ENTRY_LEN: usize = 32 + 4 + 8;
+
+/// Index at the start of a range archive, one entry per block from
+/// `first_block` on. Its size only depends on the block count, so a reader
+/// can fetch it, and then a single block, with two HTTP range requests.
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct RangeIndex {
+    pub entries: Vec<RangeEntry>,
+    pub first_block: u32,
+}
+
+/// Location of one block payload inside the range archive
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct RangeEntry {
+    pub block_hash: [u8; 32],
+    pub length: u32,
+    /// from the start of the range archive
+    pub offset: u64,
+}
+
+impl RangeIndex {
+    pub fn block_range(&self) -> BlockRange {
+        [
+            self.first_block,
+            self.first_block + self.entries.len() as u32 - 1,
+        ]
+    }
+
+    pub fn entry(&self, block_number: u32) -> Option<&RangeEntry> {
+        let position = block_number.checked_sub(self.first_block)?;
+        self.entries.get(position as usize)
+    }
+}
+
+impl RangeEntry {
+    pub fn block_hash(&self) -> String {
+        to_hex(&self.block_hash)
+    }
+
+    /// byte range of the block payload, end exclusive
+    pub fn bytes(&self) -> std::ops::Range<usize> {
+        self.offset as usize..self.offset as usize + self.length as usize
+    }
+}
+
+/// bytes taken by the magic, version and index of a range archive
+pub fn index_len(block_count: usize) -> usize {
+    RANGE_PREFIX_LEN + INDEX_FIXED_LEN + block_count * ENTRY_LEN
+}
+
+/// Packs the payloads of consecutive blocks, each with its block hash, into
+/// a range archive: `magic | format version | index | block payloads`.
+/// Every block payload is an ordinary enveloped archive payload.
+pub fn seal_range(first_block: u32, blocks: &[(String, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    if blocks.is_empty() {
+        return Err(Error::msg("range archive without blocks"));
+    }
+
+    let mut offset = index_len(blocks.len()) as u64;
+    let mut entries: Vec<RangeEntry> = Vec::with_capacity(blocks.len());
+    for (block_hash, payload) in blocks {
+        let length = u32::try_from(payload.len())
+            .map_err(|_| Error::msg("block payload too large for a range archive"))?;
+        entries.push(RangeEntry {
+            block_hash: fixed_bytes(block_hash)?,
+            length,
+            offset,
+        });
+        offset += length as u64;
+    }
+
+    let index = RangeIndex {
+        entries,
+        first_block,
+    };
+    let mut archive = Vec::with_capacity(offset as usize);
+    archive.extend_from_slice(&RANGE_MAGIC);
+    archive.push(RANGE_FORMAT_VERSION);
+    archive.extend_from_slice(&to_vec(&index)?);
+    for (_, payload) in blocks {
+        archive.extend_from_slice(payload);
+    }
+
+    // every block has to be readable back through the index
+    if open_index(&archive)? != index
+        || index
+            .entries
+            .iter()
+            .zip(blocks)
+            .any(|(entry, (_, payload))| archive.get(entry.bytes()) != Some(payload.as_slice()))
+    {
+        return Err(DecodeError::RoundtripMismatch.into());
+    }
+
+    Ok(archive)
+}
+
+/// Reads the index of a range archive. `prefix` only needs to hold the
+/// first `index_len` bytes of the archive.
+pub fn open_index(prefix: &[u8]) -> Result<RangeIndex, DecodeError> {
+    if prefix.len() < RANGE_PREFIX_LEN + INDEX_FIXED_LEN {
+        return Err(DecodeError::Truncated);
+    }
+    if !prefix.starts_with(&RANGE_MAGIC) {
+        return Err(DecodeError::InvalidBorsh(
+            "range archive without the range magic".to_string(),
+        ));
+    }
+    if prefix[RANGE_MAGIC.len()] != RANGE_FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(prefix[RANGE_MAGIC.len()]));
+    }
+
+    // Borsh writes the entry count right before the entries
+    let count_bytes: [u8; 4] = prefix[RANGE_PREFIX_LEN..RANGE_PREFIX_LEN + 4]
+        .try_into()
+        .unwrap();
+    let block_count = u32::from_le_bytes(count_bytes) as usize;
+    if block_count == 0 {
+        return Err(DecodeError::InvalidBorsh(
+            "range archive without blocks".to_string(),
+        ));
+    }
+    let len = block_count
+        .checked_mul(ENTRY_LEN)
+        .map(|entries_len| RANGE_PREFIX_LEN + INDEX_FIXED_LEN + entries_len)
+        .ok_or(DecodeError::Truncated)?;
+    if prefix.len() < len {
+        return Err(DecodeError::Truncated);
+    }
+
+    borsh_from_slice(&prefix[RANGE_PREFIX_LEN..len])
+}
+
+/// Payload of a single block of a complete range archive
+pub fn block_payload(archive: &[u8], block_number: u32) -> Result<&[u8], DecodeError> {
+    let index = open_index(archive)?;
+    let entry = index.entry(block_number).ok_or_else(|| {
+        DecodeError::InvalidBorsh(format!(
+            "block {} is outside range archive {:?}",
+            block_number,
+            index.block_range()
+        ))
+    })?;
+
+    archive.get(entry.bytes()).ok_or(DecodeError::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::archive_encoding::ArchiveEncoding;
+    use crate::utils::compression::BrotliCompressor;
+    use crate::utils::test_fixtures::{rpc_block, RPC_BLOCKS};
+
+    /// the fixture blocks, packed as the blocks from `first_block` on
+    fn sealed_fixtures(first_block: u32) -> Vec<u8> {
+        let compressor = BrotliCompressor::new(11, 22).unwrap();
+        let payloads: Vec<(String, Vec<u8>)> = RPC_BLOCKS
+            .iter()
+            .map(|name| {
+                let block = rpc_block(name);
+                let block_hash = block.hash.clone().unwrap();
+                let payload = ArchiveEncoding::BorshBrotli
+                    .encode(block, Vec::new(), &compressor)
+                    .unwrap();
+                (block_hash, payload)
+            })
+            .collect();
+        seal_range(first_block, &payloads).unwrap()
+    }
+
+    #[test]
+    fn blocks_are_read_back_through_the_index() {
+        let first_block = 7000;
+        let archive = sealed_fixtures(first_block);
+
+        // the index alone is enough to locate every block
+        let index = open_index(&archive[..index_len(RPC_BLOCKS.len())]).unwrap();
+        assert_eq!(
+            index.block_range(),
+            [first_block, first_block + RPC_BLOCKS.len() as u32 - 1]
+        );
+
+        for (position, name) in RPC_BLOCKS.iter().enumerate() {
+            let block = rpc_block(name);
+            let block_number = first_block + position as u32;
+            let entry = index.entry(block_number).unwrap();
+            assert_eq!(Some(entry.block_hash()), block.hash);
+
+            let payload = block_payload(&archive, block_number).unwrap();
+            assert_eq!(payload, &archive[entry.bytes()]);
+            assert_eq!(
+                ArchiveEncoding::BorshBrotli.decode_block(payload).unwrap(),
+                block
+            );
+        }
+
+        assert!(block_payload(&archive, first_block - 1).is_err());
+        assert!(block_payload(&archive, first_block + RPC_BLOCKS.len() as u32).is_err());
+    }
+
+    #[test]
+    fn truncated_or_foreign_archives_are_refused() {
+        let archive = sealed_fixtures(1);
+        let index_len = index_len(RPC_BLOCKS.len());
+
+        assert!(matches!(
+            open_index(&archive[..index_len - 1]),
+            Err(DecodeError::Truncated)
+        ));
+        // the last block payload is cut short
+        assert!(matches!(
+            block_payload(&archive[..archive.len() - 1], RPC_BLOCKS.len() as u32),
+            Err(DecodeError::Truncated)
+        ));
+
+        let mut other_version = archive.clone();
+        other_version[RANGE_MAGIC.len()] = RANGE_FORMAT_VERSION + 1;
+        assert!(matches!(
+            open_index(&other_version),
+            Err(DecodeError::UnsupportedVersion(_))
+        ));
+        assert!(open_index(&archive[1..]).is_err());
+        assert!(seal_range(1, &[]).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use crate::utils::archive_encoding::ArchiveEncoding;
+use crate::utils::archive_envelope;
+use crate::utils::archive_range::{index_len, open_index, RangeIndex};
+use crate::utils::arweave_gql::ArchiveRecord;
+use crate::utils::block::Block;
+use crate::utils::block_integrity::{verify_header_hash, verify_transactions_root};
+use crate::utils::block_ranges::{BlockRange, BlockRanges};
+use crate::utils::constants::ARWEAVE_GQL_GATEWAY;
+use crate::utils::env_var::get_env_var;
+use crate::utils::wvm_client::retrieve_block_with_txs;
+use anyhow::Error;
+use rand::seq::SliceRandom;
+use reqwest::header::RANGE;
+use reqwest::Client;
+use std::time::Duration;
+
+const DATA_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Which archives get their content downloaded and checked against the RPC.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyMode {
+    Off,
+    /// `n` random archived blocks above the checkpoint
+    Sample(usize),
+    /// every archived block inside the range
+    Range(BlockRange),
+    /// every archived block
+    Full,
+}
+
+impl VerifyMode {
+    /// Parses the `verify_archives` env var: `off`, `sample:<n>`,
+    /// `range:<start>-<end>` or `full`. Defaults to `off`
+    pub fn from_env() -> Result<VerifyMode, Error> {
+        let config = get_env_var("verify_archives").unwrap_or_else(|_| "off".to_string());
+        let invalid = || Error::msg(format!("invalid verify_archives value: {}", config));
+
+        let mode = match config.trim().split_once(':') {
+            None if config.trim() == "off" => VerifyMode::Off,
+            None if config.trim() == "full" => VerifyMode::Full,
+            Some(("sample", count)) => VerifyMode::Sample(count.parse().map_err(|_| invalid())?),
+            Some(("range", range)) => {
+                let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+                let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+                let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+                VerifyMode::Range([start, end])
+            }
+            _ => return Err(invalid()),
+        };
+
+        Ok(mode)
+    }
+
+    /// whether archives under `next_block` (the scan checkpoint) are needed,
+    /// in which case the scan has to start from the beginning. `Full` is also
+    /// how archives orphaned by a reorg deeper than `reorg_window` are found
+    pub fn needs_full_scan(&self, next_block: u32) -> bool {
+        match self {
+            VerifyMode::Full => next_block > 0,
+            VerifyMode::Range(range) => range[0] < next_block,
+            _ => false,
+        }
+    }
+}
+
+/// Downloads the data items of the archives selected by `mode`, decodes them
+/// and compares them with the block served by the WeaveVM RPC. Blocks without
+/// a single archive that verifies are returned as gaps to backfill.
+///
+/// `archives` must be sorted by block number.
+pub async fn verify_archives(
+    archives: &[ArchiveRecord],
+    mode: &VerifyMode,
+) -> Result<BlockRanges, Error> {
+    let gateway = gateway_from_env();
+    let gateway = gateway.as_str();
+
+    let mut blocks: Vec<&[ArchiveRecord]> = archives
+        .chunk_by(|a, b| a.block_number == b.block_number)
+        .filter(|block_archives| match mode {
+            VerifyMode::Off => false,
+            VerifyMode::Range(range) => {
+                (range[0]..=range[1]).contains(&block_archives[0].block_number)
+            }
+            VerifyMode::Sample(_) | VerifyMode::Full => true,
+        })
+        .collect();
+
+    if let VerifyMode::Sample(count) = mode {
+        blocks = blocks
+            .choose_multiple(&mut rand::thread_rng(), *count)
+            .copied()
+            .collect();
+        blocks.sort_unstable_by_key(|block_archives| block_archives[0].block_number);
+    }
+
+    println!("deep verifying {} archived blocks", blocks.len());
+
+    let client = Client::new();
+    let mut failed = BlockRanges::new();
+
+    for block_archives in blocks {
+        let block_number = block_archives[0].block_number;
+        let expected = match retrieve_block_with_txs(block_number).await {
+            Ok(block) => block,
+            Err(e) => {
+                // the RPC is the reference, without it nothing can be judged
+                return Err(Error::msg(format!(
+                    "failed to fetch block {} for verification: {}",
+                    block_number, e
+                )));
+            }
+        };
+
+        let mut verified = false;
+        for archive in block_archives {
+            match verify_archive(&client, gateway, archive, &expected).await {
+                Ok(()) => {
+                    verified = true;
+                    break;
+                }
+                Err(e) => eprintln!(
+                    "archive {} of block {} failed verification: {}",
+                    archive.arweave_id, block_number, e
+                ),
+            }
+        }
+
+        if !verified {
+            failed.insert(block_number);
+        }
+    }
+
+    println!(
+        "deep verification found {} corrupt or unretrievable blocks",
+        failed.block_count()
+    );
+
+    Ok(failed)
+}
+
+async fn verify_archive(
+    client: &Client,
+    gateway: &str,
+    archive: &ArchiveRecord,
+    expected: &Block,
+) -> Result<(), Error> {
+    let (payload, recorded_hash) = match archive.block_range {
+        Some(range) => fetch_range_block(client, gateway, archive, range).await?,
+        None => (
+            fetch_bytes(client, gateway, &archive.arweave_id, None).await?,
+            archive.block_hash.clone(),
+        ),
+    };
+
+    check_archive_payload(archive, &payload, recorded_hash.as_deref(), expected)
+}
+
+/// Decodes an archive payload and compares it with the RPC block, as far as
+/// the payload's schema version allows
+fn check_archive_payload(
+    archive: &ArchiveRecord,
+    payload: &[u8],
+    recorded_hash: Option<&str>,
+    expected: &Block,
+) -> Result<(), Error> {
+    let schema_version = archive_envelope::schema_version(payload);
+    if archive.encoding_version.unwrap_or(0) != schema_version {
+        return Err(Error::msg(format!(
+            "payload schema version {} does not match WeaveVM:Encoding-Version tag {:?}",
+            schema_version, archive.encoding_version
+        )));
+    }
+
+    let encoding = ArchiveEncoding::from_tag(archive.encoding.as_deref())?;
+    let block = encoding.decode_block(payload)?;
+
+    if block.number.as_deref() != Some(format!("0x{:x}", archive.block_number).as_str()) {
+        return Err(Error::msg(format!(
+            "decoded block number {:?} does not match Block-Number tag",
+            block.number
+        )));
+    }
+    if let Some(recorded_hash) = recorded_hash {
+        if block.hash.as_deref() != Some(recorded_hash) {
+            return Err(Error::msg(format!(
+                "decoded block hash {:?} does not match the archived hash {}",
+                block.hash, recorded_hash
+            )));
+        }
+    }
+    let matches = match schema_version {
+        // version 0 dropped transactionsRoot and the typed transaction
+        // fields, the RPC block's header and transactionsRoot commit to the
+        // hashes it kept
+        0 => {
+            verify_header_hash(expected)?;
+            verify_transactions_root(expected)?;
+            block == expected.legacy_view()
+        }
+        _ if encoding.is_lossless() => &block == expected,
+        _ => block.to_rlp()? == expected.to_rlp()?,
+    };
+    if !matches {
+        return Err(Error::msg("decoded block differs from the RPC block"));
+    }
+
+    Ok(())
+}
+
+/// `verify_gateway` env var, the gateway archived data items are downloaded
+/// from
+pub fn gateway_from_env() -> String {
+    let gateway = get_env_var("verify_gateway").unwrap_or_else(|_| ARWEAVE_GQL_GATEWAY.into());
+    gateway.trim_end_matches('/').to_string()
+}
+
+/// Index of a range archive, fetched without the block payloads
+pub async fn fetch_range_index(
+    client: &Client,
+    gateway: &str,
+    arweave_id: &str,
+    range: BlockRange,
+) -> Result<RangeIndex, Error> {
+    let index_bytes = 0..index_len((range[1] - range[0]) as usize + 1);
+    let index = open_index(&fetch_bytes(client, gateway, arweave_id, Some(index_bytes)).await?)?;
+    if index.block_range() != range {
+        return Err(Error::msg(format!(
+            "range index {:?} does not match the Block-Range tags {:?}",
+            index.block_range(),
+            range
+        )));
+    }
+
+    Ok(index)
+}
+
+/// Payload and hash of a single block of a range archive, fetched through
+/// the range index without downloading the other blocks
+async fn fetch_range_block(
+    client: &Client,
+    gateway: &str,
+    archive: &ArchiveRecord,
+    range: BlockRange,
+) -> Result<(Vec<u8>, Option<String>), Error> {
+    let index = fetch_range_index(client, gateway, &archive.arweave_id, range).await?;
+
+    let entry = index
+        .entry(archive.block_number)
+        .ok_or_else(|| Error::msg("block missing from the range index"))?;
+    let payload = fetch_bytes(client, gateway, &archive.arweave_id, Some(entry.bytes())).await?;
+
+    Ok((payload, Some(entry.block_hash())))
+}
+
+/// data of an archive, or only the given bytes of it
+async fn fetch_bytes(
+    client: &Client,
+    gateway: &str,
+    arweave_id: &str,
+    bytes: Option<std::ops::Range<usize>>,
+) -> Result<Vec<u8>, Error> {
+    let mut request = client
+        .get(format!("{}/{}", gateway, arweave_id))
+        .timeout(DATA_REQUEST_TIMEOUT);
+    if let Some(bytes) = &bytes {
+        request = request.header(RANGE, format!("bytes={}-{}", bytes.start, bytes.end - 1));
+    }
+    let data = request.send().await?.error_for_status()?.bytes().await?;
+
+    // a gateway ignoring the range header answers with the whole data item
+    match bytes {
+        Some(bytes) if data.len() != bytes.len() => match data.get(bytes) {
+            Some(slice) => Ok(slice.to_vec()),
+            None => Err(Error::msg("range archive shorter than its index")),
+        },
+        _ => Ok(data.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::archive_envelope::CURRENT_SCHEMA_VERSION;
+    use crate::utils::compression::BrotliCompressor;
+    use crate::utils::test_fixtures::{fixture_bytes, rpc_block};
+
+    fn archive(block_number: u32, encoding_version: Option<u8>) -> ArchiveRecord {
+        ArchiveRecord {
+            arweave_id: "fixture".to_string(),
+            block_number,
+            block_hash: None,
+            block_range: None,
+            encoding: None,
+            encoding_version,
+            resume_cursor: None,
+        }
+    }
+
+    #[test]
+    fn version_0_archive_verifies() {
+        let expected = rpc_block("eip1559");
+        let payload = fixture_bytes("blocks/eip1559.v0.bin");
+
+        // version 0 can't hold the dynamic fee fields
+        assert_ne!(Block::from_payload(&payload).unwrap(), expected);
+        check_archive_payload(
+            &archive(2400, None),
+            &payload,
+            expected.hash.as_deref(),
+            &expected,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn version_0_archive_of_another_block_fails() {
+        let mut expected = rpc_block("eip1559");
+        let payload = fixture_bytes("blocks/eip1559.v0.bin");
+        expected.transactions.as_mut().unwrap().pop();
+
+        assert!(check_archive_payload(&archive(2400, None), &payload, None, &expected).is_err());
+    }
+
+    #[test]
+    fn current_archive_must_match_every_field() {
+        let expected = rpc_block("eip1559");
+        let payload = expected.to_payload(&BrotliCompressor::default()).unwrap();
+        let archive = archive(2400, Some(CURRENT_SCHEMA_VERSION));
+        check_archive_payload(&archive, &payload, None, &expected).unwrap();
+
+        let mut tampered = rpc_block("eip1559");
+        tampered.total_difficulty = Some("0x1".to_string());
+        assert!(check_archive_payload(&archive, &payload, None, &tampered).is_err());
+    }
+}
@@ -1,324 +1,959 @@
-use crate::utils::constants::{ARWEAVE_GQL_GATEWAY, IRYS_GQL_GATEWAY};
-use crate::utils::wvm_client::get_latest_block_number;
+use crate::utils::archive_verifier::{
+    fetch_range_index, gateway_from_env, verify_archives, VerifyMode,
+};
+use crate::utils::block::PlanetScaleCheckpoint;
+use crate::utils::block_ranges::{BlockRange, BlockRanges};
+use crate::utils::env_var::get_env_var;
+use crate::utils::gql_sources::GqlSource;
+use crate::utils::wvm_client::{get_block_hashes, get_latest_block_number};
 use anyhow::Error;
 use common::address_book::ADDRESS_BOOK;
-use reqwest::Client;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
 use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+
+const GQL_MAX_ATTEMPTS: u32 = 5;
+const GQL_RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+const GQL_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+const GQL_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_REORG_WINDOW: u32 = 256;
+
+/// Why a GQL page could not be fetched. Any of these aborts the scan: a
+/// missing page must never be mistaken for "no archives on that page".
+#[derive(Debug)]
+pub enum GqlError {
+    Request(reqwest::Error),
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    Status {
+        status: StatusCode,
+        body: String,
+    },
+    GraphQl(Value),
+    InvalidResponse(String),
+    RetriesExhausted {
+        attempts: u32,
+        last_error: Box<GqlError>,
+    },
+}
+
+impl fmt::Display for GqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GqlError::Request(e) => write!(f, "gql request failed: {}", e),
+            GqlError::RateLimited { retry_after } => {
+                write!(
+                    f,
+                    "gql gateway rate limited (retry after: {:?})",
+                    retry_after
+                )
+            }
+            GqlError::Status { status, body } => {
+                write!(f, "gql gateway responded {}: {}", status, body)
+            }
+            GqlError::GraphQl(errors) => write!(f, "gql query errors: {}", errors),
+            GqlError::InvalidResponse(reason) => write!(f, "invalid gql response: {}", reason),
+            GqlError::RetriesExhausted {
+                attempts,
+                last_error,
+            } => write!(
+                f,
+                "gql page failed after {} attempts: {}",
+                attempts, last_error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GqlError {}
+
+impl GqlError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            GqlError::Request(_) | GqlError::RateLimited { .. } => true,
+            GqlError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            GqlError::InvalidResponse(_) => true,
+            _ => false,
+        }
+    }
+}
 
-async fn send_graphql(gateway: &str, query: Value) -> Result<Value, Error> {
+async fn send_graphql(gateway: &str, query: &Value) -> Result<Value, GqlError> {
     let client = Client::new();
     let res = client
         .post(format!("{}/graphql", gateway))
         .header("Content-Type", "application/json")
-        .json(&query)
+        .timeout(GQL_REQUEST_TIMEOUT)
+        .json(query)
         .send()
-        .await?;
+        .await
+        .map_err(GqlError::Request)?;
+
+    let status = res.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(retry_after_delay);
+        return Err(GqlError::RateLimited { retry_after });
+    }
+    if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        return Err(GqlError::Status { status, body });
+    }
+
+    let json_res: Value = res
+        .json()
+        .await
+        .map_err(|e| GqlError::InvalidResponse(e.to_string()))?;
+
+    // a response carrying errors is a failed query even when it has data,
+    // the data of a partially failed query may miss edges
+    match json_res.get("errors") {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(errors)) if errors.is_empty() => {}
+        Some(errors) => return Err(GqlError::GraphQl(errors.clone())),
+    }
 
-    let json_res: Value = res.json().await?;
     Ok(json_res)
 }
 
-async fn retrieve_all_transactions(scan_count: u32, address: &str) -> Result<Vec<u32>, Error> {
-    let mut block_numbers: Vec<u32> = Vec::new();
-    let mut cursor: Option<String> = None;
+/// `Retry-After` header value, either delay seconds or an HTTP date
+fn retry_after_delay(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    // a date in the past means retrying right away
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// `send_graphql` with bounded retries, exponential backoff and `Retry-After`
+/// handling for rate limited gateways
+async fn send_graphql_with_retry(gateway: &str, query: &Value) -> Result<Value, GqlError> {
+    let mut attempt: u32 = 0;
+    let mut backoff = GQL_RETRY_BASE_DELAY;
+
+    loop {
+        attempt += 1;
+        let error = match send_graphql(gateway, query).await {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+
+        if !error.is_retryable() {
+            return Err(error);
+        }
+        if attempt >= GQL_MAX_ATTEMPTS {
+            return Err(GqlError::RetriesExhausted {
+                attempts: attempt,
+                last_error: Box::new(error),
+            });
+        }
+
+        let delay = match &error {
+            GqlError::RateLimited {
+                retry_after: Some(retry_after),
+            } => *retry_after,
+            _ => backoff,
+        };
+        eprintln!(
+            "gql attempt {}/{} failed: {}. Retrying in {:?}",
+            attempt, GQL_MAX_ATTEMPTS, error, delay
+        );
+        sleep(delay).await;
+        backoff = (backoff * 2).min(GQL_RETRY_MAX_DELAY);
+    }
+}
+
+/// A `WeaveVM-ExEx` data item as indexed by a GQL source.
+#[derive(Clone, Debug)]
+pub struct ArchiveRecord {
+    pub arweave_id: String,
+    pub block_number: u32,
+    /// `Block-Hash` tag, missing on archives that predate it
+    pub block_hash: Option<String>,
+    /// `Block-Range-Start`/`Block-Range-End` tags of range archives, which
+    /// pack several blocks in one data item
+    pub block_range: Option<BlockRange>,
+    /// `WeaveVM:Encoding` tag
+    pub encoding: Option<String>,
+    /// `WeaveVM:Encoding-Version` tag, the payload envelope schema version
+    pub encoding_version: Option<u8>,
+    /// cursor of the edge before this archive, a scan resuming from it sees
+    /// the archive again. None for the first edge of a scan from the start
+    pub resume_cursor: Option<String>,
+}
+
+impl ArchiveRecord {
+    fn from_node(node: &Value) -> Option<ArchiveRecord> {
+        let arweave_id = node.get("id")?.as_str()?.to_string();
+        let tags = node.get("tags")?.as_array()?;
+        let tag_value = |name: &str| {
+            tags.iter().find_map(|tag| {
+                if tag.get("name")?.as_str()? == name {
+                    tag.get("value")?.as_str()
+                } else {
+                    None
+                }
+            })
+        };
+
+        let block_range = match (tag_value("Block-Range-Start"), tag_value("Block-Range-End")) {
+            (Some(start), Some(end)) => {
+                let range: BlockRange = [start.parse().ok()?, end.parse().ok()?];
+                if range[0] > range[1] {
+                    return None;
+                }
+                Some(range)
+            }
+            _ => None,
+        };
+        let block_number = match block_range {
+            Some(range) => range[0],
+            None => tag_value("Block-Number")?.parse::<u32>().ok()?,
+        };
+        // a range archive is tagged with the hash of its last block, which
+        // commits to the others through the parent hashes
+        let block_hash = match block_range {
+            Some(_) => tag_value("Block-Range-End-Hash"),
+            None => tag_value("Block-Hash"),
+        }
+        .map(String::from);
+        let encoding = tag_value("WeaveVM:Encoding").map(String::from);
+        let encoding_version =
+            tag_value("WeaveVM:Encoding-Version").and_then(|version| version.parse::<u8>().ok());
+
+        Some(ArchiveRecord {
+            arweave_id,
+            block_number,
+            block_hash,
+            block_range,
+            encoding,
+            encoding_version,
+            resume_cursor: None,
+        })
+    }
+
+    /// one record per block the archive holds, a range archive covers every
+    /// block of its range. Only the last block of a range has its hash, the
+    /// others are read from the range index when needed
+    pub fn covered_blocks(self) -> Vec<ArchiveRecord> {
+        match self.block_range {
+            Some(range) => (range[0]..=range[1])
+                .map(|block_number| ArchiveRecord {
+                    block_number,
+                    block_hash: self.block_hash.clone().filter(|_| block_number == range[1]),
+                    ..self.clone()
+                })
+                .collect(),
+            None => vec![self],
+        }
+    }
+}
+
+/// An archive whose `Block-Hash` tag is not the canonical hash of its block
+/// number, e.g. a block orphaned by a reorg.
+#[derive(Clone, Debug)]
+pub struct NonCanonicalArchive {
+    pub archive: ArchiveRecord,
+    pub canonical_hash: String,
+}
+
+/// Archived blocks seen by a scan in scan order, plus the cursor of the last
+/// scanned edge so the next scan can resume right after it. A scan stopped by
+/// its page limit is not `complete`, the archives after its cursor are still
+/// unseen.
+pub struct ScannedBlocks {
+    pub archives: Vec<ArchiveRecord>,
+    pub cursor: Option<String>,
+    pub complete: bool,
+}
+
+async fn retrieve_all_transactions(
+    source: &dyn GqlSource,
+    scan_count: u32,
+    address: &str,
+    start_cursor: Option<String>,
+) -> Result<ScannedBlocks, GqlError> {
+    let mut archives: Vec<ArchiveRecord> = Vec::new();
+    let mut cursor: Option<String> = start_cursor;
     const PAGE_SIZE: u32 = 1000;
     let mut page_count: u32 = 0;
+    let mut complete = true;
 
     loop {
         page_count += 1;
         println!(
-            "Fetching page {}. Current cursor: {:#?}",
-            page_count, cursor
+            "Fetching {} page {}. Current cursor: {:#?}",
+            source.name(),
+            page_count,
+            cursor
         );
 
-        let query = json!({
-            "query": r#"
-            query GetTransactions($cursor: String, $address: String!, $pageSize: Int!) {
-                transactions(
-                    first: $pageSize,
-                    after: $cursor,
-                    sort: HEIGHT_DESC,
-                    owners: [$address],
-                    tags: [
-                        { name: "Protocol", values: ["WeaveVM-ExEx"] }
-                    ],
-                ) {
-                    edges {
-                        node {
-                            tags {
-                                name
-                                value
-                            }
-                        }
-                        cursor
-                    }
-                    pageInfo {
-                        hasNextPage
-                    }
-                }
-            }
-            "#,
-            "variables": {
-                "cursor": cursor,
-                "pageSize": PAGE_SIZE,
-                "address": address
-            }
-        });
+        let query = source.build_query(address, cursor.as_deref(), PAGE_SIZE);
 
-        let res = send_graphql(ARWEAVE_GQL_GATEWAY, query)
-            .await
-            .unwrap_or(generate_empty_gql_server_res());
+        let res = send_graphql_with_retry(source.gateway(), &query).await?;
 
         let transactions = res
             .get("data")
             .and_then(|data| data.get("transactions"))
-            .ok_or_else(|| Error::msg("Invalid response structure"))?;
+            .ok_or_else(|| GqlError::InvalidResponse("Invalid response structure".into()))?;
 
-        let new_block_numbers: Vec<u32> = transactions
+        let edges = transactions
             .get("edges")
             .and_then(|edges| edges.as_array())
-            .ok_or_else(|| Error::msg("Edges not found or not an array"))?
-            .iter()
-            .filter_map(|edge| {
-                edge.get("node")
-                    .and_then(|node| node.get("tags"))
-                    .and_then(|tags| tags.as_array())
-                    .and_then(|tags| {
-                        tags.iter().find_map(|tag| {
-                            if tag.get("name")?.as_str()? == "Block-Number" {
-                                tag.get("value")?.as_str()?.parse::<u32>().ok()
-                            } else {
-                                None
-                            }
-                        })
-                    })
-            })
-            .collect();
+            .ok_or_else(|| GqlError::InvalidResponse("Edges not found or not an array".into()))?;
+        let new_archives = page_archives(edges, cursor.clone());
 
         println!(
             "Fetched {} new block numbers on page {}",
-            new_block_numbers.len(),
+            new_archives.len(),
             page_count
         );
-        block_numbers.extend(new_block_numbers);
+        archives.extend(new_archives);
+
+        // oldest-first pagination: the page cursor points at the newest scanned
+        // archive, keep it even on the final page so it can be checkpointed
+        if let Some(page_cursor) = source.page_cursor(transactions) {
+            cursor = Some(page_cursor);
+        }
 
         let page_info = transactions
             .get("pageInfo")
-            .ok_or_else(|| Error::msg("PageInfo not found"))?;
+            .ok_or_else(|| GqlError::InvalidResponse("PageInfo not found".into()))?;
 
         let has_next_page = page_info
             .get("hasNextPage")
             .and_then(|hnp| hnp.as_bool())
-            .unwrap_or(false);
+            .ok_or_else(|| GqlError::InvalidResponse("hasNextPage not found".into()))?;
 
         if !has_next_page {
             println!("No more pages. Pagination complete.");
             break;
         }
 
-        cursor = transactions
-            .get("edges")
-            .and_then(|edges| edges.as_array())
-            .and_then(|edges| edges.last())
-            .and_then(|last_edge| last_edge.get("cursor"))
-            .and_then(|cursor_val| cursor_val.as_str())
-            .map(String::from);
-
         println!(
             "Page {} complete. Has next page: {}. Next cursor: {:#?}",
             page_count, has_next_page, cursor
         );
-        if page_count > scan_count {
+        if page_count >= scan_count {
+            println!(
+                "{} scan stopped after {} pages, the next run resumes from cursor {:?}",
+                source.name(),
+                page_count,
+                cursor
+            );
+            complete = false;
             break;
         }
     }
 
     println!("Pagination complete. Total pages fetched: {}", page_count);
 
-    block_numbers.sort();
-    block_numbers.dedup();
-
     println!("{}", "#".repeat(100));
-    println!("Total scanned block numbers: {}", block_numbers.len());
+    println!("Total scanned block numbers: {}", archives.len());
+
+    Ok(ScannedBlocks {
+        archives,
+        cursor,
+        complete,
+    })
+}
+
+/// Archives of a page of `edges`, each with the cursor of the edge before it.
+/// `cursor` is the one the page was requested after
+fn page_archives(edges: &[Value], mut cursor: Option<String>) -> Vec<ArchiveRecord> {
+    let mut archives = Vec::with_capacity(edges.len());
+    for edge in edges.iter() {
+        if let Some(mut archive) = edge.get("node").and_then(ArchiveRecord::from_node) {
+            archive.resume_cursor = cursor.clone();
+            archives.push(archive);
+        }
+        if let Some(edge_cursor) = edge.get("cursor").and_then(Value::as_str) {
+            cursor = Some(edge_cursor.to_string());
+        }
+    }
+    archives
+}
+
+/// Persisted scan progress: every block up to `block_number` is known to be
+/// archived, and every archive before the per source/owner `cursors` has
+/// been seen. The blocks above `block_number` seen before a cursor, by scans
+/// that stopped at their page limit, are kept per source/owner in `archived`.
+#[derive(Debug, Default)]
+pub struct ScanCheckpoint {
+    pub block_number: Option<u32>,
+    pub cursors: HashMap<String, String>,
+    pub archived: HashMap<String, BlockRanges>,
+}
+
+impl ScanCheckpoint {
+    pub fn from_rows(rows: Vec<PlanetScaleCheckpoint>) -> ScanCheckpoint {
+        // every owner row is written with the same height, the lowest one wins
+        // if a previous save was interrupted halfway. A negative height is a
+        // scan that never got past its first gap
+        let block_number = rows
+            .iter()
+            .map(|row| u32::try_from(row.block_number).ok())
+            .min()
+            .flatten();
+        let mut cursors = HashMap::new();
+        let mut archived = HashMap::new();
+        for row in rows.into_iter() {
+            if !row.archived.is_empty() {
+                // a row that doesn't parse is rescanned from its cursor on
+                if let Ok(ranges) = serde_json::from_str::<BlockRanges>(&row.archived) {
+                    archived.insert(row.scan_key.clone(), ranges);
+                }
+            }
+            if !row.cursor.is_empty() {
+                cursors.insert(row.scan_key, row.cursor);
+            }
+        }
+
+        ScanCheckpoint {
+            block_number,
+            cursors,
+            archived,
+        }
+    }
+
+    /// first block height that still has to be checked
+    pub fn next_block(&self) -> u32 {
+        self.block_number.map(|block| block + 1).unwrap_or(0)
+    }
+
+    /// Checkpoint of a complete scan whose gaps are all archived or queued.
+    /// It stops under the reorg window and moves the cursors back before the
+    /// window archives, so the next run checks the window against the
+    /// canonical chain again
+    pub fn after_complete_scan(&self, gap_scan: &GapScan) -> ScanCheckpoint {
+        let mut cursors = self.cursors.clone();
+        cursors.extend(gap_scan.cursors.clone());
+        for (scan_key, cursor) in gap_scan.window_cursors.iter() {
+            match cursor {
+                Some(cursor) => cursors.insert(scan_key.clone(), cursor.clone()),
+                None => cursors.remove(scan_key),
+            };
+        }
+
+        ScanCheckpoint {
+            block_number: gap_scan.window_start.checked_sub(1),
+            cursors,
+            // the window blocks of this scan are scanned again, only those
+            // seen before its start cursor are kept
+            archived: self.archived.clone(),
+        }
+    }
+}
 
-    Ok(block_numbers)
+/// Result of a gap detection run over the blocks above the checkpoint. An
+/// incomplete scan reports no missing blocks, only the progress to save.
+#[derive(Debug)]
+pub struct GapScan {
+    pub complete: bool,
+    pub missing: BlockRanges,
+    /// archives conflicting with the canonical chain, their blocks are part
+    /// of `missing`
+    pub non_canonical: Vec<NonCanonicalArchive>,
+    /// archived blocks that failed deep verification, also part of `missing`
+    pub corrupt: BlockRanges,
+    pub latest_block: u32,
+    /// first block whose archives were checked against the canonical chain
+    pub window_start: u32,
+    /// cursors of the last scanned archive per source/owner
+    pub cursors: HashMap<String, String>,
+    /// per source/owner, the cursor before the first scanned archive of the
+    /// window, `None` to rescan from the start
+    pub window_cursors: HashMap<String, Option<String>>,
+    /// canonical archived blocks above the checkpoint seen before the cursors
+    pub archived: HashMap<String, BlockRanges>,
 }
 
-pub async fn detect_missing_blocks(scan_count: u32) -> Result<Vec<u32>, Error> {
-    // load WeaveVM address book
+/// owners of the `WeaveVM-ExEx` archives, the ExEx publisher and the
+/// backfiller from the WeaveVM address book, plus the owner of the uploads of
+/// this backfiller when its wallet is another one
+pub fn archive_owners(uploader_owner: Option<String>) -> Vec<String> {
     let address_book: Value = serde_json::from_str(ADDRESS_BOOK).unwrap();
-    let exex_archiver_addr = address_book["ario_fmt_alphanet_exex_publisher"]
-        .as_str()
-        .unwrap();
-    let exex_backfill_addr = address_book["ario_fmt_alphanet_exex_backfiller"]
+    let mut owners: Vec<String> = [
+        "ario_fmt_alphanet_exex_publisher",
+        "ario_fmt_alphanet_exex_backfiller",
+    ]
+    .iter()
+    .map(|name| address_book[name].as_str().unwrap().to_string())
+    .collect();
+
+    if let Some(owner) = uploader_owner.filter(|owner| !owners.contains(owner)) {
+        owners.push(owner);
+    }
+    owners
+}
+
+/// backfiller address of the WeaveVM address book
+pub fn backfiller_address() -> String {
+    let address_book: Value = serde_json::from_str(ADDRESS_BOOK).unwrap();
+    address_book["ario_fmt_alphanet_exex_backfiller"]
         .as_str()
-        .unwrap();
-    let exex_archiver_blocks = retrieve_all_transactions(scan_count, exex_archiver_addr)
-        .await
-        .unwrap();
-    let exex_backfill_blocks = retrieve_all_transactions(scan_count, exex_backfill_addr)
-        .await
-        .unwrap();
-    // concat archiver and backfill blocks
-    let mut blocks = [&exex_archiver_blocks[..], &exex_backfill_blocks[..]].concat();
-    // remove possible duplicates from both archiver & backfill
-    blocks.sort();
-    blocks.dedup();
+        .unwrap()
+        .to_string()
+}
+
+/// checkpoint keys of every scanned source/owner pair
+pub fn scan_keys(sources: &[Box<dyn GqlSource>], owners: &[String]) -> Vec<String> {
+    sources
+        .iter()
+        .flat_map(|source| owners.iter().map(|owner| source.scan_key(owner)))
+        .collect()
+}
+
+pub async fn detect_missing_blocks(
+    sources: &[Box<dyn GqlSource>],
+    owners: &[String],
+    scan_count: u32,
+    checkpoint: &ScanCheckpoint,
+    verify_mode: &VerifyMode,
+) -> Result<GapScan, Error> {
     // latest WeaveVM block number from the RPC
-    let latest_block = get_latest_block_number().await.unwrap_or(0) as u32;
+    let latest_block = get_latest_block_number().await? as u32;
+    let next_block = checkpoint.next_block();
+    // only the archives of recent blocks can still be orphaned by a reorg
+    let reorg_window_start = latest_block
+        .saturating_sub(reorg_window_from_env()?)
+        .max(next_block);
+
+    let mut archives: Vec<ArchiveRecord> = Vec::new();
+    let mut cursors: HashMap<String, String> = HashMap::new();
+    let mut window_cursors: HashMap<String, Option<String>> = HashMap::new();
+    // blocks seen per source/owner, kept for the next run if a scan is cut short
+    let mut scanned_blocks: HashMap<String, Vec<u32>> = HashMap::new();
+    let mut complete = true;
+
+    // union of every source: a block indexed by any of them is archived
+    for source in sources.iter() {
+        for owner in owners.iter() {
+            // resume after the last checkpointed archive of this source/owner
+            let scan_key = source.scan_key(owner);
+            let start_cursor = checkpoint.cursors.get(&scan_key).cloned();
+            let scanned =
+                retrieve_all_transactions(source.as_ref(), scan_count, owner, start_cursor).await?;
+
+            let covered: Vec<ArchiveRecord> = scanned
+                .archives
+                .into_iter()
+                .flat_map(ArchiveRecord::covered_blocks)
+                .collect();
+            scanned_blocks.insert(
+                scan_key.clone(),
+                covered.iter().map(|archive| archive.block_number).collect(),
+            );
+            if let Some(cursor) = window_cursor(&covered, reorg_window_start) {
+                window_cursors.insert(scan_key.clone(), cursor);
+            }
+            archives.extend(covered);
+            if let Some(cursor) = scanned.cursor {
+                cursors.insert(scan_key, cursor);
+            }
+            complete &= scanned.complete;
+        }
+    }
 
-    // expected (correct but not a must to be found) blocks sequencer on Arweave
-    // its final form will represent the missed_blocks Vec
-    let mut canonical_chain_blocks: Vec<u32> = (0..=latest_block).collect();
+    // blocks under the checkpoint were already verified by a previous run
+    archives.retain(|archive| {
+        archive.block_number >= next_block && archive.block_number <= latest_block
+    });
+    archives.sort_unstable_by_key(|archive| archive.block_number);
+
+    let non_canonical = detect_non_canonical_archives(&archives, reorg_window_start).await?;
+    let non_canonical_blocks: HashSet<u32> = non_canonical
+        .iter()
+        .map(|conflict| conflict.archive.block_number)
+        .collect();
+
+    // archives that exist but whose content can't be retrieved or decoded
+    // into the canonical block are gaps too
+    archives.retain(|archive| !non_canonical_blocks.contains(&archive.block_number));
+    let corrupt = verify_archives(&archives, verify_mode).await?;
+
+    // a block only counts as archived if at least one of its archives carries
+    // the canonical hash (or carries no hash to compare against) and verifies
+    let is_archived = |block_number: &u32| {
+        *block_number >= next_block
+            && *block_number <= latest_block
+            && !non_canonical_blocks.contains(block_number)
+            && !corrupt.contains(*block_number)
+    };
+    let mut archived: HashMap<String, BlockRanges> = checkpoint.archived.clone();
+    for (scan_key, mut blocks) in scanned_blocks.into_iter() {
+        blocks.retain(is_archived);
+        blocks.sort_unstable();
+        blocks.dedup();
+        archived
+            .entry(scan_key)
+            .or_default()
+            .extend(&BlockRanges::from_sorted(&blocks));
+    }
 
-    for &current_block in blocks.iter() {
-        // Remove current_block from canonical_chain_blocks
-        canonical_chain_blocks.retain(|&block| block != current_block);
+    // archived blocks of every source/owner compressed into ranges, the gaps
+    // between them up to the chain tip are the missed blocks
+    let mut archived_ranges = BlockRanges::new();
+    for ranges in archived.values() {
+        archived_ranges.extend(ranges);
     }
+    let missing_ranges = match complete {
+        true => archived_ranges.complement(next_block, latest_block),
+        // blocks after a cursor are unseen, nothing can be called missing yet
+        false => BlockRanges::new(),
+    };
+
+    println!(
+        "missing blocks number: {:#?} in {} ranges, {} non-canonical archives, {} corrupt archived blocks (checkpoint: {:?}, complete scan: {})",
+        missing_ranges.block_count(),
+        missing_ranges.ranges().len(),
+        non_canonical.len(),
+        corrupt.block_count(),
+        checkpoint.block_number,
+        complete
+    );
+
+    Ok(GapScan {
+        complete,
+        missing: missing_ranges,
+        non_canonical,
+        corrupt,
+        latest_block,
+        window_start: reorg_window_start,
+        cursors,
+        window_cursors,
+        archived,
+    })
+}
+
+/// cursor a scan resumes from to see the archives of the blocks from
+/// `window_start` on again, the one before the first of them in scan order.
+/// None if `archives` has none of them, `Some(None)` to rescan from the start
+fn window_cursor(archives: &[ArchiveRecord], window_start: u32) -> Option<Option<String>> {
+    archives
+        .iter()
+        .find(|archive| archive.block_number >= window_start)
+        .map(|archive| archive.resume_cursor.clone())
+}
+
+/// Compares the `Block-Hash` tag of the archives from `window_start` on with
+/// the canonical hash from the WeaveVM RPC. Returns the mismatching archives
+/// of blocks that have no canonical archive at all, those blocks need to be
+/// backfilled again.
+///
+/// The checkpoint of a complete scan stops under the window, so every run
+/// checks the archives of the last `reorg_window` blocks again. A reorg deeper
+/// than `reorg_window` is only caught by a full rescan with
+/// `verify_archives=full` (see `VerifyMode::needs_full_scan`), which compares
+/// every archived block with the canonical one.
+async fn detect_non_canonical_archives(
+    archives: &[ArchiveRecord],
+    window_start: u32,
+) -> Result<Vec<NonCanonicalArchive>, Error> {
+    // archives are sorted by block number
+    let mut archives: Vec<ArchiveRecord> = archives
+        [archives.partition_point(|archive| archive.block_number < window_start)..]
+        .to_vec();
+    let mut block_numbers: Vec<u32> = archives
+        .iter()
+        .filter(|archive| archive.block_hash.is_some() || archive.block_range.is_some())
+        .map(|archive| archive.block_number)
+        .collect();
+    block_numbers.dedup();
 
-    println!("missing blocks number: {:#?}", canonical_chain_blocks.len());
+    let canonical_hashes = get_block_hashes(&block_numbers).await?;
+    add_range_block_hashes(&mut archives, &canonical_hashes).await?;
 
-    Ok(canonical_chain_blocks)
+    Ok(non_canonical_archives(&archives, &canonical_hashes))
 }
 
-// util functions
+/// archives of the blocks without any archive carrying the canonical hash,
+/// `archives` being sorted by block number
+fn non_canonical_archives(
+    archives: &[ArchiveRecord],
+    canonical_hashes: &HashMap<u32, String>,
+) -> Vec<NonCanonicalArchive> {
+    let mut non_canonical: Vec<NonCanonicalArchive> = Vec::new();
+
+    // check them block by block
+    for block_archives in archives.chunk_by(|a, b| a.block_number == b.block_number) {
+        let block_number = block_archives[0].block_number;
+        let canonical_hash = match canonical_hashes.get(&block_number) {
+            Some(hash) => hash,
+            None => continue,
+        };
+
+        let is_canonical = |archive: &ArchiveRecord| match &archive.block_hash {
+            Some(hash) => same_hash(hash, canonical_hash),
+            None => true,
+        };
+
+        if block_archives.iter().any(is_canonical) {
+            continue;
+        }
 
-fn generate_empty_gql_server_res() -> Value {
-    json!({
-        "data": {
-            "transactions": {
-                "edges": [],
-                "pageInfo": {
-                    "hasNextPage": false,
-                }
+        for archive in block_archives.iter() {
+            println!(
+                "non-canonical archive {} for block {}: {:?} != {}",
+                archive.arweave_id, block_number, archive.block_hash, canonical_hash
+            );
+            non_canonical.push(NonCanonicalArchive {
+                archive: archive.clone(),
+                canonical_hash: canonical_hash.clone(),
+            });
+        }
+    }
+
+    non_canonical
+}
+
+/// Fills in the hash of every block of the range archives whose last block
+/// is not canonical, from their range index. The blocks of a range ending in
+/// a canonical block are canonical too.
+async fn add_range_block_hashes(
+    archives: &mut [ArchiveRecord],
+    canonical_hashes: &HashMap<u32, String>,
+) -> Result<(), Error> {
+    let orphaned: HashMap<String, BlockRange> = archives
+        .iter()
+        .filter_map(|archive| {
+            let range = archive.block_range?;
+            let end_hash = archive.block_hash.as_deref()?;
+            let canonical_hash = canonical_hashes.get(&range[1])?;
+            match archive.block_number == range[1] && !same_hash(end_hash, canonical_hash) {
+                true => Some((archive.arweave_id.clone(), range)),
+                false => None,
             }
+        })
+        .collect();
+    if orphaned.is_empty() {
+        return Ok(());
+    }
+
+    let client = Client::new();
+    let gateway = gateway_from_env();
+    let mut indexes = HashMap::with_capacity(orphaned.len());
+    for (arweave_id, range) in orphaned {
+        let index = fetch_range_index(&client, &gateway, &arweave_id, range)
+            .await
+            .map_err(|e| {
+                Error::msg(format!(
+                    "failed to read the range index of {}: {}",
+                    arweave_id, e
+                ))
+            })?;
+        indexes.insert(arweave_id, index);
+    }
+
+    for archive in archives.iter_mut() {
+        if let Some(index) = indexes.get(&archive.arweave_id) {
+            archive.block_hash = index
+                .entry(archive.block_number)
+                .map(|entry| entry.block_hash());
         }
-    })
+    }
+
+    Ok(())
+}
+
+/// Id of a backfill data item of `owner` already indexed by `gateway` with
+/// all of the given tags, the GQL of the uploader finding it within seconds
+/// of its upload
+pub async fn find_backfill_upload(
+    gateway: &str,
+    owner: &str,
+    tags: &[(&str, String)],
+) -> Result<Option<String>, Error> {
+    // tag values are quoted as JSON strings, which GQL string literals accept
+    let mut tag_filters = vec![r#"{ name: "Protocol", values: ["WeaveVM-ExEx"] }"#.to_string()];
+    for (name, value) in tags {
+        tag_filters.push(format!(
+            "{{ name: {}, values: [{}] }}",
+            serde_json::to_string(name)?,
+            serde_json::to_string(value)?
+        ));
+    }
+
+    let query = json!({
+        "query": format!(
+            r#"
+            query FindUpload($address: String!) {{
+                transactions(first: 1, owners: [$address], tags: [{}]) {{
+                    edges {{
+                        node {{
+                            id
+                        }}
+                    }}
+                }}
+            }}
+            "#,
+            tag_filters.join(", ")
+        ),
+        "variables": {
+            "address": owner
+        }
+    });
+
+    let res = send_graphql_with_retry(gateway, &query).await?;
+    let edges = res
+        .get("data")
+        .and_then(|data| data.get("transactions"))
+        .and_then(|transactions| transactions.get("edges"))
+        .and_then(|edges| edges.as_array())
+        .ok_or_else(|| GqlError::InvalidResponse("Edges not found or not an array".into()))?;
+
+    Ok(edges
+        .first()
+        .and_then(|edge| edge.get("node"))
+        .and_then(|node| node.get("id"))
+        .and_then(|id| id.as_str())
+        .map(String::from))
+}
+
+/// `reorg_window` env var, how many blocks under the chain tip get their
+/// archives checked against the canonical hashes
+fn reorg_window_from_env() -> Result<u32, Error> {
+    let window = match get_env_var("reorg_window").ok() {
+        Some(window) => window,
+        None => return Ok(DEFAULT_REORG_WINDOW),
+    };
+
+    match window.trim().parse::<u32>().ok() {
+        Some(window) => Ok(window),
+        None => Err(Error::msg(format!(
+            "invalid reorg_window value: {}",
+            window
+        ))),
+    }
 }
 
-// async fn irys_retrieve_all_transactions(scan_count: u32) -> Result<Vec<u32>, Error> {
-//     let mut block_numbers: Vec<u32> = Vec::new();
-//     let mut cursor: Option<String> = None;
-//     const PAGE_SIZE: u32 = 1000;
-//     let mut page_count: u32 = 0;
-//     let current_timestamp = get_timestamp();
-
-//     loop {
-//         page_count += 1;
-//         println!(
-//             "Fetching page {}. Current cursor: {:#?}",
-//             page_count, cursor
-//         );
-
-//         let query = json!({
-//             "query": r#"
-//             query GetTransactions($cursor: String, $pageSize: Int!, $startTimestamp: BigInt!, $endTimestamp: BigInt!) {
-//                 transactions(
-//                     first: $pageSize,
-//                     after: $cursor,
-//                     timestamp: {from: $startTimestamp, to: $endTimestamp},
-//                     order: DESC,
-//                     tags: [
-//                         { name: "Protocol", values: ["WeaveVM-ExEx"] }
-//                     ],
-//                     owners: ["5JUE58yemNynRDeQDyVECKbGVCQbnX7unPrBRqCPVn5Z", "F8XVrMQzsHiWfn1CaKtUPxAgUkATXQjXULWw3oVXCiFV"]
-//                 ) {
-//                     edges {
-//                         node {
-//                             tags {
-//                                 name
-//                                 value
-//                             }
-//                         }
-//                         cursor
-//                     }
-//                     pageInfo {
-//                         hasNextPage
-//                         endCursor
-//                     }
-//                 }
-//             }
-//             "#,
-//             "variables": {
-//                 "cursor": cursor,
-//                 "pageSize": PAGE_SIZE,
-//                 "startTimestamp": GQL_START_TIMESTAMP,
-//                 "endTimestamp": current_timestamp
-//             }
-//         });
-
-//         let res = send_graphql(IRYS_GQL_GATEWAY, query)
-//             .await
-//             .unwrap_or(generate_empty_gql_server_res());
-
-//         let transactions = res
-//             .get("data")
-//             .and_then(|data| data.get("transactions"))
-//             .ok_or_else(|| Error::msg("Invalid response structure"))?;
-
-//         let new_block_numbers: Vec<u32> = transactions
-//             .get("edges")
-//             .and_then(|edges| edges.as_array())
-//             .ok_or_else(|| Error::msg("Edges not found or not an array"))?
-//             .iter()
-//             .filter_map(|edge| {
-//                 edge.get("node")
-//                     .and_then(|node| node.get("tags"))
-//                     .and_then(|tags| tags.as_array())
-//                     .and_then(|tags| {
-//                         tags.iter().find_map(|tag| {
-//                             if tag.get("name")?.as_str()? == "Block-Number" {
-//                                 tag.get("value")?.as_str()?.parse::<u32>().ok()
-//                             } else {
-//                                 None
-//                             }
-//                         })
-//                     })
-//             })
-//             .collect();
-
-//         println!(
-//             "Fetched {} new block numbers on page {}",
-//             new_block_numbers.len(),
-//             page_count
-//         );
-//         block_numbers.extend(new_block_numbers);
-
-//         let page_info = transactions
-//             .get("pageInfo")
-//             .ok_or_else(|| Error::msg("PageInfo not found"))?;
-
-//         let has_next_page = page_info
-//             .get("hasNextPage")
-//             .and_then(|hnp| hnp.as_bool())
-//             .unwrap_or(false);
-
-//         if !has_next_page {
-//             println!("No more pages. Pagination complete.");
-//             break;
-//         }
-
-//         cursor = page_info
-//             .get("endCursor")
-//             .and_then(|ec| ec.as_str())
-//             .map(String::from);
-
-//         println!(
-//             "Page {} complete. Has next page: {}. Next cursor: {:#?}",
-//             page_count, has_next_page, cursor
-//         );
-//         if page_count > scan_count {
-//             break;
-//         }
-//     }
-
-//     println!("Pagination complete. Total pages fetched: {}", page_count);
-
-//     block_numbers.sort();
-//     block_numbers.dedup();
-
-//     println!("{}", "#".repeat(100));
-//     println!("Total scanned block numbers: {}", block_numbers.len());
-
-//     Ok(block_numbers)
-// }
+fn same_hash(a: &str, b: &str) -> bool {
+    let a = a.strip_prefix("0x").unwrap_or(a);
+    let b = b.strip_prefix("0x").unwrap_or(b);
+    a.eq_ignore_ascii_case(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_archive_end_block_carries_the_end_hash() {
+        let node = json!({
+            "id": "range-archive",
+            "tags": [
+                { "name": "Block-Range-Start", "value": "10" },
+                { "name": "Block-Range-End", "value": "12" },
+                { "name": "Block-Range-End-Hash", "value": "0xend" },
+            ]
+        });
+        let blocks = ArchiveRecord::from_node(&node).unwrap().covered_blocks();
+
+        let hashes: Vec<(u32, Option<&str>)> = blocks
+            .iter()
+            .map(|archive| (archive.block_number, archive.block_hash.as_deref()))
+            .collect();
+        assert_eq!(hashes, vec![(10, None), (11, None), (12, Some("0xend"))]);
+    }
+
+    #[test]
+    fn uploader_wallet_is_scanned_once() {
+        let address_book = archive_owners(None);
+        assert_eq!(address_book.len(), 2);
+        assert_eq!(address_book[1], backfiller_address());
+
+        assert_eq!(archive_owners(Some(backfiller_address())), address_book);
+        let owners = archive_owners(Some("native-wallet".to_string()));
+        assert_eq!(owners[..2], address_book[..]);
+        assert_eq!(owners[2], "native-wallet");
+    }
+
+    fn edge(block_number: u32, block_hash: &str) -> Value {
+        json!({
+            "node": {
+                "id": format!("archive-{}", block_number),
+                "tags": [
+                    { "name": "Block-Number", "value": block_number.to_string() },
+                    { "name": "Block-Hash", "value": block_hash },
+                ]
+            },
+            "cursor": format!("cursor-{}", block_number)
+        })
+    }
+
+    #[test]
+    fn next_run_checks_the_reorg_window_again() {
+        let hash = |block_number: u32| format!("0x{:064x}", block_number);
+        let edges: Vec<Value> = (0..=103).map(|n| edge(n, &hash(n))).collect();
+        let scan_key = "arweave:owner".to_string();
+        // archives a scan resuming from the checkpoint cursor sees, out of
+        // the `uploaded` first edges
+        let scan = |checkpoint: &ScanCheckpoint, uploaded: usize| {
+            let cursor = checkpoint.cursors.get(&scan_key).cloned();
+            let start = edges
+                .iter()
+                .position(|edge| edge["cursor"].as_str() == cursor.as_deref())
+                .map_or(0, |position| position + 1);
+            page_archives(&edges[start..uploaded], cursor)
+        };
+        let complete_scan =
+            |archives: &[ArchiveRecord], latest_block: u32, window_start: u32| GapScan {
+                complete: true,
+                missing: BlockRanges::new(),
+                non_canonical: Vec::new(),
+                corrupt: BlockRanges::new(),
+                latest_block,
+                window_start,
+                cursors: HashMap::from([(scan_key.clone(), format!("cursor-{}", latest_block))]),
+                window_cursors: window_cursor(archives, window_start)
+                    .map(|cursor| (scan_key.clone(), cursor))
+                    .into_iter()
+                    .collect(),
+                archived: HashMap::new(),
+            };
+
+        // first run up to tip 100 with a 10 blocks window
+        let first = scan(&ScanCheckpoint::default(), 101);
+        let checkpoint =
+            ScanCheckpoint::default().after_complete_scan(&complete_scan(&first, 100, 90));
+        assert_eq!(checkpoint.next_block(), 90);
+        assert_eq!(checkpoint.cursors[&scan_key], "cursor-89");
+
+        // block 98, under the first tip, is orphaned before the second run
+        let second = scan(&checkpoint, 104);
+        assert_eq!(second[0].block_number, 90);
+        let window: Vec<ArchiveRecord> = second
+            .iter()
+            .filter(|archive| archive.block_number >= 93)
+            .cloned()
+            .collect();
+        let canonical_hashes: HashMap<u32, String> = (93..=103)
+            .map(|n| (n, if n == 98 { hash(1000) } else { hash(n) }))
+            .collect();
+        let non_canonical: Vec<String> = non_canonical_archives(&window, &canonical_hashes)
+            .into_iter()
+            .map(|conflict| conflict.archive.arweave_id)
+            .collect();
+        assert_eq!(non_canonical, vec!["archive-98"]);
+
+        let checkpoint = checkpoint.after_complete_scan(&complete_scan(&second, 103, 93));
+        assert_eq!(checkpoint.next_block(), 93);
+        assert_eq!(checkpoint.cursors[&scan_key], "cursor-92");
+    }
+}
@@ -0,0 +1,430 @@
+use crate::utils::arweave_tx::{
+    b64url, b64url_decode, chunk_json, merkle_tree, owner_address, transaction_id, DataTransaction,
+    MerkleTree, MAX_CHUNK_SIZE,
+};
+use crate::utils::arweave_upload::{PartialUpload, Upload, Uploader};
+use crate::utils::constants::ARWEAVE_NODE_URL;
+use crate::utils::env_var::get_env_var;
+use async_trait::async_trait;
+use bundlr_sdk::tags::Tag;
+use eyre::eyre;
+use reqwest::Client;
+use rsa::pss::SigningKey;
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::{BigUint, RsaPrivateKey};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const CHUNK_MAX_ATTEMPTS: u32 = 3;
+
+/// RSA members of an Arweave JWK wallet file
+#[derive(Deserialize)]
+struct Jwk {
+    n: String,
+    e: String,
+    d: String,
+    p: String,
+    q: String,
+}
+
+/// Arweave wallet signing transactions with RSA-PSS
+pub struct ArweaveWallet {
+    owner: Vec<u8>,
+    signing_key: SigningKey<Sha256>,
+}
+
+impl ArweaveWallet {
+    pub fn from_jwk(jwk: &str) -> Result<ArweaveWallet, anyhow::Error> {
+        let jwk: Jwk = serde_json::from_str(jwk)
+            .map_err(|e| anyhow::Error::msg(format!("invalid arweave jwk: {}", e)))?;
+        let owner = b64url_decode(&jwk.n)?;
+        let uint = |value: &str| b64url_decode(value).map(|bytes| BigUint::from_bytes_be(&bytes));
+
+        let key = RsaPrivateKey::from_components(
+            BigUint::from_bytes_be(&owner),
+            uint(&jwk.e)?,
+            uint(&jwk.d)?,
+            vec![uint(&jwk.p)?, uint(&jwk.q)?],
+        )
+        .map_err(|e| anyhow::Error::msg(format!("invalid arweave jwk: {}", e)))?;
+
+        Ok(ArweaveWallet {
+            owner,
+            signing_key: SigningKey::new(key),
+        })
+    }
+
+    pub fn address(&self) -> String {
+        owner_address(&self.owner)
+    }
+
+    /// RSA-PSS signature over SHA-256 of `message`, with a 32 bytes salt
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.signing_key
+            .sign_with_rng(&mut rand::thread_rng(), message)
+            .to_vec()
+    }
+}
+
+/// Posts data items as native Arweave transactions, paid in AR by the wallet.
+/// Payloads above one chunk are posted chunk by chunk after the transaction.
+pub struct ArweaveUploader {
+    client: Client,
+    node: String,
+    wallet: ArweaveWallet,
+}
+
+impl ArweaveUploader {
+    /// Wallet from the JWK JSON in the `arweave_jwk` env var, posting to the
+    /// `arweave_node` env var (defaults to arweave.net)
+    pub fn from_env() -> Result<ArweaveUploader, anyhow::Error> {
+        let jwk =
+            get_env_var("arweave_jwk").map_err(|_| anyhow::Error::msg("arweave_jwk is not set"))?;
+        let node = get_env_var("arweave_node").unwrap_or_else(|_| ARWEAVE_NODE_URL.to_string());
+        let wallet = ArweaveWallet::from_jwk(&jwk)?;
+        println!("arweave uploads from wallet {}", wallet.address());
+
+        Ok(ArweaveUploader {
+            client: Client::new(),
+            node: node.trim_end_matches('/').to_string(),
+            wallet,
+        })
+    }
+
+    async fn get_text(&self, path: &str) -> eyre::Result<String> {
+        let res = self
+            .client
+            .get(format!("{}/{}", self.node, path))
+            .send()
+            .await?;
+        let status = res.status();
+        let body = res.text().await?;
+        if !status.is_success() {
+            return Err(eyre!("GET /{} failed with {}: {}", path, status, body));
+        }
+
+        eyre::Ok(body)
+    }
+
+    async fn post_json(&self, path: &str, body: &Value) -> eyre::Result<()> {
+        let res = self
+            .client
+            .post(format!("{}/{}", self.node, path))
+            .json(body)
+            .send()
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(eyre!("POST /{} failed with {}: {}", path, status, body));
+        }
+
+        eyre::Ok(())
+    }
+
+    /// posts every chunk of `data`, each retried a few times. On failure the
+    /// upload is left partial, resumable from the signed transaction `tx`
+    async fn post_chunks(&self, tree: &MerkleTree, data: &[u8], tx: &Value) -> eyre::Result<()> {
+        let id = tx["id"].as_str().unwrap_or_default();
+        for index in 0..tree.chunks.len() {
+            let chunk = chunk_json(tree, data, index);
+            let mut attempt = 1;
+            while let Err(e) = self.post_json("chunk", &chunk).await {
+                if attempt >= CHUNK_MAX_ATTEMPTS {
+                    return Err(eyre::Report::new(PartialUpload {
+                        id: id.to_string(),
+                        state: tx.to_string(),
+                        message: format!("chunk {} rejected: {}", index, e),
+                    }));
+                }
+                sleep(Duration::from_secs(attempt as u64)).await;
+                attempt += 1;
+            }
+        }
+
+        eyre::Ok(())
+    }
+
+    /// whether the node serves the last chunk of the mined transaction `id`
+    async fn last_chunk_stored(&self, id: &str) -> bool {
+        let offset = match self.get_text(&format!("tx/{}/offset", id)).await {
+            Ok(offset) => offset,
+            Err(_) => return false,
+        };
+        let end = match serde_json::from_str::<Value>(&offset) {
+            Ok(offset) => offset["offset"].as_str().unwrap_or_default().to_string(),
+            Err(_) => return false,
+        };
+
+        self.get_text(&format!("chunk/{}", end)).await.is_ok()
+    }
+}
+
+#[async_trait]
+impl Uploader for ArweaveUploader {
+    fn name(&self) -> &str {
+        "arweave"
+    }
+
+    fn unit(&self) -> &str {
+        "winston"
+    }
+
+    fn owner(&self) -> Option<String> {
+        Some(self.wallet.address())
+    }
+
+    /// the node indexes its own transactions, e.g. arweave.net
+    fn gql_gateway(&self) -> &str {
+        &self.node
+    }
+
+    async fn price(&self, bytes: usize) -> eyre::Result<u128> {
+        parse_winston(&self.get_text(&format!("price/{}", bytes)).await?)
+    }
+
+    async fn balance(&self) -> eyre::Result<Option<u128>> {
+        let path = format!("wallet/{}/balance", self.wallet.address());
+        parse_winston(&self.get_text(&path).await?).map(Some)
+    }
+
+    async fn upload(&self, data: Vec<u8>, tags: Vec<Tag>) -> eyre::Result<Upload> {
+        let tree = merkle_tree(&data);
+        let reward = self.get_text(&format!("price/{}", data.len())).await?;
+        let last_tx = self.get_text("tx_anchor").await?;
+
+        let tx = DataTransaction {
+            data_root: tree.data_root,
+            data_size: data.len(),
+            last_tx: b64url_decode(last_tx.trim()).map_err(|e| eyre!("{}", e))?,
+            owner: self.wallet.owner.clone(),
+            reward: reward.trim().to_string(),
+            tags: tags.into_iter().map(|tag| (tag.name, tag.value)).collect(),
+        };
+        let signature = self.wallet.sign(&tx.signature_data());
+        let id = transaction_id(&signature);
+
+        // small payloads travel inside the transaction
+        let inline = data.len() <= MAX_CHUNK_SIZE;
+        let tx = tx.to_json(&signature, inline.then_some(data.as_slice()));
+        // a timed out or failed post may still have been accepted by the
+        // node, the signed transaction is posted again rather than a new one
+        if let Err(e) = self.post_json("tx", &tx).await {
+            return Err(eyre::Report::new(PartialUpload {
+                id,
+                state: tx.to_string(),
+                message: format!("transaction post failed: {}", e),
+            }));
+        }
+
+        // the transaction is paid for once posted, failed chunks are resumed
+        // under the same transaction
+        if !inline {
+            self.post_chunks(&tree, &data, &tx).await?;
+        }
+
+        eyre::Ok(Upload { id, receipt: None })
+    }
+
+    /// an indexed transaction may miss chunks of its data, it is confirmed
+    /// once the node serves the last one as chunks are posted in order.
+    /// Otherwise its header is the state to post the chunks again from
+    async fn unconfirmed_state(&self, id: &str, size: usize) -> eyre::Result<Option<String>> {
+        let data_size = self.get_text(&format!("tx/{}/data_size", id)).await?;
+        if data_size.trim() != size.to_string() {
+            return Err(eyre!(
+                "transaction {} holds {} bytes instead of {}",
+                id,
+                data_size.trim(),
+                size
+            ));
+        }
+        // small payloads travel inside the transaction
+        if size <= MAX_CHUNK_SIZE || self.last_chunk_stored(id).await {
+            return eyre::Ok(None);
+        }
+
+        // a pending transaction has no header to resume from yet
+        let tx = self.get_text(&format!("tx/{}", id)).await?;
+        match serde_json::from_str::<Value>(&tx) {
+            Ok(header) if header["id"] == id => eyre::Ok(Some(tx)),
+            _ => Err(eyre!("transaction {} is not confirmed yet: {}", id, tx)),
+        }
+    }
+
+    /// posts the signed transaction again, which the node accepts once, and
+    /// every chunk of its data unless it travels inside the transaction
+    async fn resume(&self, state: &str, data: Vec<u8>) -> eyre::Result<Upload> {
+        let tx: Value = serde_json::from_str(state)?;
+        let id = tx["id"]
+            .as_str()
+            .ok_or_else(|| eyre!("partial upload without transaction id"))?
+            .to_string();
+        let tree = merkle_tree(&data);
+        if tx["data_root"].as_str() != Some(b64url(&tree.data_root).as_str()) {
+            return Err(eyre!("data does not match the data root of {}", id));
+        }
+
+        self.post_json("tx", &tx)
+            .await
+            .map_err(|e| eyre!("arweave transaction {} rejected: {}", id, e))?;
+        if tx["data"].as_str().unwrap_or_default().is_empty() {
+            self.post_chunks(&tree, &data, &tx).await?;
+        }
+
+        eyre::Ok(Upload { id, receipt: None })
+    }
+}
+
+fn parse_winston(value: &str) -> eyre::Result<u128> {
+    value
+        .trim()
+        .parse::<u128>()
+        .map_err(|_| eyre!("invalid winston amount: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::arweave_tx::DataTransaction;
+    use crate::utils::test_fixtures::fixture_bytes;
+    use rsa::pss::{Signature, VerifyingKey};
+    use rsa::signature::Verifier;
+    use rsa::RsaPublicKey;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn verify(owner: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let key =
+            RsaPublicKey::new(BigUint::from_bytes_be(owner), BigUint::from(65537u32)).unwrap();
+        let signature = Signature::try_from(signature).unwrap();
+        VerifyingKey::<Sha256>::new(key)
+            .verify(message, &signature)
+            .is_ok()
+    }
+
+    #[test]
+    fn signatures_verify_against_the_wallet_owner() {
+        let jwk = String::from_utf8(fixture_bytes("arweave/wallet.json")).unwrap();
+        let vectors: Value =
+            serde_json::from_slice(&fixture_bytes("arweave/vectors.json")).unwrap();
+        let vector = &vectors["transaction"];
+        let wallet = ArweaveWallet::from_jwk(&jwk).unwrap();
+        assert_eq!(wallet.address(), vector["owner_address"]);
+
+        let message = b64url_decode(vector["signature_data"].as_str().unwrap()).unwrap();
+        // signed by the wallet, and by the vector generator with the same key
+        let signature = wallet.sign(&message);
+        assert!(verify(&wallet.owner, &message, &signature));
+        let vector_signature = b64url_decode(vector["signature"].as_str().unwrap()).unwrap();
+        assert!(verify(&wallet.owner, &message, &vector_signature));
+
+        let tampered = DataTransaction {
+            data_root: [0; 32],
+            data_size: 0,
+            last_tx: Vec::new(),
+            owner: wallet.owner.clone(),
+            reward: "0".to_string(),
+            tags: Vec::new(),
+        };
+        assert!(!verify(
+            &wallet.owner,
+            &tampered.signature_data(),
+            &signature
+        ));
+    }
+
+    /// local node answering each request with the status and body of the
+    /// first route its path starts with, 404 otherwise
+    fn stub_node(routes: Vec<(&'static str, u16, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let node = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(length) =
+                        header.to_ascii_lowercase().strip_prefix("content-length:")
+                    {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                }
+                reader.read_exact(&mut vec![0; content_length]).unwrap();
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let (status, body) = routes
+                    .iter()
+                    .find(|(prefix, ..)| path.starts_with(prefix))
+                    .map_or((404, ""), |(_, status, body)| (*status, *body));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Stub\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        node
+    }
+
+    #[tokio::test]
+    async fn failed_transaction_post_is_resumable() {
+        let jwk = String::from_utf8(fixture_bytes("arweave/wallet.json")).unwrap();
+        let uploader = ArweaveUploader {
+            client: Client::new(),
+            node: stub_node(vec![
+                ("/price/", 200, "1000"),
+                ("/tx_anchor", 200, "AAAA"),
+                ("/tx", 500, "node overloaded"),
+            ]),
+            wallet: ArweaveWallet::from_jwk(&jwk).unwrap(),
+        };
+
+        let e = uploader
+            .upload(b"block".to_vec(), Vec::new())
+            .await
+            .unwrap_err();
+        let partial = e.downcast_ref::<PartialUpload>().unwrap();
+        let tx: Value = serde_json::from_str(&partial.state).unwrap();
+        assert_eq!(tx["id"], partial.id);
+        assert_eq!(tx["data"], b64url(b"block"));
+        assert!(partial.message.contains("node overloaded"));
+    }
+
+    #[tokio::test]
+    async fn indexed_transaction_missing_chunks_is_resumed() {
+        let jwk = String::from_utf8(fixture_bytes("arweave/wallet.json")).unwrap();
+        let uploader = ArweaveUploader {
+            client: Client::new(),
+            node: stub_node(vec![
+                ("/tx/indexed/data_size", 200, "300000"),
+                ("/tx/indexed/offset", 404, "Not Found"),
+                ("/tx/indexed", 200, r#"{"id":"indexed","data":""}"#),
+            ]),
+            wallet: ArweaveWallet::from_jwk(&jwk).unwrap(),
+        };
+
+        let state = uploader.unconfirmed_state("indexed", 300000).await.unwrap();
+        assert_eq!(state.as_deref(), Some(r#"{"id":"indexed","data":""}"#));
+        // a transaction holding another size is not the upload
+        assert!(uploader
+            .unconfirmed_state("indexed", 300000 - 1)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use anyhow::Error;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256, Sha384};
+
+/// Arweave chunks data in 256 KiB pieces, a last piece under 32 KiB is
+/// balanced with the one before it
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+pub const MIN_CHUNK_SIZE: usize = 32 * 1024;
+const NOTE_SIZE: usize = 32;
+
+pub fn b64url(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn b64url_decode(value: &str) -> Result<Vec<u8>, Error> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|e| Error::msg(format!("invalid base64url value {}: {}", value, e)))
+}
+
+/// Input of the Arweave deep hash, the SHA-384 commitment signed by
+/// transactions
+pub enum DeepHashItem {
+    Blob(Vec<u8>),
+    List(Vec<DeepHashItem>),
+}
+
+pub fn deep_hash(item: &DeepHashItem) -> [u8; 48] {
+    match item {
+        DeepHashItem::Blob(data) => {
+            let tag = sha384(format!("blob{}", data.len()).as_bytes());
+            sha384(&[tag, sha384(data)].concat())
+        }
+        DeepHashItem::List(items) => {
+            let mut acc = sha384(format!("list{}", items.len()).as_bytes());
+            for item in items {
+                acc = sha384(&[acc, deep_hash(item)].concat());
+            }
+            acc
+        }
+    }
+}
+
+/// One chunk of the transaction data, `min_byte_range..max_byte_range`
+#[derive(Debug)]
+pub struct Chunk {
+    pub data_hash: [u8; 32],
+    pub min_byte_range: usize,
+    pub max_byte_range: usize,
+}
+
+/// Inclusion proof of a chunk under the data root, posted with the chunk
+#[derive(Debug)]
+pub struct ChunkProof {
+    pub offset: usize,
+    pub proof: Vec<u8>,
+}
+
+/// Merkle tree of the transaction data, chunks and proofs in data order
+pub struct MerkleTree {
+    pub data_root: [u8; 32],
+    pub chunks: Vec<Chunk>,
+    pub proofs: Vec<ChunkProof>,
+}
+
+enum Node {
+    Leaf {
+        id: [u8; 32],
+        data_hash: [u8; 32],
+        max_byte_range: usize,
+    },
+    Branch {
+        id: [u8; 32],
+        byte_range: usize,
+        max_byte_range: usize,
+        left: Box<Node>,
+        right: Box<Node>,
+    },
+}
+
+impl Node {
+    fn id(&self) -> [u8; 32] {
+        match self {
+            Node::Leaf { id, .. } | Node::Branch { id, .. } => *id,
+        }
+    }
+
+    fn max_byte_range(&self) -> usize {
+        match self {
+            Node::Leaf { max_byte_range, .. } | Node::Branch { max_byte_range, .. } => {
+                *max_byte_range
+            }
+        }
+    }
+
+    fn branch(left: Node, right: Node) -> Node {
+        let byte_range = left.max_byte_range();
+        Node::Branch {
+            id: sha256(
+                &[
+                    sha256(&left.id()),
+                    sha256(&right.id()),
+                    sha256(&note(byte_range)),
+                ]
+                .concat(),
+            ),
+            byte_range,
+            max_byte_range: right.max_byte_range(),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// proofs of every leaf under the node, `path` being the proof down to it
+    fn proofs(&self, path: &[u8], proofs: &mut Vec<ChunkProof>) {
+        match self {
+            Node::Leaf {
+                data_hash,
+                max_byte_range,
+                ..
+            } => proofs.push(ChunkProof {
+                offset: max_byte_range.saturating_sub(1),
+                proof: [path, data_hash, &note(*max_byte_range)].concat(),
+            }),
+            Node::Branch {
+                byte_range,
+                left,
+                right,
+                ..
+            } => {
+                let path = [path, &left.id(), &right.id(), &note(*byte_range)].concat();
+                left.proofs(&path, proofs);
+                right.proofs(&path, proofs);
+            }
+        }
+    }
+}
+
+pub fn chunk_data(data: &[u8]) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut rest = data;
+    let mut cursor = 0;
+
+    while rest.len() >= MAX_CHUNK_SIZE {
+        let mut chunk_size = MAX_CHUNK_SIZE;
+        // never leave a last chunk under the minimum size
+        let next_chunk_size = rest.len() - MAX_CHUNK_SIZE;
+        if next_chunk_size > 0 && next_chunk_size < MIN_CHUNK_SIZE {
+            chunk_size = rest.len().div_ceil(2);
+        }
+
+        chunks.push(Chunk {
+            data_hash: sha256(&rest[..chunk_size]),
+            min_byte_range: cursor,
+            max_byte_range: cursor + chunk_size,
+        });
+        cursor += chunk_size;
+        rest = &rest[chunk_size..];
+    }
+
+    chunks.push(Chunk {
+        data_hash: sha256(rest),
+        min_byte_range: cursor,
+        max_byte_range: cursor + rest.len(),
+    });
+    chunks
+}
+
+pub fn merkle_tree(data: &[u8]) -> MerkleTree {
+    let chunks = chunk_data(data);
+    let mut layer: Vec<Node> = chunks
+        .iter()
+        .map(|chunk| Node::Leaf {
+            id: sha256(
+                &[
+                    sha256(&chunk.data_hash),
+                    sha256(&note(chunk.max_byte_range)),
+                ]
+                .concat(),
+            ),
+            data_hash: chunk.data_hash,
+            max_byte_range: chunk.max_byte_range,
+        })
+        .collect();
+
+    // pairs up each layer, an odd node out moves up unchanged
+    while layer.len() > 1 {
+        let mut next_layer: Vec<Node> = Vec::with_capacity(layer.len().div_ceil(2));
+        let mut nodes = layer.into_iter();
+        while let Some(left) = nodes.next() {
+            next_layer.push(match nodes.next() {
+                Some(right) => Node::branch(left, right),
+                None => left,
+            });
+        }
+        layer = next_layer;
+    }
+
+    let root = layer.pop().expect("chunk_data returns at least one chunk");
+    let mut proofs: Vec<ChunkProof> = Vec::with_capacity(chunks.len());
+    root.proofs(&[], &mut proofs);
+
+    // data of a multiple of the chunk size ends with an empty chunk, part of
+    // the data root but never uploaded
+    let mut chunks = chunks;
+    if chunks
+        .last()
+        .is_some_and(|chunk| chunk.max_byte_range == chunk.min_byte_range)
+    {
+        chunks.pop();
+        proofs.pop();
+    }
+
+    MerkleTree {
+        data_root: root.id(),
+        chunks,
+        proofs,
+    }
+}
+
+/// Format 2 transaction storing data, without transfer
+pub struct DataTransaction {
+    pub data_root: [u8; 32],
+    pub data_size: usize,
+    pub last_tx: Vec<u8>,
+    /// RSA modulus of the signing wallet
+    pub owner: Vec<u8>,
+    /// in winston
+    pub reward: String,
+    pub tags: Vec<(String, String)>,
+}
+
+impl DataTransaction {
+    /// deep hash of the transaction fields, the message the owner signs
+    pub fn signature_data(&self) -> [u8; 48] {
+        let blob = |bytes: &[u8]| DeepHashItem::Blob(bytes.to_vec());
+        deep_hash(&DeepHashItem::List(vec![
+            blob(b"2"),
+            blob(&self.owner),
+            // target
+            blob(&[]),
+            // quantity
+            blob(b"0"),
+            blob(self.reward.as_bytes()),
+            blob(&self.last_tx),
+            DeepHashItem::List(
+                self.tags
+                    .iter()
+                    .map(|(name, value)| {
+                        DeepHashItem::List(vec![blob(name.as_bytes()), blob(value.as_bytes())])
+                    })
+                    .collect(),
+            ),
+            blob(self.data_size.to_string().as_bytes()),
+            blob(&self.data_root),
+        ]))
+    }
+
+    /// JSON accepted by `POST /tx`. Without `data`, the data has to be
+    /// posted chunk by chunk
+    pub fn to_json(&self, signature: &[u8], data: Option<&[u8]>) -> Value {
+        json!({
+            "format": 2,
+            "id": transaction_id(signature),
+            "last_tx": b64url(&self.last_tx),
+            "owner": b64url(&self.owner),
+            "tags": self
+                .tags
+                .iter()
+                .map(|(name, value)| json!({
+                    "name": b64url(name.as_bytes()),
+                    "value": b64url(value.as_bytes()),
+                }))
+                .collect::<Vec<Value>>(),
+            "target": "",
+            "quantity": "0",
+            "data": data.map(b64url).unwrap_or_default(),
+            "data_size": self.data_size.to_string(),
+            "data_root": b64url(&self.data_root),
+            "reward": self.reward,
+            "signature": b64url(signature),
+        })
+    }
+}
+
+/// JSON accepted by `POST /chunk`
+pub fn chunk_json(tree: &MerkleTree, data: &[u8], index: usize) -> Value {
+    let chunk = &tree.chunks[index];
+    let proof = &tree.proofs[index];
+    json!({
+        "data_root": b64url(&tree.data_root),
+        "data_size": data.len().to_string(),
+        "data_path": b64url(&proof.proof),
+        "offset": proof.offset.to_string(),
+        "chunk": b64url(&data[chunk.min_byte_range..chunk.max_byte_range]),
+    })
+}
+
+pub fn transaction_id(signature: &[u8]) -> String {
+    b64url(&sha256(signature))
+}
+
+/// wallet address of an RSA modulus
+pub fn owner_address(owner: &[u8]) -> String {
+    b64url(&sha256(owner))
+}
+
+fn note(value: usize) -> [u8; NOTE_SIZE] {
+    let mut note = [0u8; NOTE_SIZE];
+    note[NOTE_SIZE - 8..].copy_from_slice(&(value as u64).to_be_bytes());
+    note
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn sha384(data: &[u8]) -> [u8; 48] {
+    Sha384::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_fixtures::fixture_bytes;
+
+    fn vectors() -> Value {
+        serde_json::from_slice(&fixture_bytes("arweave/vectors.json")).unwrap()
+    }
+
+    fn data_of(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn b64(value: &Value) -> Vec<u8> {
+        b64url_decode(value.as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn deep_hash_matches_vector() {
+        let blob = |bytes: &[u8]| DeepHashItem::Blob(bytes.to_vec());
+        let item = DeepHashItem::List(vec![
+            blob(b"hello"),
+            DeepHashItem::List(vec![blob(b""), blob(&[0, 1, 2])]),
+            blob(&[b'x'; 300]),
+        ]);
+
+        assert_eq!(deep_hash(&item).to_vec(), b64(&vectors()["deep_hash"]));
+    }
+
+    #[test]
+    fn merkle_tree_matches_vectors() {
+        let vectors = vectors();
+        // one chunk, an exact multiple of 256 KiB, a last chunk under 32 KiB
+        // balanced with the one before, and an uneven tree
+        for size in [
+            1000,
+            2 * MAX_CHUNK_SIZE,
+            MAX_CHUNK_SIZE + 10000,
+            3 * MAX_CHUNK_SIZE + 100000,
+        ] {
+            let vector = &vectors["merkle"][size.to_string()];
+            let tree = merkle_tree(&data_of(size));
+
+            assert_eq!(
+                tree.data_root.to_vec(),
+                b64(&vector["data_root"]),
+                "{}",
+                size
+            );
+            let chunks: Vec<(usize, usize, Vec<u8>)> = tree
+                .chunks
+                .iter()
+                .map(|chunk| {
+                    (
+                        chunk.min_byte_range,
+                        chunk.max_byte_range,
+                        chunk.data_hash.to_vec(),
+                    )
+                })
+                .collect();
+            let expected: Vec<(usize, usize, Vec<u8>)> = vector["chunks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|chunk| {
+                    (
+                        chunk[0].as_u64().unwrap() as usize,
+                        chunk[1].as_u64().unwrap() as usize,
+                        b64(&chunk[2]),
+                    )
+                })
+                .collect();
+            assert_eq!(chunks, expected, "{}", size);
+
+            let proofs: Vec<(usize, Vec<u8>)> = tree
+                .proofs
+                .iter()
+                .map(|proof| (proof.offset, proof.proof.clone()))
+                .collect();
+            let expected: Vec<(usize, Vec<u8>)> = vector["proofs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|proof| (proof[0].as_u64().unwrap() as usize, b64(&proof[1])))
+                .collect();
+            assert_eq!(proofs, expected, "{}", size);
+        }
+    }
+
+    #[test]
+    fn chunk_data_balances_a_small_last_chunk() {
+        let ranges = |size: usize| -> Vec<(usize, usize)> {
+            chunk_data(&data_of(size))
+                .iter()
+                .map(|chunk| (chunk.min_byte_range, chunk.max_byte_range))
+                .collect()
+        };
+
+        // an exact multiple ends with the empty chunk of the data root
+        assert_eq!(
+            ranges(2 * MAX_CHUNK_SIZE),
+            vec![
+                (0, MAX_CHUNK_SIZE),
+                (MAX_CHUNK_SIZE, 2 * MAX_CHUNK_SIZE),
+                (2 * MAX_CHUNK_SIZE, 2 * MAX_CHUNK_SIZE)
+            ]
+        );
+        let half = (MAX_CHUNK_SIZE + 10000) / 2;
+        assert_eq!(
+            ranges(MAX_CHUNK_SIZE + 10000),
+            vec![(0, half), (half, 2 * half)]
+        );
+    }
+
+    #[test]
+    fn signature_data_matches_vector() {
+        let vectors = vectors();
+        let vector = &vectors["transaction"];
+        let wallet: Value = serde_json::from_slice(&fixture_bytes("arweave/wallet.json")).unwrap();
+        let data = data_of(vector["data_size"].as_u64().unwrap() as usize);
+
+        let tx = DataTransaction {
+            data_root: merkle_tree(&data).data_root,
+            data_size: data.len(),
+            last_tx: b64(&vector["last_tx"]),
+            owner: b64(&wallet["n"]),
+            reward: vector["reward"].as_str().unwrap().to_string(),
+            tags: vector["tags"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|tag| {
+                    (
+                        tag[0].as_str().unwrap().to_string(),
+                        tag[1].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect(),
+        };
+
+        assert_eq!(tx.signature_data().to_vec(), b64(&vector["signature_data"]));
+        assert_eq!(owner_address(&tx.owner), vector["owner_address"]);
+        assert_eq!(transaction_id(&b64(&vector["signature"])), vector["id"]);
+    }
+}
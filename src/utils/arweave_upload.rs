@@ -1,31 +1,284 @@
-use crate::utils::constants::IRYS_UPLOADER_URL;
+use crate::utils::arweave_native::{ArweaveUploader, ArweaveWallet};
+use crate::utils::constants::{IRYS_GQL_GATEWAY, IRYS_UPLOADER_URL};
 use crate::utils::env_var::get_env_var;
+use crate::utils::local_uploader::LocalUploader;
+use crate::utils::upload_receipt::UploadReceipt;
+use async_trait::async_trait;
 use bundlr_sdk::{
+    currency::arweave::{Arweave, ArweaveBuilder},
+    currency::ethereum::{Ethereum, EthereumBuilder},
     currency::solana::{Solana, SolanaBuilder},
+    currency::Currency,
     tags::Tag,
-    Bundlr, BundlrBuilder,
+    Bundlr, BundlrBuilder, BundlrError,
 };
+use ethers::signers::{LocalWallet, Signer};
+use ethers::utils::hex;
 use eyre::eyre;
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::{OnceCell, RwLock};
 
+/// Destination of the data items. `ArweaveRequest` adds the protocol tags,
+/// implementations store the data with the given tags and return its id.
+#[async_trait]
+pub trait Uploader: Send + Sync {
+    /// short identifier, used in logs
+    fn name(&self) -> &str;
+
+    /// whether uploads stay off Arweave, in which case a backfill run must
+    /// not index or checkpoint anything
+    fn dry_run(&self) -> bool {
+        false
+    }
+
+    /// atomic unit of the funding currency, for logs
+    fn unit(&self) -> &str {
+        ""
+    }
+
+    /// cost of uploading `bytes` of data in one data item, in `unit`
+    async fn price(&self, _bytes: usize) -> eyre::Result<u128> {
+        eyre::Ok(0)
+    }
+
+    /// address signing the data items, the owner GQL indexes them under.
+    /// `None` for the backfiller address of the address book
+    fn owner(&self) -> Option<String> {
+        None
+    }
+
+    /// GQL gateway indexing the data items within seconds of their upload
+    fn gql_gateway(&self) -> &str {
+        IRYS_GQL_GATEWAY
+    }
+
+    /// funds available for uploads in `unit`, `None` when uploads are free
+    async fn balance(&self) -> eyre::Result<Option<u128>> {
+        eyre::Ok(None)
+    }
+
+    async fn upload(&self, data: Vec<u8>, tags: Vec<Tag>) -> eyre::Result<Upload>;
+
+    /// receipt of an earlier upload `id`, `None` when the uploader gives none
+    async fn receipt(&self, _id: &str) -> eyre::Result<Option<UploadReceipt>> {
+        eyre::Ok(None)
+    }
+
+    /// state to resume an earlier upload `id` found on GQL from, when not all
+    /// of its `size` bytes arrived. `None` by default, bundlers take the whole
+    /// data item in the upload request
+    async fn unconfirmed_state(&self, _id: &str, _size: usize) -> eyre::Result<Option<String>> {
+        eyre::Ok(None)
+    }
+
+    /// finishes an upload that failed with a `PartialUpload`, from its `state`
+    async fn resume(&self, _state: &str, _data: Vec<u8>) -> eyre::Result<Upload> {
+        Err(eyre!("{} uploads can't be resumed", self.name()))
+    }
+}
+
+/// An accepted data item, with the bundler receipt when the uploader gets one
 #[derive(Clone, Debug)]
+pub struct Upload {
+    pub id: String,
+    pub receipt: Option<UploadReceipt>,
+}
+
+/// An upload that may be paid for and accepted but whose data did not all
+/// arrive, e.g. a native transaction whose post failed or whose chunks were
+/// rejected. `Uploader::resume` finishes it
+/// from `state` instead of paying for a new one.
+#[derive(Debug)]
+pub struct PartialUpload {
+    pub id: String,
+    pub state: String,
+    pub message: String,
+}
+
+impl fmt::Display for PartialUpload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "upload {} is incomplete: {}", self.id, self.message)
+    }
+}
+
+impl std::error::Error for PartialUpload {}
+
+/// Builds the uploader named by the `uploader` env var: `irys` (default),
+/// `arweave` for native transactions from the `arweave_jwk` wallet, or
+/// `local` with an optional `=<directory>`, e.g. `local=./dry-run`, which
+/// defaults to `./backfill-uploads`
+pub fn uploader_from_env() -> Result<Arc<dyn Uploader>, anyhow::Error> {
+    let config = get_env_var("uploader").unwrap_or_else(|_| "irys".to_string());
+    let (name, dir) = match config.split_once('=') {
+        Some((name, dir)) => (name.trim(), Some(dir.trim())),
+        None => (config.trim(), None),
+    };
+
+    match name {
+        "irys" => Ok(Arc::new(UploaderProvider::new(
+            None,
+            IrysCurrency::from_env().map_err(|e| anyhow::Error::msg(e.to_string()))?,
+        ))),
+        "arweave" => Ok(Arc::new(ArweaveUploader::from_env()?)),
+        "local" => Ok(Arc::new(LocalUploader::new(
+            dir.unwrap_or("./backfill-uploads"),
+        )?)),
+        other => Err(anyhow::Error::msg(format!("unknown uploader: {}", other))),
+    }
+}
+
+/// Currency funding the Irys uploads, it decides the key format of `irys_pk`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IrysCurrency {
+    /// base58 keypair
+    Solana,
+    /// hex private key of an Ethereum wallet, paying in mainnet ETH
+    Ethereum,
+    /// path to a JWK wallet file
+    Arweave,
+}
+
+impl IrysCurrency {
+    /// `irys_currency` env var: `solana` (default), `ethereum` or `arweave`
+    pub fn from_env() -> eyre::Result<IrysCurrency> {
+        let currency = get_env_var("irys_currency").unwrap_or_else(|_| "solana".to_string());
+        match currency.trim() {
+            "solana" => eyre::Ok(IrysCurrency::Solana),
+            "ethereum" | "evm" => eyre::Ok(IrysCurrency::Ethereum),
+            "arweave" => eyre::Ok(IrysCurrency::Arweave),
+            other => Err(eyre!("invalid irys_currency value: {}", other)),
+        }
+    }
+
+    /// name of the currency in the Irys API
+    fn token(&self) -> &'static str {
+        match self {
+            IrysCurrency::Solana => "solana",
+            IrysCurrency::Ethereum => "ethereum",
+            IrysCurrency::Arweave => "arweave",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            IrysCurrency::Solana => "lamports",
+            IrysCurrency::Ethereum => "wei",
+            IrysCurrency::Arweave => "winston",
+        }
+    }
+
+    /// wallet address of `private_key`, in the key format of the currency
+    fn wallet_address(&self, private_key: &str) -> eyre::Result<String> {
+        match self {
+            IrysCurrency::Solana => {
+                // a keypair is the secret key followed by the public key
+                let keypair = bs58::decode(private_key.trim())
+                    .into_vec()
+                    .map_err(|e| eyre!("irys_pk is not a base58 keypair: {}", e))?;
+                if keypair.len() != 64 {
+                    return Err(eyre!("irys_pk is not a 64 bytes solana keypair"));
+                }
+                eyre::Ok(bs58::encode(&keypair[32..]).into_string())
+            }
+            IrysCurrency::Ethereum => {
+                let wallet = LocalWallet::from_str(&evm_private_key(private_key)?)?;
+                eyre::Ok(format!("{:?}", wallet.address()))
+            }
+            IrysCurrency::Arweave => eyre::Ok(read_arweave_wallet(private_key)?.address()),
+        }
+    }
+}
+
+/// bundler client funded in one of the supported currencies
+enum IrysClient {
+    Solana(Bundlr<Solana>),
+    Ethereum(Bundlr<Ethereum>),
+    Arweave(Bundlr<Arweave>),
+}
+
+impl IrysClient {
+    async fn send_data_item(&self, data: Vec<u8>, tags: Vec<Tag>) -> eyre::Result<Value> {
+        match self {
+            IrysClient::Solana(bundlr) => send_data_item(bundlr, data, tags).await,
+            IrysClient::Ethereum(bundlr) => send_data_item(bundlr, data, tags).await,
+            IrysClient::Arweave(bundlr) => send_data_item(bundlr, data, tags).await,
+        }
+    }
+}
+
+/// Uploads data items through a single Irys bundler client, built on first
+/// use and shared by every clone of the provider.
+#[derive(Clone)]
 pub struct UploaderProvider {
+    bundlr: Arc<RwLock<Option<Arc<IrysClient>>>>,
+    /// bundler key signing the upload receipts
+    bundler_public: Arc<OnceCell<String>>,
+    currency: IrysCurrency,
     private_key: Option<String>,
 }
 
-async fn init_bundlr(private_key: Option<String>) -> eyre::Result<Bundlr<Solana>> {
-    let irys_wallet_pk: String = get_env_var("irys_pk").unwrap_or_else(|e| private_key.unwrap());
-    let url = Url::parse(IRYS_UPLOADER_URL).unwrap();
-
-    let currency = SolanaBuilder::new()
-        .wallet(&irys_wallet_pk)
-        .build()
-        .map_err(|e| {
-            eyre::eyre!(
-                "failed to initialize irys provider, failed to create bundlr wallet instance: {}",
-                e
+// the private key stays out of logs
+impl fmt::Debug for UploaderProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploaderProvider")
+            .field("currency", &self.currency)
+            .field(
+                "private_key",
+                &self.private_key.as_ref().map(|_| "<redacted>"),
             )
-        })?;
+            .finish_non_exhaustive()
+    }
+}
+
+fn irys_private_key(private_key: Option<String>) -> eyre::Result<String> {
+    get_env_var("irys_pk")
+        .ok()
+        .or(private_key)
+        .ok_or_else(|| eyre!("irys_pk is not set"))
+}
+
+async fn init_bundlr(
+    currency: IrysCurrency,
+    private_key: Option<String>,
+) -> eyre::Result<IrysClient> {
+    let irys_wallet_pk = irys_private_key(private_key)?;
+
+    let client = match currency {
+        IrysCurrency::Solana => {
+            let currency = SolanaBuilder::new()
+                .wallet(&irys_wallet_pk)
+                .build()
+                .map_err(wallet_error)?;
+            IrysClient::Solana(build_bundlr(currency).await?)
+        }
+        IrysCurrency::Ethereum => {
+            let currency = EthereumBuilder::new()
+                .wallet(&evm_private_key(&irys_wallet_pk)?)
+                .build()
+                .map_err(wallet_error)?;
+            IrysClient::Ethereum(build_bundlr(currency).await?)
+        }
+        IrysCurrency::Arweave => {
+            // fail on an unreadable wallet here rather than at signing time
+            read_arweave_wallet(&irys_wallet_pk)?;
+            let currency = ArweaveBuilder::new()
+                .keypair_path(PathBuf::from(&irys_wallet_pk))
+                .build()
+                .map_err(wallet_error)?;
+            IrysClient::Arweave(build_bundlr(currency).await?)
+        }
+    };
+
+    Ok(client)
+}
+
+async fn build_bundlr<C: Currency>(currency: C) -> eyre::Result<Bundlr<C>> {
+    let url = Url::parse(IRYS_UPLOADER_URL)?;
 
     let bundlr = BundlrBuilder::new()
         .url(url)
@@ -39,46 +292,257 @@ async fn init_bundlr(private_key: Option<String>) -> eyre::Result<Bundlr<Solana>
     Ok(bundlr)
 }
 
+fn read_arweave_wallet(path: &str) -> eyre::Result<ArweaveWallet> {
+    let jwk = std::fs::read_to_string(path)
+        .map_err(|e| eyre!("failed to read arweave wallet {}: {}", path, e))?;
+    ArweaveWallet::from_jwk(&jwk).map_err(|e| eyre!("{}", e))
+}
+
+fn wallet_error(e: impl std::fmt::Display) -> eyre::Report {
+    eyre!(
+        "failed to initialize irys provider, failed to create bundlr wallet instance: {}",
+        e
+    )
+}
+
+/// 32 bytes hex private key, with or without the `0x` prefix
+fn evm_private_key(key: &str) -> eyre::Result<String> {
+    let key = key.trim();
+    let key = key.strip_prefix("0x").unwrap_or(key);
+    match hex::decode(key) {
+        Ok(bytes) if bytes.len() == 32 => eyre::Ok(key.to_lowercase()),
+        _ => Err(eyre!("irys_pk is not a 32 bytes hex private key")),
+    }
+}
+
 impl UploaderProvider {
-    pub fn new(private_key: Option<String>) -> UploaderProvider {
-        UploaderProvider { private_key }
+    pub fn new(private_key: Option<String>, currency: IrysCurrency) -> UploaderProvider {
+        UploaderProvider {
+            bundlr: Arc::new(RwLock::new(None)),
+            bundler_public: Arc::new(OnceCell::new()),
+            currency,
+            private_key,
+        }
     }
 
-    pub async fn upload_data(&self, data: Vec<u8>, param_tags: Vec<Tag>) -> eyre::Result<String> {
-        let mut tags = vec![
-            Tag::new("Protocol", "WeaveVM-ExEx"),
-            Tag::new("ExEx-Type", "Arweave-Data-Uploader"),
-        ];
+    /// the shared bundlr client, initialized if there is none yet
+    async fn bundlr(&self) -> eyre::Result<Arc<IrysClient>> {
+        if let Some(bundlr) = self.bundlr.read().await.as_ref() {
+            return eyre::Ok(bundlr.clone());
+        }
+
+        let mut cached = self.bundlr.write().await;
+        // another upload may have initialized it while this one waited
+        if let Some(bundlr) = cached.as_ref() {
+            return eyre::Ok(bundlr.clone());
+        }
+        let bundlr = Arc::new(
+            init_bundlr(self.currency, self.private_key.clone())
+                .await
+                .map_err(|e| eyre!("failed to initialize bundlr: {}", e))?,
+        );
+        *cached = Some(bundlr.clone());
+
+        eyre::Ok(bundlr)
+    }
+
+    async fn bundler_public(&self) -> eyre::Result<&str> {
+        let public = self
+            .bundler_public
+            .get_or_try_init(|| async {
+                let url = format!("{}/public", IRYS_UPLOADER_URL);
+                let public = reqwest::get(&url).await?.error_for_status()?.text().await?;
+                eyre::Ok(public.trim().to_string())
+            })
+            .await?;
+
+        eyre::Ok(public.as_str())
+    }
+
+    /// receipt of an upload response, kept only when signed by the bundler
+    async fn verified_receipt(&self, response: &Value) -> Option<UploadReceipt> {
+        let receipt = UploadReceipt::from_response(response)?;
+        let verified = match self.bundler_public().await {
+            Ok(bundler_public) => receipt.verify(bundler_public).map_err(|e| e.to_string()),
+            Err(e) => Err(format!("failed to fetch the bundler public key: {}", e)),
+        };
+
+        match verified {
+            Ok(()) => Some(receipt),
+            Err(e) => {
+                eprintln!("discarding receipt of {}: {}", receipt.id, e);
+                None
+            }
+        }
+    }
+
+    /// drops `stale` so the next upload builds a new client, unless another
+    /// upload already replaced it
+    async fn invalidate(&self, stale: &Arc<IrysClient>) {
+        let mut cached = self.bundlr.write().await;
+        if cached
+            .as_ref()
+            .is_some_and(|bundlr| Arc::ptr_eq(bundlr, stale))
+        {
+            *cached = None;
+        }
+    }
+}
+
+#[async_trait]
+impl Uploader for UploaderProvider {
+    fn name(&self) -> &str {
+        "irys"
+    }
 
-        tags.extend(param_tags);
+    fn unit(&self) -> &str {
+        self.currency.unit()
+    }
 
-        let bundlr = init_bundlr(self.private_key.clone())
-            .await
-            .map_err(|e| eyre!("failed to initialize bundlr: {}", e))?;
+    /// the `irys_pk` wallet signs the data items, whatever its currency
+    fn owner(&self) -> Option<String> {
+        let private_key = irys_private_key(self.private_key.clone()).ok()?;
+        self.currency.wallet_address(&private_key).ok()
+    }
 
-        let mut tx = bundlr
-            .create_transaction(data, tags)
-            .map_err(|e| eyre!("failed to create transaction: {}", e))?;
+    async fn price(&self, bytes: usize) -> eyre::Result<u128> {
+        let url = format!(
+            "{}/price/{}/{}",
+            IRYS_UPLOADER_URL,
+            self.currency.token(),
+            bytes
+        );
+        let price = reqwest::get(&url).await?.error_for_status()?.text().await?;
+        price
+            .trim()
+            .parse::<u128>()
+            .map_err(|_| eyre!("invalid irys price: {}", price))
+    }
 
-        bundlr
-            .sign_transaction(&mut tx)
-            .await
-            .map_err(|e| eyre!("failed to sign transaction: {}", e))?;
+    async fn balance(&self) -> eyre::Result<Option<u128>> {
+        let address = self
+            .currency
+            .wallet_address(&irys_private_key(self.private_key.clone())?)?;
+        let url = format!(
+            "{}/account/balance/{}?address={}",
+            IRYS_UPLOADER_URL,
+            self.currency.token(),
+            address
+        );
+        let res: Value = reqwest::get(&url).await?.error_for_status()?.json().await?;
+        // the balance is a decimal string
+        let balance = match &res["balance"] {
+            Value::String(balance) => balance.parse::<u128>().ok(),
+            balance => balance.as_u64().map(u128::from),
+        };
+
+        balance
+            .map(Some)
+            .ok_or_else(|| eyre!("invalid irys balance response: {}", res))
+    }
 
-        let result = bundlr
-            .send_transaction(tx)
-            .await
-            .map_err(|e| eyre!("failed to send transaction: {}", e))?;
+    async fn upload(&self, data: Vec<u8>, tags: Vec<Tag>) -> eyre::Result<Upload> {
+        let bundlr = self.bundlr().await?;
+        let response = match bundlr.send_data_item(data.clone(), tags.clone()).await {
+            Err(e) if is_stale_client_error(&e) => {
+                // rejected by the bundler with the current wallet or public
+                // info, retry once with a freshly initialized client
+                eprintln!("refreshing bundlr client after: {}", e);
+                self.invalidate(&bundlr).await;
+                let bundlr = self.bundlr().await?;
+                bundlr.send_data_item(data, tags).await?
+            }
+            result => result?,
+        };
 
-        let id = result["id"]
+        let id = response["id"]
             .as_str()
             .ok_or_else(|| eyre!("missing 'id' field in response"))?
             .to_string();
+        let receipt = self.verified_receipt(&response).await;
 
-        eyre::Ok(id)
+        eyre::Ok(Upload { id, receipt })
+    }
+
+    /// the receipt the bundler keeps for every data item it accepted
+    async fn receipt(&self, id: &str) -> eyre::Result<Option<UploadReceipt>> {
+        let url = format!("{}/tx/{}/receipt", IRYS_UPLOADER_URL, id);
+        let res = reqwest::get(&url).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return eyre::Ok(None);
+        }
+        let response: Value = res.error_for_status()?.json().await?;
+
+        eyre::Ok(self.verified_receipt(&response).await)
     }
 }
 
+async fn send_data_item<C: Currency>(
+    bundlr: &Bundlr<C>,
+    data: Vec<u8>,
+    tags: Vec<Tag>,
+) -> eyre::Result<Value> {
+    let mut tx = bundlr
+        .create_transaction(data, tags)
+        .map_err(|e| eyre!("failed to create transaction: {}", e))?;
+
+    bundlr
+        .sign_transaction(&mut tx)
+        .await
+        .map_err(|e| eyre!("failed to sign transaction: {}", e))?;
+
+    bundlr
+        .send_transaction(tx)
+        .await
+        .map_err(|e| match rejection_status(&e) {
+            Some(status) => eyre::Report::new(BundlerRejection {
+                status,
+                message: e.to_string(),
+            }),
+            None => eyre!("failed to send transaction: {}", e),
+        })
+}
+
+/// A data item the bundler answered with an error status
+#[derive(Debug)]
+struct BundlerRejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl fmt::Display for BundlerRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bundler rejected the transaction: {}", self.message)
+    }
+}
+
+impl std::error::Error for BundlerRejection {}
+
+/// HTTP status of a bundler response error, which the SDK reports as
+/// `ResponseError("Status: <code> <reason>[:<body>]")`
+fn rejection_status(error: &BundlrError) -> Option<StatusCode> {
+    match error {
+        BundlrError::ResponseError(message) => {
+            let code = message.strip_prefix("Status: ")?.get(..3)?;
+            StatusCode::from_u16(code.parse().ok()?).ok()
+        }
+        _ => None,
+    }
+}
+
+/// whether the bundler rejected the upload because of the client itself,
+/// i.e. answered 401 or 403. Other failures keep the client
+fn is_stale_client_error(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<BundlerRejection>()
+        .is_some_and(|rejection| {
+            matches!(
+                rejection.status,
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+            )
+        })
+}
+
 #[derive(Clone, Debug)]
 pub struct ArweaveRequest {
     tags: Vec<Tag>,
@@ -100,6 +564,10 @@ impl ArweaveRequest {
         self
     }
 
+    pub fn data_size(&self) -> usize {
+        self.data.len()
+    }
+
     pub fn set_data(&mut self, data: Vec<u8>) -> &mut ArweaveRequest {
         self.data = data;
         self
@@ -111,13 +579,73 @@ impl ArweaveRequest {
     }
 
     pub async fn send(&self) -> eyre::Result<String> {
-        let provider = UploaderProvider::new(self.private_key.clone());
-        self.send_with_provider(&provider).await
+        let provider = UploaderProvider::new(self.private_key.clone(), IrysCurrency::from_env()?);
+        self.send_with(&provider).await.map(|upload| upload.id)
     }
 
-    pub async fn send_with_provider(&self, provider: &UploaderProvider) -> eyre::Result<String> {
-        provider
-            .upload_data(self.data.clone(), self.tags.clone())
-            .await
+    pub async fn send_with(&self, uploader: &dyn Uploader) -> eyre::Result<Upload> {
+        let mut tags = vec![
+            Tag::new("Protocol", "WeaveVM-ExEx"),
+            Tag::new("ExEx-Type", "Arweave-Data-Uploader"),
+        ];
+        tags.extend(self.tags.iter().cloned());
+
+        uploader.upload(self.data.clone(), tags).await
+    }
+
+    /// finishes the upload of this request that failed with a `PartialUpload`
+    pub async fn resume_with(&self, uploader: &dyn Uploader, state: &str) -> eyre::Result<Upload> {
+        uploader.resume(state, self.data.clone()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(message: &str) -> eyre::Report {
+        let error = BundlrError::ResponseError(message.to_string());
+        eyre::Report::new(BundlerRejection {
+            status: rejection_status(&error).unwrap(),
+            message: error.to_string(),
+        })
+    }
+
+    #[test]
+    fn only_auth_statuses_are_stale() {
+        assert!(is_stale_client_error(&rejection(
+            "Status: 401 Unauthorized"
+        )));
+        assert!(is_stale_client_error(&rejection(
+            "Status: 403 Forbidden:invalid signature"
+        )));
+        assert!(!is_stale_client_error(&rejection(
+            "Status: 402 Payment Required:not enough balance for signature 401"
+        )));
+        assert!(!is_stale_client_error(&eyre!("failed to send: 401")));
+    }
+
+    #[test]
+    fn debug_hides_the_private_key() {
+        let provider = UploaderProvider::new(Some("secret".to_string()), IrysCurrency::Solana);
+
+        assert!(!format!("{:?}", provider).contains("secret"));
+    }
+
+    #[test]
+    fn owner_is_the_signing_wallet() {
+        let key = format!("0x{}", "00".repeat(31) + "01");
+        let provider = UploaderProvider::new(Some(key), IrysCurrency::Ethereum);
+        assert_eq!(
+            provider.owner().as_deref(),
+            Some("0x7e5f4552091a69125d5dfcb7b8c2659029395bdf")
+        );
+
+        let keypair = bs58::encode([[1u8; 32], [2u8; 32]].concat()).into_string();
+        let provider = UploaderProvider::new(Some(keypair), IrysCurrency::Solana);
+        assert_eq!(
+            provider.owner(),
+            Some(bs58::encode([2u8; 32]).into_string())
+        );
     }
 }
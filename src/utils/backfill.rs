@@ -1,44 +1,278 @@
-use crate::utils::arweave_gql::detect_missing_blocks;
-use crate::utils::arweave_upload::{ArweaveRequest, UploaderProvider};
-use crate::utils::block::Block;
-use crate::utils::constants::{RETH_CLIENT_VERSION, WVM_NETWORK_TAG};
-use crate::utils::planetscale::{ps_init, ps_insert_block};
-use crate::utils::wvm_client::{block_hex_to_decimal, retrieve_block_with_txs};
+use crate::utils::archive_encoding::ArchiveEncoding;
+use crate::utils::archive_envelope::CURRENT_SCHEMA_VERSION;
+use crate::utils::archive_verifier::VerifyMode;
+use crate::utils::arweave_gql::{
+    archive_owners, detect_missing_blocks, scan_keys, GapScan, ScanCheckpoint,
+};
+use crate::utils::arweave_upload::uploader_from_env;
+use crate::utils::backfill_pipeline::{run_pipeline, ArchiveSettings, PipelineConfig};
+use crate::utils::block_ranges::BlockRanges;
+use crate::utils::compression::compressor_from_env;
+use crate::utils::env_var::{get_env_flag, get_env_var};
+use crate::utils::gql_sources::gql_sources_from_env;
+use crate::utils::planetscale::{
+    ps_get_checkpoints, ps_init, ps_insert_conflict, ps_upsert_checkpoint,
+};
+use crate::utils::retry_queue::RetryQueue;
+use crate::utils::upload_budget::preflight;
+use crate::utils::wvm_client::RpcBlockSource;
 use anyhow::{Error, Ok};
+use planetscale_driver::PSConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::sleep;
 
 pub async fn backfill_blocks(scan_count: u32) -> Result<(), Error> {
-    let ar_uploader_provider = UploaderProvider::new(None);
-    let missed_blocks = detect_missing_blocks(scan_count).await.unwrap();
-    // planetscale connection
-    let conn = ps_init().await;
-
-    for block_number in missed_blocks.iter() {
-        let wvm_block = retrieve_block_with_txs(*block_number).await;
-        let block_number_hex: &str = wvm_block.number.as_ref().unwrap();
-        let block_number = block_hex_to_decimal(block_number_hex.as_ref());
-        let block_hash = wvm_block.hash.as_ref().unwrap().as_str();
-        let borsh_block = Block::borsh_ser(&wvm_block);
-        let borsh_brotli = Block::brotli_compress(&borsh_block);
-
-        let arweave_id = ArweaveRequest::new()
-            .set_tag("Content-Type", "application/octet-stream")
-            .set_tag("WeaveVM:Encoding", "Borsh-Brotli")
-            .set_tag("Block-Number", block_number.as_str())
-            .set_tag("Block-Hash", block_hash)
-            .set_tag("Client-Version", RETH_CLIENT_VERSION)
-            .set_tag("Network", WVM_NETWORK_TAG)
-            .set_tag("WeaveVM:Backfill", "true")
-            .set_data(borsh_brotli)
-            .send_with_provider(&ar_uploader_provider)
-            .await
-            .unwrap();
-
-        println!("\n\nARWEAVE TXID: {}\n\n", arweave_id);
-        let parsed_block_number = block_number.parse::<u64>()?;
-        ps_insert_block(&conn, parsed_block_number, block_hash, &arweave_id)
-            .await
-            .unwrap();
+    let uploader = uploader_from_env()?;
+    let dry_run = uploader.dry_run();
+    println!(
+        "uploading with {}{}",
+        uploader.name(),
+        if dry_run { " (dry run)" } else { "" }
+    );
+    // dry runs read PlanetScale when it is configured but never write to it
+    let conn = match ps_init().await {
+        Result::Ok(conn) => Some(Arc::new(conn)),
+        Err(e) if dry_run => {
+            println!("dry run without PlanetScale: {}", e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let writer = conn.clone().filter(|_| !dry_run);
+    let mut checkpoint = match conn.as_ref() {
+        Some(conn) => ScanCheckpoint::from_rows(ps_get_checkpoints(conn).await?),
+        None => ScanCheckpoint::default(),
+    };
+    let gql_sources = gql_sources_from_env()?;
+    // archives uploaded from another wallet than the address book backfiller
+    // are found under its own owner
+    let owners = archive_owners(uploader.owner());
+    let scan_keys = scan_keys(&gql_sources, &owners);
+    let verify_mode = VerifyMode::from_env()?;
+    // deep verifying already checkpointed blocks needs their archives again
+    if verify_mode.needs_full_scan(checkpoint.next_block()) {
+        println!("verification mode {:?} rescans from genesis", verify_mode);
+        checkpoint = ScanCheckpoint::default();
+    }
+    // a failed or partial scan aborts the run before anything is uploaded
+    let gap_scan =
+        detect_missing_blocks(&gql_sources, &owners, scan_count, &checkpoint, &verify_mode).await?;
+
+    if let Some(conn) = writer.as_ref() {
+        for conflict in gap_scan.non_canonical.iter() {
+            ps_insert_conflict(
+                conn,
+                conflict.archive.block_number as u64,
+                &conflict.archive.arweave_id,
+                conflict.archive.block_hash.as_deref().unwrap_or_default(),
+                &conflict.canonical_hash,
+            )
+            .await?;
+        }
+    }
+
+    // a scan cut short by its page limit only saves how far it got, the next
+    // run resumes from its cursors before anything is uploaded
+    if !gap_scan.complete {
+        if let Some(conn) = writer.as_ref() {
+            let mut cursors = checkpoint.cursors.clone();
+            cursors.extend(gap_scan.cursors.clone());
+            save_checkpoint(
+                conn,
+                checkpoint.block_number,
+                &scan_keys,
+                &cursors,
+                &gap_scan.archived,
+            )
+            .await?;
+        }
+        println!("gap scan incomplete, continuing it on the next run");
+        return Ok(());
+    }
+
+    // everything under the first gap is verified, checkpoint it together with
+    // the previous cursors so a failed run resumes from the same scan position
+    if let Some(first_missing) = gap_scan.missing.ranges().first().map(|range| range[0]) {
+        if let Some(conn) = writer
+            .as_ref()
+            .filter(|_| first_missing > checkpoint.next_block())
+        {
+            save_checkpoint(
+                conn,
+                Some(first_missing - 1),
+                &scan_keys,
+                &checkpoint.cursors,
+                &checkpoint.archived,
+            )
+            .await?;
+        }
+    }
+
+    let settings = Arc::new(ArchiveSettings {
+        blocks: Arc::new(RpcBlockSource),
+        compressor: Arc::from(compressor_from_env(dry_run)?),
+        encoding: ArchiveEncoding::from_env(get_env_flag("archive_receipts"))?,
+        encoding_version: CURRENT_SCHEMA_VERSION.to_string(),
+        range_size: archive_range_size()?,
+    });
+    let pipeline_config = PipelineConfig::from_env()?;
+    let mut retry_queue = match conn.as_ref() {
+        Some(conn) => RetryQueue::load(conn, dry_run).await?,
+        None => RetryQueue::empty()?,
+    };
+
+    // queued blocks the scan found archived (e.g. by the ExEx) are done
+    let scanned_archived = gap_scan
+        .missing
+        .complement(checkpoint.next_block(), gap_scan.latest_block);
+    if let Some(conn) = writer.as_ref() {
+        retry_queue.clear(conn, &scanned_archived).await?;
+    }
+
+    // the gaps plus the queued blocks that are due, without dead letters and
+    // blocks still backing off
+    let held_back = retry_queue.held_back();
+    let mut blocks = retry_queue.due();
+    for range in gap_scan.missing.ranges() {
+        blocks.extend(&held_back.complement(range[0], range[1]));
+    }
+
+    // refuses the run when it can't be paid for
+    let ranges = blocks.chunks(settings.range_size);
+    let preflight = preflight(&ranges, settings.clone(), uploader.as_ref()).await?;
+    let budget = Arc::new(preflight.budget);
+
+    let report = run_pipeline(
+        ranges,
+        preflight.encoded,
+        settings.clone(),
+        &pipeline_config,
+        uploader.clone(),
+        writer.clone(),
+        budget.clone(),
+    )
+    .await;
+    println!("spent {} {}", budget.spent(), uploader.unit());
+    let conn = match writer {
+        Some(conn) => conn,
+        None => return Ok(()),
+    };
+    retry_queue.record(&conn, &report).await?;
+
+    // the blocks left over budget are only found again by rescanning their gap
+    if !report.unspent.is_empty() {
+        println!(
+            "spend budget exhausted, {} blocks left for the next run",
+            report.unspent.block_count()
+        );
+        return Ok(());
+    }
+
+    // failed blocks are tracked by the retry queue from here on, every gap up
+    // to the scanned chain tip is archived or queued
+    complete_checkpoint(&conn, &scan_keys, &checkpoint, &gap_scan).await?;
+
+    // retries due within `retry_max_wait_secs` are drained before the run
+    // ends, later ones wait for the next run
+    while let Some(delay) = retry_queue.next_due_in() {
+        println!("next block retry in {:?}", delay);
+        sleep(delay).await;
+
+        let report = run_pipeline(
+            retry_queue.due().chunks(settings.range_size),
+            HashMap::new(),
+            settings.clone(),
+            &pipeline_config,
+            uploader.clone(),
+            Some(conn.clone()),
+            budget.clone(),
+        )
+        .await;
+        retry_queue.record(&conn, &report).await?;
+        if budget.is_exhausted() {
+            println!("spend budget exhausted, leaving the queued retries for the next run");
+            break;
+        }
     }
 
     Ok(())
 }
+
+/// `archive_range_size` env var, the number of consecutive blocks packed in
+/// one range archive. Defaults to 1, a data item per block
+fn archive_range_size() -> Result<u32, Error> {
+    let size = match get_env_var("archive_range_size").ok() {
+        Some(size) => size,
+        None => return Ok(1),
+    };
+
+    match size.trim().parse::<u32>().ok() {
+        Some(size) if size > 0 => Ok(size),
+        _ => Err(Error::msg(format!(
+            "invalid archive_range_size value: {}",
+            size
+        ))),
+    }
+}
+
+async fn complete_checkpoint(
+    conn: &PSConnection,
+    scan_keys: &[String],
+    checkpoint: &ScanCheckpoint,
+    gap_scan: &GapScan,
+) -> Result<(), Error> {
+    let checkpoint = checkpoint.after_complete_scan(gap_scan);
+    save_checkpoint(
+        conn,
+        checkpoint.block_number,
+        scan_keys,
+        &checkpoint.cursors,
+        &checkpoint.archived,
+    )
+    .await
+}
+
+/// writes a row per scanned source/owner, with an empty cursor for those
+/// without archives yet, so the block height is kept even on a first run.
+/// Only the `archived` blocks above `block_number` still need to be kept
+async fn save_checkpoint(
+    conn: &PSConnection,
+    block_number: Option<u32>,
+    scan_keys: &[String],
+    cursors: &HashMap<String, String>,
+    archived: &HashMap<String, BlockRanges>,
+) -> Result<(), Error> {
+    let next_block = block_number.map_or(0, |block| block as u64 + 1);
+    for scan_key in scan_keys.iter() {
+        let cursor = cursors
+            .get(scan_key)
+            .map(String::as_str)
+            .unwrap_or_default();
+        ps_upsert_checkpoint(
+            conn,
+            scan_key,
+            block_number.map_or(-1, i64::from),
+            cursor,
+            &archived_above(archived.get(scan_key), next_block)?,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// JSON of the `archived` ranges from `next_block` on, empty if there are none
+fn archived_above(archived: Option<&BlockRanges>, next_block: u64) -> Result<String, Error> {
+    let mut above = BlockRanges::new();
+    for range in archived.map(BlockRanges::ranges).unwrap_or_default() {
+        if range[1] as u64 >= next_block {
+            above.insert_range((range[0] as u64).max(next_block) as u32, range[1]);
+        }
+    }
+
+    if above.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(serde_json::to_string(&above)?)
+}
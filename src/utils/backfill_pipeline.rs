@@ -0,0 +1,689 @@
+use crate::utils::archive_encoding::ArchiveEncoding;
+use crate::utils::archive_range::seal_range;
+use crate::utils::arweave_upload::{ArweaveRequest, Uploader};
+use crate::utils::block::{Block, Receipt};
+use crate::utils::block_integrity::{verify_block_integrity, verify_receipts_root};
+use crate::utils::block_ranges::{BlockRange, BlockRanges};
+use crate::utils::compression::Compressor;
+use crate::utils::constants::{RETH_CLIENT_VERSION, WVM_NETWORK_TAG};
+use crate::utils::env_var::get_env_var;
+use crate::utils::planetscale::ps_insert_block;
+use crate::utils::upload_budget::{send_within_budget, BudgetExhausted, SpendBudget};
+use crate::utils::upload_jobs::{upload_once, UploadKey};
+use crate::utils::wvm_client::BlockSource;
+use anyhow::Error;
+use planetscale_driver::PSConnection;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+/// Workers per pipeline stage, and capacity of the channels between stages.
+/// A full channel blocks the stage feeding it, so at most
+/// `workers + channel_capacity` ranges wait on a slow stage.
+#[derive(Debug)]
+pub struct PipelineConfig {
+    pub channel_capacity: usize,
+    pub encode_workers: usize,
+    pub fetch_workers: usize,
+    pub index_workers: usize,
+    pub upload_workers: usize,
+}
+
+impl PipelineConfig {
+    /// `pipeline_channel_capacity`, `fetch_concurrency`, `encode_concurrency`,
+    /// `upload_concurrency` and `index_concurrency` env vars
+    pub fn from_env() -> Result<PipelineConfig, Error> {
+        let cpus = std::thread::available_parallelism()
+            .map(|cpus| cpus.get())
+            .unwrap_or(4);
+
+        Ok(PipelineConfig {
+            channel_capacity: env_count("pipeline_channel_capacity", 16)?,
+            encode_workers: env_count("encode_concurrency", cpus)?,
+            fetch_workers: env_count("fetch_concurrency", 8)?,
+            index_workers: env_count("index_concurrency", 4)?,
+            upload_workers: env_count("upload_concurrency", 4)?,
+        })
+    }
+}
+
+fn env_count(key: &str, default: usize) -> Result<usize, Error> {
+    let value = match get_env_var(key).ok() {
+        Some(value) => value,
+        None => return Ok(default),
+    };
+
+    match value.trim().parse::<usize>().ok() {
+        Some(count) if count > 0 => Ok(count),
+        _ => Err(Error::msg(format!("invalid {} value: {}", key, value))),
+    }
+}
+
+/// How the archives of a run are written, shared by the pipeline workers
+pub struct ArchiveSettings {
+    /// where the archived blocks are read from
+    pub blocks: Arc<dyn BlockSource>,
+    pub compressor: Arc<dyn Compressor>,
+    pub encoding: ArchiveEncoding,
+    pub encoding_version: String,
+    /// blocks per data item, range archives above 1
+    pub range_size: u32,
+}
+
+/// Data item of a range, with the hashes of its blocks for the index
+pub struct EncodedRange {
+    block_hashes: Vec<String>,
+    key: UploadKey,
+    request: ArweaveRequest,
+}
+
+impl EncodedRange {
+    /// size of the data item, as it is uploaded
+    pub fn data_size(&self) -> usize {
+        self.request.data_size()
+    }
+}
+
+struct UploadedRange {
+    arweave_id: String,
+    block_hashes: Vec<String>,
+}
+
+/// Blocks a pipeline run archived and indexed, the ranges it did not with
+/// the error that stopped them, and the blocks skipped once the spend budget
+/// ran out
+#[derive(Debug, Default)]
+pub struct PipelineReport {
+    pub archived: BlockRanges,
+    pub failures: Vec<(BlockRange, String)>,
+    pub unspent: BlockRanges,
+}
+
+impl PipelineReport {
+    pub fn failed(&self) -> BlockRanges {
+        let mut failed = BlockRanges::new();
+        for (range, _) in self.failures.iter() {
+            failed.insert_range(range[0], range[1]);
+        }
+        failed
+    }
+}
+
+/// Archives `ranges` (one data item each) through the staged pipeline
+/// `fetch -> encode -> upload -> index`. The ranges already `encoded`, e.g.
+/// by the cost preflight, start at the upload stage. A range failing at any
+/// stage is logged and dropped, the others carry on. Dry runs write nothing
+/// to PlanetScale and may run without a connection.
+pub async fn run_pipeline(
+    ranges: Vec<BlockRange>,
+    mut encoded: HashMap<BlockRange, EncodedRange>,
+    settings: Arc<ArchiveSettings>,
+    config: &PipelineConfig,
+    uploader: Arc<dyn Uploader>,
+    conn: Option<Arc<PSConnection>>,
+    budget: Arc<SpendBudget>,
+) -> PipelineReport {
+    let started = Instant::now();
+    let report = Arc::new(Mutex::new(PipelineReport::default()));
+
+    let (range_tx, range_rx) = mpsc::channel::<(BlockRange, ())>(config.channel_capacity);
+    let (fetched_tx, fetched_rx) = mpsc::channel(config.channel_capacity);
+    let (encoded_tx, encoded_rx) = mpsc::channel(config.channel_capacity);
+    let presampled_tx = encoded_tx.clone();
+    let (uploaded_tx, uploaded_rx) = mpsc::channel(config.channel_capacity);
+
+    let fetch_settings = settings.clone();
+    let dry_run = uploader.dry_run();
+    let upload_conn = conn.clone();
+    let fetch_budget = budget.clone();
+    let stages = [
+        tokio::spawn(run_stage(
+            "fetch",
+            config.fetch_workers,
+            range_rx,
+            Some(fetched_tx),
+            report.clone(),
+            move |range, ()| {
+                // no point fetching blocks that can't be paid for
+                let exhausted = fetch_budget.is_exhausted();
+                let settings = fetch_settings.clone();
+                async move {
+                    if exhausted {
+                        return Err(BudgetExhausted.into());
+                    }
+                    fetch_range(range, &settings).await
+                }
+            },
+        )),
+        tokio::spawn(run_stage(
+            "encode",
+            config.encode_workers,
+            fetched_rx,
+            Some(encoded_tx),
+            report.clone(),
+            move |range, blocks| encode_range(range, blocks, settings.clone()),
+        )),
+        tokio::spawn(run_stage(
+            "upload",
+            config.upload_workers,
+            encoded_rx,
+            Some(uploaded_tx),
+            report.clone(),
+            move |_, encoded| {
+                upload_range(
+                    encoded,
+                    uploader.clone(),
+                    upload_conn.clone(),
+                    budget.clone(),
+                )
+            },
+        )),
+        tokio::spawn(run_stage(
+            "index",
+            config.index_workers,
+            uploaded_rx,
+            None::<mpsc::Sender<(BlockRange, ())>>,
+            report.clone(),
+            move |range, uploaded| index_range(range, uploaded, conn.clone(), dry_run),
+        )),
+    ];
+
+    for range in ranges.iter() {
+        // waits while the stage is saturated
+        let sent = match encoded.remove(range) {
+            Some(encoded) => presampled_tx.send((*range, encoded)).await.is_ok(),
+            None => range_tx.send((*range, ())).await.is_ok(),
+        };
+        if !sent {
+            break;
+        }
+    }
+    drop(range_tx);
+    drop(presampled_tx);
+
+    for stage in stages {
+        if let Err(e) = stage.await {
+            eprintln!("backfill pipeline stage crashed: {}", e);
+        }
+    }
+
+    let mut report = std::mem::take(&mut *report.lock().unwrap());
+    // a range neither archived nor failed got lost in a crashed worker
+    let mut accounted = report.failed();
+    accounted.extend(&report.archived);
+    accounted.extend(&report.unspent);
+    for range in ranges.iter() {
+        for lost in accounted.complement(range[0], range[1]).ranges() {
+            report
+                .failures
+                .push((*lost, "lost in a crashed pipeline worker".to_string()));
+        }
+    }
+
+    println!(
+        "backfill pipeline archived {} blocks, {} failed, {} over budget, in {:?}",
+        report.archived.block_count(),
+        report.failed().block_count(),
+        report.unspent.block_count(),
+        started.elapsed()
+    );
+
+    report
+}
+
+/// Runs `work` on every range received, with at most `workers` in flight,
+/// and passes the results on to the next stage. The last stage (no `output`)
+/// records its ranges as archived, failures and ranges over budget are
+/// recorded by every stage.
+async fn run_stage<In, Out, F, Fut>(
+    name: &'static str,
+    workers: usize,
+    mut input: mpsc::Receiver<(BlockRange, In)>,
+    output: Option<mpsc::Sender<(BlockRange, Out)>>,
+    report: Arc<Mutex<PipelineReport>>,
+    work: F,
+) where
+    In: Send + 'static,
+    Out: Send + 'static,
+    F: Fn(BlockRange, In) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Out, Error>> + Send + 'static,
+{
+    let work = Arc::new(work);
+    let mut running: JoinSet<()> = JoinSet::new();
+
+    while let Some((range, item)) = input.recv().await {
+        // a busy stage stops reading, which fills its input channel and
+        // blocks the stage before it
+        if running.len() >= workers {
+            running.join_next().await;
+        }
+
+        let work = work.clone();
+        let output = output.clone();
+        let report = report.clone();
+        running.spawn(async move {
+            let result = match work(range, item).await {
+                Ok(out) => match output {
+                    Some(output) => output
+                        .send((range, out))
+                        .await
+                        .map_err(|_| Error::msg("next stage stopped")),
+                    None => {
+                        report
+                            .lock()
+                            .unwrap()
+                            .archived
+                            .insert_range(range[0], range[1]);
+                        Ok(())
+                    }
+                },
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                if e.is::<BudgetExhausted>() {
+                    report
+                        .lock()
+                        .unwrap()
+                        .unspent
+                        .insert_range(range[0], range[1]);
+                    return;
+                }
+                eprintln!(
+                    "{} failed for blocks {}-{}: {}",
+                    name, range[0], range[1], e
+                );
+                report
+                    .lock()
+                    .unwrap()
+                    .failures
+                    .push((range, format!("{}: {}", name, e)));
+            }
+        });
+    }
+
+    while running.join_next().await.is_some() {}
+}
+
+/// blocks of the range with their receipts when the encoding archives them,
+/// each checked for self-consistency
+async fn fetch_range(
+    range: BlockRange,
+    settings: &ArchiveSettings,
+) -> Result<Vec<(Block, Vec<Receipt>)>, Error> {
+    let mut blocks: Vec<(Block, Vec<Receipt>)> = Vec::new();
+
+    for block_number in range[0]..=range[1] {
+        let block = settings.blocks.block_with_txs(block_number).await?;
+        // refuse to archive a block that isn't self-consistent
+        verify_block_integrity(&block)?;
+        let receipts = if settings.encoding.includes_receipts() {
+            let receipts = settings.blocks.block_receipts(&block).await?;
+            verify_receipts_root(&block, &receipts)?;
+            receipts
+        } else {
+            Vec::new()
+        };
+        blocks.push((block, receipts));
+    }
+
+    Ok(blocks)
+}
+
+/// fetches and encodes the data item archiving the range, outside of a
+/// pipeline run
+pub async fn fetch_and_encode(
+    range: BlockRange,
+    settings: Arc<ArchiveSettings>,
+) -> Result<EncodedRange, Error> {
+    let blocks = fetch_range(range, &settings).await?;
+    encode_range(range, blocks, settings).await
+}
+
+/// Encodes and compresses the blocks on the blocking thread pool, and builds
+/// the data item of the range
+async fn encode_range(
+    range: BlockRange,
+    blocks: Vec<(Block, Vec<Receipt>)>,
+    settings: Arc<ArchiveSettings>,
+) -> Result<EncodedRange, Error> {
+    tokio::task::spawn_blocking(move || build_request(range, blocks, &settings)).await?
+}
+
+fn build_request(
+    range: BlockRange,
+    blocks: Vec<(Block, Vec<Receipt>)>,
+    settings: &ArchiveSettings,
+) -> Result<EncodedRange, Error> {
+    let mut payloads: Vec<(String, Vec<u8>)> = Vec::new();
+    for (block, receipts) in blocks {
+        let block_hash = block
+            .hash
+            .clone()
+            .ok_or_else(|| Error::msg("block without hash"))?;
+        let payload = settings
+            .encoding
+            .encode(block, receipts, settings.compressor.as_ref())?;
+        payloads.push((block_hash, payload));
+    }
+    let block_hashes: Vec<String> = payloads
+        .iter()
+        .map(|(block_hash, _)| block_hash.clone())
+        .collect();
+
+    let end_block_hash = block_hashes
+        .last()
+        .ok_or_else(|| Error::msg("empty block range"))?;
+    let key = if settings.range_size > 1 {
+        UploadKey::range(range, end_block_hash)
+    } else {
+        UploadKey::block(range[0], end_block_hash)
+    };
+
+    let mut request = ArweaveRequest::new();
+    request
+        .set_tag("Content-Type", "application/octet-stream")
+        .set_tag("WeaveVM:Encoding", settings.encoding.tag())
+        .set_tag("WeaveVM:Encoding-Version", &settings.encoding_version)
+        .set_tag("WeaveVM:Compression", &settings.compressor.tag());
+    if settings.range_size > 1 {
+        request
+            .set_tag("Block-Range-Start", &range[0].to_string())
+            .set_tag("Block-Range-End", &range[1].to_string())
+            .set_tag("Block-Range-End-Hash", end_block_hash)
+            .set_data(seal_range(range[0], &payloads)?);
+    } else {
+        let (block_hash, payload) = payloads
+            .pop()
+            .ok_or_else(|| Error::msg("empty block range"))?;
+        request
+            .set_tag("Block-Number", &range[0].to_string())
+            .set_tag("Block-Hash", &block_hash)
+            .set_data(payload);
+    }
+    request
+        .set_tag("Client-Version", RETH_CLIENT_VERSION)
+        .set_tag("Network", WVM_NETWORK_TAG)
+        .set_tag("WeaveVM:Backfill", "true");
+
+    Ok(EncodedRange {
+        block_hashes,
+        key,
+        request,
+    })
+}
+
+/// uploads the data item unless an earlier run already did, dry runs skip
+/// the upload job table
+async fn upload_range(
+    encoded: EncodedRange,
+    uploader: Arc<dyn Uploader>,
+    conn: Option<Arc<PSConnection>>,
+    budget: Arc<SpendBudget>,
+) -> Result<UploadedRange, Error> {
+    let arweave_id = if uploader.dry_run() {
+        send_within_budget(&budget, uploader.as_ref(), &encoded.request)
+            .await?
+            .id
+    } else {
+        let conn = planetscale(conn)?;
+        upload_once(
+            &conn,
+            uploader.as_ref(),
+            &encoded.key,
+            &encoded.request,
+            &budget,
+        )
+        .await?
+    };
+    println!("\n\nARWEAVE TXID: {}\n\n", arweave_id);
+
+    Ok(UploadedRange {
+        arweave_id,
+        block_hashes: encoded.block_hashes,
+    })
+}
+
+async fn index_range(
+    range: BlockRange,
+    uploaded: UploadedRange,
+    conn: Option<Arc<PSConnection>>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    if dry_run {
+        return Ok(());
+    }
+    let conn = planetscale(conn)?;
+
+    for (block_number, block_hash) in (range[0]..=range[1]).zip(uploaded.block_hashes.iter()) {
+        ps_insert_block(&conn, block_number as u64, block_hash, &uploaded.arweave_id).await?;
+    }
+
+    Ok(())
+}
+
+/// connection of a run that writes to PlanetScale
+fn planetscale(conn: Option<Arc<PSConnection>>) -> Result<Arc<PSConnection>, Error> {
+    conn.ok_or_else(|| Error::msg("only dry runs may run without PlanetScale"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::archive_envelope::CURRENT_SCHEMA_VERSION;
+    use crate::utils::archive_range::block_payload;
+    use crate::utils::compression::BrotliCompressor;
+    use crate::utils::local_uploader::LocalUploader;
+    use crate::utils::test_fixtures::{rpc_block, RPC_BLOCKS};
+    use async_trait::async_trait;
+    use serde_json::Value;
+    use std::path::{Path, PathBuf};
+
+    /// The fixture blocks under their own numbers, any other block is
+    /// missing. With `first_block` they stand in for the consecutive blocks
+    /// from it on instead.
+    struct FixtureBlocks {
+        first_block: Option<u32>,
+    }
+
+    #[async_trait]
+    impl BlockSource for FixtureBlocks {
+        async fn block_with_txs(&self, block_number: u32) -> Result<Block, Error> {
+            let found = match self.first_block {
+                Some(first_block) => block_number
+                    .checked_sub(first_block)
+                    .and_then(|position| RPC_BLOCKS.get(position as usize))
+                    .map(|name| rpc_block(name)),
+                None => {
+                    let number = format!("0x{:x}", block_number);
+                    RPC_BLOCKS
+                        .iter()
+                        .map(|name| rpc_block(name))
+                        .find(|block| block.number.as_deref() == Some(number.as_str()))
+                }
+            };
+            found.ok_or_else(|| Error::msg(format!("block {} not found", block_number)))
+        }
+
+        async fn block_receipts(&self, _block: &Block) -> Result<Vec<Receipt>, Error> {
+            Err(Error::msg("no fixture receipts"))
+        }
+    }
+
+    fn block_number(block: &Block) -> u32 {
+        let number = block.number.as_deref().unwrap().trim_start_matches("0x");
+        u32::from_str_radix(number, 16).unwrap()
+    }
+
+    fn fixture_settings(blocks: FixtureBlocks, range_size: u32) -> Arc<ArchiveSettings> {
+        Arc::new(ArchiveSettings {
+            blocks: Arc::new(blocks),
+            compressor: Arc::new(BrotliCompressor::new(11, 22).unwrap()),
+            encoding: ArchiveEncoding::BorshBrotli,
+            encoding_version: CURRENT_SCHEMA_VERSION.to_string(),
+            range_size,
+        })
+    }
+
+    fn test_config() -> PipelineConfig {
+        PipelineConfig {
+            channel_capacity: 2,
+            encode_workers: 2,
+            fetch_workers: 2,
+            index_workers: 1,
+            upload_workers: 2,
+        }
+    }
+
+    fn upload_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("backfill-pipeline-{}-{}", name, std::process::id()))
+    }
+
+    /// `<id>.json` sidecars written by the local uploader
+    fn sidecars(dir: &Path) -> Vec<Value> {
+        let mut sidecars: Vec<Value> = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                sidecars.push(serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap());
+            }
+        }
+        sidecars
+    }
+
+    fn tag(sidecar: &Value, name: &str) -> Option<String> {
+        sidecar["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|tag| tag["name"] == name)
+            .and_then(|tag| tag["value"].as_str())
+            .map(String::from)
+    }
+
+    #[tokio::test]
+    async fn dry_run_archives_fixture_blocks_locally() {
+        let dir = upload_dir("blocks");
+        let uploader = Arc::new(LocalUploader::new(dir.to_str().unwrap()).unwrap());
+        let settings = fixture_settings(FixtureBlocks { first_block: None }, 1);
+
+        let blocks: Vec<Block> = RPC_BLOCKS.iter().map(|name| rpc_block(name)).collect();
+        let mut ranges: Vec<BlockRange> = blocks
+            .iter()
+            .map(|block| [block_number(block), block_number(block)])
+            .collect();
+        ranges.push([1, 1]);
+        // a range encoded beforehand, as by the cost preflight, is uploaded as is
+        let mut encoded = HashMap::new();
+        encoded.insert(
+            ranges[0],
+            fetch_and_encode(ranges[0], settings.clone()).await.unwrap(),
+        );
+
+        // dry runs need no PlanetScale connection
+        let report = run_pipeline(
+            ranges,
+            encoded,
+            settings,
+            &test_config(),
+            uploader,
+            None,
+            Arc::new(SpendBudget::new(None)),
+        )
+        .await;
+
+        assert_eq!(report.archived.block_count(), RPC_BLOCKS.len() as u64);
+        assert!(report.unspent.is_empty());
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, [1, 1]);
+        assert!(report.failures[0].1.starts_with("fetch: "));
+
+        // every block is written with its tags and decodes back to itself
+        let sidecars = sidecars(&dir);
+        assert_eq!(sidecars.len(), RPC_BLOCKS.len());
+        for block in blocks {
+            let sidecar = sidecars
+                .iter()
+                .find(|sidecar| tag(sidecar, "Block-Hash").as_deref() == block.hash.as_deref())
+                .unwrap();
+            assert_eq!(
+                tag(sidecar, "Block-Number"),
+                Some(block_number(&block).to_string())
+            );
+            assert_eq!(
+                tag(sidecar, "WeaveVM:Encoding").as_deref(),
+                Some("Borsh-Brotli")
+            );
+            assert_eq!(tag(sidecar, "WeaveVM:Backfill").as_deref(), Some("true"));
+
+            let payload = std::fs::read(dir.join(sidecar["id"].as_str().unwrap())).unwrap();
+            assert_eq!(
+                ArchiveEncoding::BorshBrotli.decode_block(&payload).unwrap(),
+                block
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dry_run_archives_block_ranges() {
+        let dir = upload_dir("ranges");
+        let uploader = Arc::new(LocalUploader::new(dir.to_str().unwrap()).unwrap());
+        let first_block = 1000;
+        let settings = fixture_settings(
+            FixtureBlocks {
+                first_block: Some(first_block),
+            },
+            2,
+        );
+        let last_block = first_block + RPC_BLOCKS.len() as u32 - 1;
+        let mut ranges = BlockRanges::new();
+        ranges.insert_range(first_block, last_block);
+
+        let report = run_pipeline(
+            ranges.chunks(settings.range_size),
+            HashMap::new(),
+            settings,
+            &test_config(),
+            uploader,
+            None,
+            Arc::new(SpendBudget::new(None)),
+        )
+        .await;
+
+        assert_eq!(report.archived, ranges);
+        assert!(report.failures.is_empty());
+
+        // 2 + 2 + 1 blocks, each readable through the range index
+        let sidecars = sidecars(&dir);
+        assert_eq!(sidecars.len(), 3);
+        for sidecar in sidecars.iter() {
+            let start: u32 = tag(sidecar, "Block-Range-Start").unwrap().parse().unwrap();
+            let end: u32 = tag(sidecar, "Block-Range-End").unwrap().parse().unwrap();
+            assert!(end - start < 2);
+            assert_eq!(tag(sidecar, "Block-Number"), None);
+
+            let archive = std::fs::read(dir.join(sidecar["id"].as_str().unwrap())).unwrap();
+            for block_number in start..=end {
+                let block = rpc_block(RPC_BLOCKS[(block_number - first_block) as usize]);
+                let payload = block_payload(&archive, block_number).unwrap();
+                if block_number == end {
+                    assert_eq!(tag(sidecar, "Block-Range-End-Hash"), block.hash);
+                }
+                assert_eq!(
+                    ArchiveEncoding::BorshBrotli.decode_block(payload).unwrap(),
+                    block
+                );
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
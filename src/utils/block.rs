@@ -1,9 +1,18 @@
+use crate::utils::archive_envelope::{self, CURRENT_SCHEMA_VERSION};
+use crate::utils::block_legacy::BlockV0;
+use crate::utils::compression::Compressor;
+use anyhow::Error;
 use borsh::to_vec;
 use borsh_derive::{BorshDeserialize, BorshSerialize};
+use brotli::enc::StandardAlloc;
+use brotli::{BrotliDecompressStream, BrotliResult, BrotliState};
 use planetscale_driver::Database;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::value::{to_raw_value, RawValue};
 use serde_json::Value;
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
 
 #[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -24,20 +33,31 @@ pub struct Block {
     pub parent_beacon_block_root: Option<String>,
     pub parent_hash: Option<String>,
     pub receipts_root: Option<String>,
+    pub requests_hash: Option<String>,
     pub sha3_uncles: Option<String>,
     pub size: Option<String>,
     pub state_root: Option<String>,
     pub timestamp: Option<String>,
     pub total_difficulty: Option<String>,
     pub transactions: Option<Vec<Transaction>>,
+    pub transactions_root: Option<String>,
     pub uncles: Option<Vec<String>>,
-    pub withdrawals: Option<Vec<String>>,
+    pub withdrawals: Option<Vec<Withdrawal>>,
     pub withdrawals_root: Option<String>,
+    #[serde(flatten)]
+    pub other: ExtraFields,
 }
 
+/// Transaction as returned by `eth_getBlockByNumber`, covering the fields of
+/// every typed transaction (legacy, EIP-2930, EIP-1559, EIP-4844, EIP-7702).
+/// Fields a type doesn't use stay `None`, and fields the model doesn't know
+/// yet are kept in `other`.
 #[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
+    pub access_list: Option<Vec<AccessListItem>>,
+    pub authorization_list: Option<Vec<Authorization>>,
+    pub blob_versioned_hashes: Option<Vec<String>>,
     pub block_hash: Option<String>,
     pub block_number: Option<String>,
     pub chain_id: Option<String>,
@@ -46,6 +66,9 @@ pub struct Transaction {
     pub gas_price: Option<String>,
     pub hash: Option<String>,
     pub input: Option<String>,
+    pub max_fee_per_blob_gas: Option<String>,
+    pub max_fee_per_gas: Option<String>,
+    pub max_priority_fee_per_gas: Option<String>,
     pub nonce: Option<String>,
     pub r: Option<String>,
     pub s: Option<String>,
@@ -55,6 +78,141 @@ pub struct Transaction {
     pub transaction_type: Option<String>,
     pub v: Option<String>,
     pub value: Option<String>,
+    pub y_parity: Option<String>,
+    #[serde(flatten)]
+    pub other: ExtraFields,
+}
+
+/// Receipt as returned by `eth_getBlockReceipts` / `eth_getTransactionReceipt`
+#[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    pub blob_gas_price: Option<String>,
+    pub blob_gas_used: Option<String>,
+    pub block_hash: Option<String>,
+    pub block_number: Option<String>,
+    pub contract_address: Option<String>,
+    pub cumulative_gas_used: Option<String>,
+    pub effective_gas_price: Option<String>,
+    pub from: Option<String>,
+    pub gas_used: Option<String>,
+    pub logs: Option<Vec<Log>>,
+    pub logs_bloom: Option<String>,
+    /// post-transaction state root of pre-Byzantium receipts
+    pub root: Option<String>,
+    pub status: Option<String>,
+    pub to: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub transaction_index: Option<String>,
+    #[serde(rename = "type")]
+    pub transaction_type: Option<String>,
+    #[serde(flatten)]
+    pub other: ExtraFields,
+}
+
+#[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub address: Option<String>,
+    pub block_hash: Option<String>,
+    pub block_number: Option<String>,
+    pub data: Option<String>,
+    pub log_index: Option<String>,
+    pub removed: Option<bool>,
+    pub topics: Option<Vec<String>>,
+    pub transaction_hash: Option<String>,
+    pub transaction_index: Option<String>,
+    #[serde(flatten)]
+    pub other: ExtraFields,
+}
+
+/// EIP-4895 beacon chain withdrawal
+#[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Withdrawal {
+    pub address: Option<String>,
+    pub amount: Option<String>,
+    pub index: Option<String>,
+    pub validator_index: Option<String>,
+}
+
+/// EIP-2930 access list entry
+#[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListItem {
+    pub address: Option<String>,
+    pub storage_keys: Option<Vec<String>>,
+}
+
+/// EIP-7702 signed authorization
+#[derive(Debug, Deserialize, Serialize, BorshSerialize, BorshDeserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorization {
+    pub address: Option<String>,
+    pub chain_id: Option<String>,
+    pub nonce: Option<String>,
+    pub r: Option<String>,
+    pub s: Option<String>,
+    // early 7702 devnets return the parity as `v`
+    #[serde(alias = "v")]
+    pub y_parity: Option<String>,
+}
+
+/// JSON-RPC fields without a dedicated model field, kept as their raw JSON
+/// text so newer node versions don't lose data, numbers beyond 2^53 included.
+/// Borsh stores them as `(name, json)` pairs.
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(transparent)]
+pub struct ExtraFields(pub BTreeMap<String, Box<RawValue>>);
+
+impl<'de> Deserialize<'de> for ExtraFields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // flattened fields arrive buffered, which a `RawValue` can't be read
+        // from. `arbitrary_precision` keeps their numbers exact in a `Value`
+        let fields = BTreeMap::<String, Value>::deserialize(deserializer)?;
+        fields
+            .into_iter()
+            .map(|(name, value)| to_raw_value(&value).map(|raw| (name, raw)))
+            .collect::<Result<BTreeMap<String, Box<RawValue>>, serde_json::Error>>()
+            .map(ExtraFields)
+            .map_err(de::Error::custom)
+    }
+}
+
+impl PartialEq for ExtraFields {
+    fn eq(&self, other: &ExtraFields) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|((name, raw), (other_name, other_raw))| {
+                    name == other_name && raw.get() == other_raw.get()
+                })
+    }
+}
+
+impl borsh::BorshSerialize for ExtraFields {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        let entries: Vec<(&String, &str)> =
+            self.0.iter().map(|(name, raw)| (name, raw.get())).collect();
+        borsh::BorshSerialize::serialize(&entries, writer)
+    }
+}
+
+impl borsh::BorshDeserialize for ExtraFields {
+    fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        let entries: Vec<(String, String)> = borsh::BorshDeserialize::deserialize_reader(reader)?;
+        entries
+            .into_iter()
+            .map(|(name, json)| {
+                RawValue::from_string(json)
+                    .map(|raw| (name, raw))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .collect::<io::Result<BTreeMap<String, Box<RawValue>>>>()
+            .map(ExtraFields)
+    }
 }
 
 impl Block {
@@ -62,17 +220,200 @@ impl Block {
         let block: Block = serde_json::from_value(value)?;
         Ok(block)
     }
-    pub fn brotli_compress(input: &[u8]) -> Vec<u8> {
+    pub fn brotli_compress(input: &[u8], quality: u32, window: u32) -> Vec<u8> {
         let mut writer: brotli::CompressorWriter<Vec<u8>> =
-            brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+            brotli::CompressorWriter::new(Vec::new(), 4096, quality, window);
         writer.write_all(input).unwrap();
         writer.into_inner()
     }
     pub fn borsh_ser(input: &Block) -> Vec<u8> {
         to_vec(input).unwrap()
     }
+    pub fn brotli_decompress(input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut state = BrotliState::new(
+            StandardAlloc::default(),
+            StandardAlloc::default(),
+            StandardAlloc::default(),
+        );
+        let mut buffer = vec![0u8; 4096];
+        let mut output: Vec<u8> = Vec::new();
+        let mut available_in = input.len();
+        let mut input_offset = 0;
+        let mut total_out = 0;
+
+        loop {
+            let mut available_out = buffer.len();
+            let mut output_offset = 0;
+            let result = BrotliDecompressStream(
+                &mut available_in,
+                &mut input_offset,
+                input,
+                &mut available_out,
+                &mut output_offset,
+                &mut buffer,
+                &mut total_out,
+                &mut state,
+            );
+            output.extend_from_slice(&buffer[..output_offset]);
+
+            match result {
+                BrotliResult::NeedsMoreOutput => continue,
+                BrotliResult::ResultSuccess if available_in == 0 => return Ok(output),
+                BrotliResult::ResultSuccess => {
+                    return Err(DecodeError::InvalidCompression(format!(
+                        "{} trailing bytes after the brotli stream",
+                        available_in
+                    )))
+                }
+                // the stream ended before the brotli end marker
+                BrotliResult::NeedsMoreInput => return Err(DecodeError::Truncated),
+                BrotliResult::ResultFailure => {
+                    return Err(DecodeError::InvalidCompression(format!(
+                        "corrupt brotli stream at byte {}",
+                        input_offset
+                    )))
+                }
+            }
+        }
+    }
+    pub fn borsh_de(input: &[u8]) -> Result<Block, DecodeError> {
+        borsh_from_slice(input)
+    }
+    /// enveloped Borsh archive payload of the block
+    pub fn to_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        archive_envelope::seal(CURRENT_SCHEMA_VERSION, compressor, &Block::borsh_ser(self))
+    }
+    /// decodes an archive payload of any schema version, the inverse of
+    /// `to_payload`
+    pub fn from_payload(payload: &[u8]) -> Result<Block, DecodeError> {
+        match archive_envelope::open(payload)? {
+            (0, body) => borsh_from_slice::<BlockV0>(&body)?.try_into(),
+            (1, body) => Block::borsh_de(&body),
+            (version, _) => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+    /// encodes the block and checks the payload decodes back to the very same
+    /// block, so nothing that can't be read back gets archived
+    pub fn to_verified_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        let payload = self.to_payload(compressor)?;
+        if &Block::from_payload(&payload)? != self {
+            return Err(DecodeError::RoundtripMismatch.into());
+        }
+        Ok(payload)
+    }
+}
+
+/// Archive payload of a block together with its transaction receipts, in
+/// transaction order. Encoded as `Borsh-Brotli-Receipts`, which only exists
+/// in enveloped form.
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct BlockPayload {
+    pub block: Block,
+    pub receipts: Vec<Receipt>,
+}
+
+impl BlockPayload {
+    pub fn to_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        archive_envelope::seal(CURRENT_SCHEMA_VERSION, compressor, &to_vec(self).unwrap())
+    }
+    pub fn from_payload(payload: &[u8]) -> Result<BlockPayload, DecodeError> {
+        match archive_envelope::open(payload)? {
+            (1, body) => borsh_from_slice(&body),
+            (version, _) => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+    pub fn to_verified_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        let payload = self.to_payload(compressor)?;
+        if &BlockPayload::from_payload(&payload)? != self {
+            return Err(DecodeError::RoundtripMismatch.into());
+        }
+        Ok(payload)
+    }
+}
+
+pub fn borsh_from_slice<T: borsh::BorshDeserialize>(input: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = EndTrackingReader {
+        input,
+        reached_end: false,
+    };
+    let value = T::deserialize_reader(&mut reader).map_err(|e| {
+        // borsh turns the reader's `UnexpectedEof` into an `InvalidData`
+        // error, so a payload ending mid-field is told by the reader
+        if reader.reached_end {
+            DecodeError::Truncated
+        } else {
+            DecodeError::InvalidBorsh(e.to_string())
+        }
+    })?;
+
+    match reader.input.len() {
+        0 => Ok(value),
+        trailing => Err(DecodeError::InvalidBorsh(format!(
+            "{} trailing bytes after the Borsh body",
+            trailing
+        ))),
+    }
+}
+
+/// reader remembering whether a read asked for more than the input held
+struct EndTrackingReader<'a> {
+    input: &'a [u8],
+    reached_end: bool,
+}
+
+impl io::Read for EndTrackingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.len() > self.input.len() {
+            self.reached_end = true;
+        }
+        self.input.read(buf)
+    }
 }
 
+/// Why an archive payload could not be decoded into a `Block`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// the payload ends before the encoded block does
+    Truncated,
+    InvalidCompression(String),
+    InvalidBorsh(String),
+    InvalidRlp(String),
+    /// envelope schema version this build can't decode
+    UnsupportedVersion(u8),
+    UnsupportedCodec(u8),
+    /// `WeaveVM:Encoding` tag this build doesn't know
+    UnsupportedEncoding(String),
+    /// the decoded block differs from the encoded one
+    RoundtripMismatch,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated block payload"),
+            DecodeError::InvalidCompression(reason) => {
+                write!(f, "invalid brotli compression: {}", reason)
+            }
+            DecodeError::InvalidBorsh(reason) => write!(f, "invalid borsh block: {}", reason),
+            DecodeError::InvalidRlp(reason) => write!(f, "invalid block rlp: {}", reason),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported payload schema version {}", version)
+            }
+            DecodeError::UnsupportedCodec(codec) => {
+                write!(f, "unsupported payload codec {}", codec)
+            }
+            DecodeError::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported archive encoding {}", encoding)
+            }
+            DecodeError::RoundtripMismatch => {
+                write!(f, "decoded block differs from the encoded block")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 #[derive(Database, Debug, Serialize, Deserialize)]
 pub struct PlanetScaleBlock {
     pub block_hash: String,
@@ -89,3 +430,160 @@ impl PlanetScaleBlock {
         }
     }
 }
+
+#[derive(Database, Debug, Serialize, Deserialize)]
+pub struct PlanetScaleCheckpoint {
+    pub scan_key: String,
+    /// -1 until a first block is checkpointed
+    pub block_number: i64,
+    pub cursor: String,
+    /// JSON `BlockRanges` of the blocks above `block_number` seen before
+    /// `cursor`, empty once the scan reached the chain tip
+    pub archived: String,
+}
+
+#[derive(Database, Debug, Serialize, Deserialize)]
+pub struct PlanetScaleRetry {
+    pub block_number: u64,
+    pub attempts: u64,
+    pub last_error: String,
+    /// unix seconds
+    pub next_attempt_at: u64,
+}
+
+#[derive(Database, Debug, Serialize, Deserialize)]
+pub struct PlanetScaleDeadLetter {
+    pub block_number: u64,
+    pub attempts: u64,
+    pub last_error: String,
+    /// unix seconds
+    pub failed_at: u64,
+}
+
+#[derive(Database, Debug, Serialize, Deserialize)]
+pub struct PlanetScaleReceipt {
+    pub arweave_hash: String,
+    pub deadline_height: u64,
+    /// unix milliseconds
+    pub timestamp: u64,
+    /// full bundler response, JSON
+    pub receipt: String,
+}
+
+#[derive(Database, Debug, Serialize, Deserialize)]
+pub struct PlanetScaleUpload {
+    pub upload_key: String,
+    /// empty while the upload is pending
+    pub arweave_hash: String,
+    /// set when a paid upload is pending on its data, see `PartialUpload`
+    pub resume_state: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::archive_encoding::ArchiveEncoding;
+    use crate::utils::compression::{BrotliCompressor, ZstdCompressor};
+    use crate::utils::test_fixtures::{fixture_bytes, rpc_block, RPC_BLOCKS};
+
+    #[test]
+    fn borsh_payload_roundtrip() {
+        for name in RPC_BLOCKS {
+            let block = rpc_block(name);
+            for compressor in [
+                Box::new(BrotliCompressor::default()) as Box<dyn Compressor>,
+                Box::new(ZstdCompressor::new(3).unwrap()),
+            ] {
+                let payload = block.to_verified_payload(compressor.as_ref()).unwrap();
+                assert_eq!(Block::from_payload(&payload).unwrap(), block, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn version_0_payload_decodes() {
+        let expected = rpc_block("eip1559");
+        let block = Block::from_payload(&fixture_bytes("blocks/eip1559.v0.bin")).unwrap();
+
+        assert_eq!(block.hash, expected.hash);
+        assert_eq!(block.transactions_root, None);
+        let transactions = block.transactions.unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[1].hash, expected.transactions.unwrap()[1].hash);
+        assert_eq!(transactions[1].access_list, None);
+    }
+
+    #[test]
+    fn extra_fields_keep_big_numbers() {
+        let mut json: Value = serde_json::from_slice(&fixture_bytes("blocks/legacy.json")).unwrap();
+        json["l1BlockNumber"] = serde_json::from_str("18446744073709551617").unwrap();
+        json["transactions"][0]["sourceHash"] = Value::from("0x01");
+        let block = Block::load_block_from_value(json).unwrap();
+
+        let payload = block
+            .to_verified_payload(&BrotliCompressor::default())
+            .unwrap();
+        let decoded = Block::from_payload(&payload).unwrap();
+        assert_eq!(
+            decoded.other.0["l1BlockNumber"].get(),
+            "18446744073709551617"
+        );
+        assert_eq!(
+            decoded.transactions.unwrap()[0].other.0["sourceHash"].get(),
+            "\"0x01\""
+        );
+        assert_eq!(
+            serde_json::to_value(&block).unwrap()["l1BlockNumber"].to_string(),
+            "18446744073709551617"
+        );
+    }
+
+    #[test]
+    fn truncated_borsh_body() {
+        let body = Block::borsh_ser(&rpc_block("legacy"));
+
+        for len in [0, 1, body.len() / 2, body.len() - 1] {
+            assert_eq!(
+                Block::borsh_de(&body[..len]),
+                Err(DecodeError::Truncated),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_after_borsh_body() {
+        let mut body = Block::borsh_ser(&rpc_block("legacy"));
+        body.push(0);
+
+        assert!(matches!(
+            Block::borsh_de(&body),
+            Err(DecodeError::InvalidBorsh(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_schema_versions_are_refused() {
+        let compressor = BrotliCompressor::default();
+        let body = Block::borsh_ser(&rpc_block("legacy"));
+        let payload = archive_envelope::seal(9, &compressor, &body).unwrap();
+
+        for encoding in [
+            ArchiveEncoding::BorshBrotli,
+            ArchiveEncoding::BorshBrotliReceipts,
+            ArchiveEncoding::CompactBrotli,
+            ArchiveEncoding::CompactBrotliReceipts,
+            ArchiveEncoding::RlpBrotli,
+        ] {
+            let err = encoding.decode_block(&payload).unwrap_err();
+            assert_eq!(
+                err,
+                DecodeError::UnsupportedVersion(9),
+                "{}",
+                encoding.tag()
+            );
+            assert_eq!(err.to_string(), "unsupported payload schema version 9");
+        }
+    }
+}
@@ -0,0 +1,496 @@
+use crate::utils::archive_envelope::{self, CURRENT_SCHEMA_VERSION};
+use crate::utils::block::{
+    borsh_from_slice, AccessListItem, Authorization, Block, DecodeError, ExtraFields, Receipt,
+    Transaction, Withdrawal,
+};
+use crate::utils::block_rlp::{hex_bytes, hex_quantity, to_hex};
+use crate::utils::compression::Compressor;
+use anyhow::Error;
+use borsh::to_vec;
+use borsh_derive::{BorshDeserialize, BorshSerialize};
+use ethers::types::U256;
+use std::io;
+
+type Hash = [u8; 32];
+type Address = [u8; 20];
+
+/// `Block` with hashes and addresses as fixed bytes, quantities as integers
+/// and data as raw bytes, instead of hex strings. Encoded as
+/// `Compact-Brotli`, converts losslessly to and from the JSON-RPC `Block`.
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct CompactBlock {
+    pub base_fee_per_gas: Option<Quantity>,
+    pub blob_gas_used: Option<u64>,
+    pub difficulty: Option<Quantity>,
+    pub excess_blob_gas: Option<u64>,
+    pub extra_data: Option<Vec<u8>>,
+    pub gas_limit: Option<u64>,
+    pub gas_used: Option<u64>,
+    pub hash: Option<Hash>,
+    pub logs_bloom: Option<Vec<u8>>,
+    pub miner: Option<Address>,
+    pub mix_hash: Option<Hash>,
+    pub nonce: Option<Vec<u8>>,
+    pub number: Option<u64>,
+    pub parent_beacon_block_root: Option<Hash>,
+    pub parent_hash: Option<Hash>,
+    pub receipts_root: Option<Hash>,
+    pub requests_hash: Option<Hash>,
+    pub sha3_uncles: Option<Hash>,
+    pub size: Option<u64>,
+    pub state_root: Option<Hash>,
+    pub timestamp: Option<u64>,
+    pub total_difficulty: Option<Quantity>,
+    pub transactions: Option<Vec<CompactTransaction>>,
+    pub transactions_root: Option<Hash>,
+    pub uncles: Option<Vec<Hash>>,
+    pub withdrawals: Option<Vec<CompactWithdrawal>>,
+    pub withdrawals_root: Option<Hash>,
+    pub other: ExtraFields,
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct CompactTransaction {
+    pub access_list: Option<Vec<CompactAccessListItem>>,
+    pub authorization_list: Option<Vec<CompactAuthorization>>,
+    pub blob_versioned_hashes: Option<Vec<Hash>>,
+    pub block_hash: Option<Hash>,
+    pub block_number: Option<u64>,
+    pub chain_id: Option<Quantity>,
+    pub from: Option<Address>,
+    pub gas: Option<u64>,
+    pub gas_price: Option<Quantity>,
+    pub hash: Option<Hash>,
+    pub input: Option<Vec<u8>>,
+    pub max_fee_per_blob_gas: Option<Quantity>,
+    pub max_fee_per_gas: Option<Quantity>,
+    pub max_priority_fee_per_gas: Option<Quantity>,
+    pub nonce: Option<u64>,
+    pub r: Option<Quantity>,
+    pub s: Option<Quantity>,
+    pub to: Option<Address>,
+    pub transaction_index: Option<u64>,
+    pub transaction_type: Option<u64>,
+    pub v: Option<Quantity>,
+    pub value: Option<Quantity>,
+    pub y_parity: Option<u64>,
+    pub other: ExtraFields,
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct CompactWithdrawal {
+    pub address: Option<Address>,
+    pub amount: Option<u64>,
+    pub index: Option<u64>,
+    pub validator_index: Option<u64>,
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct CompactAccessListItem {
+    pub address: Option<Address>,
+    pub storage_keys: Option<Vec<Hash>>,
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct CompactAuthorization {
+    pub address: Option<Address>,
+    pub chain_id: Option<Quantity>,
+    pub nonce: Option<u64>,
+    pub r: Option<Quantity>,
+    pub s: Option<Quantity>,
+    pub y_parity: Option<u64>,
+}
+
+/// Compact block with its receipts, encoded as `Compact-Brotli-Receipts`.
+/// Receipts keep the JSON-RPC shape.
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct CompactBlockPayload {
+    pub block: CompactBlock,
+    pub receipts: Vec<Receipt>,
+}
+
+impl CompactBlock {
+    pub fn to_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        archive_envelope::seal(CURRENT_SCHEMA_VERSION, compressor, &to_vec(self).unwrap())
+    }
+    pub fn from_payload(payload: &[u8]) -> Result<CompactBlock, DecodeError> {
+        match archive_envelope::open(payload)? {
+            (1, body) => borsh_from_slice(&body),
+            (version, _) => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+    pub fn to_verified_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        let payload = self.to_payload(compressor)?;
+        if &CompactBlock::from_payload(&payload)? != self {
+            return Err(DecodeError::RoundtripMismatch.into());
+        }
+        Ok(payload)
+    }
+}
+
+impl CompactBlockPayload {
+    pub fn to_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        archive_envelope::seal(CURRENT_SCHEMA_VERSION, compressor, &to_vec(self).unwrap())
+    }
+    pub fn from_payload(payload: &[u8]) -> Result<CompactBlockPayload, DecodeError> {
+        match archive_envelope::open(payload)? {
+            (1, body) => borsh_from_slice(&body),
+            (version, _) => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+    pub fn to_verified_payload(&self, compressor: &dyn Compressor) -> Result<Vec<u8>, Error> {
+        let payload = self.to_payload(compressor)?;
+        if &CompactBlockPayload::from_payload(&payload)? != self {
+            return Err(DecodeError::RoundtripMismatch.into());
+        }
+        Ok(payload)
+    }
+}
+
+/// 256 bit quantity, Borsh encoded as a length byte followed by its minimal
+/// big-endian bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity(pub U256);
+
+impl borsh::BorshSerialize for Quantity {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        let significant = &bytes[(self.0.leading_zeros() / 8) as usize..];
+        writer.write_all(&[significant.len() as u8])?;
+        writer.write_all(significant)
+    }
+}
+
+impl borsh::BorshDeserialize for Quantity {
+    fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        let len = u8::deserialize_reader(reader)? as usize;
+        if len > 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("quantity of {} bytes", len),
+            ));
+        }
+        let mut bytes = vec![0u8; len];
+        reader.read_exact(&mut bytes)?;
+        Ok(Quantity(U256::from_big_endian(&bytes)))
+    }
+}
+
+impl TryFrom<&Block> for CompactBlock {
+    type Error = Error;
+
+    /// fails on hex the compact model can't hold, and on fields that wouldn't
+    /// convert back to the exact same string (e.g. a non-minimal quantity)
+    fn try_from(block: &Block) -> Result<CompactBlock, Error> {
+        let compact = CompactBlock {
+            base_fee_per_gas: quantity(&block.base_fee_per_gas)?,
+            blob_gas_used: small(&block.blob_gas_used)?,
+            difficulty: quantity(&block.difficulty)?,
+            excess_blob_gas: small(&block.excess_blob_gas)?,
+            extra_data: data(&block.extra_data)?,
+            gas_limit: small(&block.gas_limit)?,
+            gas_used: small(&block.gas_used)?,
+            hash: fixed(&block.hash)?,
+            logs_bloom: data(&block.logs_bloom)?,
+            miner: fixed(&block.miner)?,
+            mix_hash: fixed(&block.mix_hash)?,
+            nonce: data(&block.nonce)?,
+            number: small(&block.number)?,
+            parent_beacon_block_root: fixed(&block.parent_beacon_block_root)?,
+            parent_hash: fixed(&block.parent_hash)?,
+            receipts_root: fixed(&block.receipts_root)?,
+            requests_hash: fixed(&block.requests_hash)?,
+            sha3_uncles: fixed(&block.sha3_uncles)?,
+            size: small(&block.size)?,
+            state_root: fixed(&block.state_root)?,
+            timestamp: small(&block.timestamp)?,
+            total_difficulty: quantity(&block.total_difficulty)?,
+            transactions: list(&block.transactions, |item| item.try_into())?,
+            transactions_root: fixed(&block.transactions_root)?,
+            uncles: list(&block.uncles, |uncle| fixed_bytes(uncle))?,
+            withdrawals: list(&block.withdrawals, |item| item.try_into())?,
+            withdrawals_root: fixed(&block.withdrawals_root)?,
+            other: block.other.clone(),
+        };
+
+        if &Block::from(&compact) != block {
+            return Err(Error::msg(format!(
+                "block {:?} does not convert losslessly to the compact encoding",
+                block.number
+            )));
+        }
+
+        Ok(compact)
+    }
+}
+
+impl From<&CompactBlock> for Block {
+    fn from(block: &CompactBlock) -> Block {
+        Block {
+            base_fee_per_gas: hex_quantity_of(&block.base_fee_per_gas),
+            blob_gas_used: hex_small(&block.blob_gas_used),
+            difficulty: hex_quantity_of(&block.difficulty),
+            excess_blob_gas: hex_small(&block.excess_blob_gas),
+            extra_data: hex_data(&block.extra_data),
+            gas_limit: hex_small(&block.gas_limit),
+            gas_used: hex_small(&block.gas_used),
+            hash: hex_data(&block.hash),
+            logs_bloom: hex_data(&block.logs_bloom),
+            miner: hex_data(&block.miner),
+            mix_hash: hex_data(&block.mix_hash),
+            nonce: hex_data(&block.nonce),
+            number: hex_small(&block.number),
+            parent_beacon_block_root: hex_data(&block.parent_beacon_block_root),
+            parent_hash: hex_data(&block.parent_hash),
+            receipts_root: hex_data(&block.receipts_root),
+            requests_hash: hex_data(&block.requests_hash),
+            sha3_uncles: hex_data(&block.sha3_uncles),
+            size: hex_small(&block.size),
+            state_root: hex_data(&block.state_root),
+            timestamp: hex_small(&block.timestamp),
+            total_difficulty: hex_quantity_of(&block.total_difficulty),
+            transactions: block
+                .transactions
+                .as_ref()
+                .map(|transactions| transactions.iter().map(Transaction::from).collect()),
+            transactions_root: hex_data(&block.transactions_root),
+            uncles: block
+                .uncles
+                .as_ref()
+                .map(|uncles| uncles.iter().map(|uncle| to_hex(uncle)).collect()),
+            withdrawals: block
+                .withdrawals
+                .as_ref()
+                .map(|withdrawals| withdrawals.iter().map(Withdrawal::from).collect()),
+            withdrawals_root: hex_data(&block.withdrawals_root),
+            other: block.other.clone(),
+        }
+    }
+}
+
+impl TryFrom<&Transaction> for CompactTransaction {
+    type Error = Error;
+
+    fn try_from(transaction: &Transaction) -> Result<CompactTransaction, Error> {
+        Ok(CompactTransaction {
+            access_list: list(&transaction.access_list, |item| item.try_into())?,
+            authorization_list: list(&transaction.authorization_list, |item| item.try_into())?,
+            blob_versioned_hashes: list(&transaction.blob_versioned_hashes, |hash| {
+                fixed_bytes(hash)
+            })?,
+            block_hash: fixed(&transaction.block_hash)?,
+            block_number: small(&transaction.block_number)?,
+            chain_id: quantity(&transaction.chain_id)?,
+            from: fixed(&transaction.from)?,
+            gas: small(&transaction.gas)?,
+            gas_price: quantity(&transaction.gas_price)?,
+            hash: fixed(&transaction.hash)?,
+            input: data(&transaction.input)?,
+            max_fee_per_blob_gas: quantity(&transaction.max_fee_per_blob_gas)?,
+            max_fee_per_gas: quantity(&transaction.max_fee_per_gas)?,
+            max_priority_fee_per_gas: quantity(&transaction.max_priority_fee_per_gas)?,
+            nonce: small(&transaction.nonce)?,
+            r: quantity(&transaction.r)?,
+            s: quantity(&transaction.s)?,
+            to: fixed(&transaction.to)?,
+            transaction_index: small(&transaction.transaction_index)?,
+            transaction_type: small(&transaction.transaction_type)?,
+            v: quantity(&transaction.v)?,
+            value: quantity(&transaction.value)?,
+            y_parity: small(&transaction.y_parity)?,
+            other: transaction.other.clone(),
+        })
+    }
+}
+
+impl From<&CompactTransaction> for Transaction {
+    fn from(transaction: &CompactTransaction) -> Transaction {
+        Transaction {
+            access_list: transaction
+                .access_list
+                .as_ref()
+                .map(|items| items.iter().map(AccessListItem::from).collect()),
+            authorization_list: transaction
+                .authorization_list
+                .as_ref()
+                .map(|authorizations| authorizations.iter().map(Authorization::from).collect()),
+            blob_versioned_hashes: transaction
+                .blob_versioned_hashes
+                .as_ref()
+                .map(|hashes| hashes.iter().map(|hash| to_hex(hash)).collect()),
+            block_hash: hex_data(&transaction.block_hash),
+            block_number: hex_small(&transaction.block_number),
+            chain_id: hex_quantity_of(&transaction.chain_id),
+            from: hex_data(&transaction.from),
+            gas: hex_small(&transaction.gas),
+            gas_price: hex_quantity_of(&transaction.gas_price),
+            hash: hex_data(&transaction.hash),
+            input: hex_data(&transaction.input),
+            max_fee_per_blob_gas: hex_quantity_of(&transaction.max_fee_per_blob_gas),
+            max_fee_per_gas: hex_quantity_of(&transaction.max_fee_per_gas),
+            max_priority_fee_per_gas: hex_quantity_of(&transaction.max_priority_fee_per_gas),
+            nonce: hex_small(&transaction.nonce),
+            r: hex_quantity_of(&transaction.r),
+            s: hex_quantity_of(&transaction.s),
+            to: hex_data(&transaction.to),
+            transaction_index: hex_small(&transaction.transaction_index),
+            transaction_type: hex_small(&transaction.transaction_type),
+            v: hex_quantity_of(&transaction.v),
+            value: hex_quantity_of(&transaction.value),
+            y_parity: hex_small(&transaction.y_parity),
+            other: transaction.other.clone(),
+        }
+    }
+}
+
+impl TryFrom<&Withdrawal> for CompactWithdrawal {
+    type Error = Error;
+
+    fn try_from(withdrawal: &Withdrawal) -> Result<CompactWithdrawal, Error> {
+        Ok(CompactWithdrawal {
+            address: fixed(&withdrawal.address)?,
+            amount: small(&withdrawal.amount)?,
+            index: small(&withdrawal.index)?,
+            validator_index: small(&withdrawal.validator_index)?,
+        })
+    }
+}
+
+impl From<&CompactWithdrawal> for Withdrawal {
+    fn from(withdrawal: &CompactWithdrawal) -> Withdrawal {
+        Withdrawal {
+            address: hex_data(&withdrawal.address),
+            amount: hex_small(&withdrawal.amount),
+            index: hex_small(&withdrawal.index),
+            validator_index: hex_small(&withdrawal.validator_index),
+        }
+    }
+}
+
+impl TryFrom<&AccessListItem> for CompactAccessListItem {
+    type Error = Error;
+
+    fn try_from(item: &AccessListItem) -> Result<CompactAccessListItem, Error> {
+        Ok(CompactAccessListItem {
+            address: fixed(&item.address)?,
+            storage_keys: list(&item.storage_keys, |key| fixed_bytes(key))?,
+        })
+    }
+}
+
+impl From<&CompactAccessListItem> for AccessListItem {
+    fn from(item: &CompactAccessListItem) -> AccessListItem {
+        AccessListItem {
+            address: hex_data(&item.address),
+            storage_keys: item
+                .storage_keys
+                .as_ref()
+                .map(|keys| keys.iter().map(|key| to_hex(key)).collect()),
+        }
+    }
+}
+
+impl TryFrom<&Authorization> for CompactAuthorization {
+    type Error = Error;
+
+    fn try_from(authorization: &Authorization) -> Result<CompactAuthorization, Error> {
+        Ok(CompactAuthorization {
+            address: fixed(&authorization.address)?,
+            chain_id: quantity(&authorization.chain_id)?,
+            nonce: small(&authorization.nonce)?,
+            r: quantity(&authorization.r)?,
+            s: quantity(&authorization.s)?,
+            y_parity: small(&authorization.y_parity)?,
+        })
+    }
+}
+
+impl From<&CompactAuthorization> for Authorization {
+    fn from(authorization: &CompactAuthorization) -> Authorization {
+        Authorization {
+            address: hex_data(&authorization.address),
+            chain_id: hex_quantity_of(&authorization.chain_id),
+            nonce: hex_small(&authorization.nonce),
+            r: hex_quantity_of(&authorization.r),
+            s: hex_quantity_of(&authorization.s),
+            y_parity: hex_small(&authorization.y_parity),
+        }
+    }
+}
+
+fn list<T, C>(
+    field: &Option<Vec<T>>,
+    convert: impl Fn(&T) -> Result<C, Error>,
+) -> Result<Option<Vec<C>>, Error> {
+    field
+        .as_ref()
+        .map(|items| items.iter().map(convert).collect())
+        .transpose()
+}
+
+pub fn fixed_bytes<const N: usize>(value: &str) -> Result<[u8; N], Error> {
+    <[u8; N]>::try_from(hex_bytes(value)?.as_slice())
+        .map_err(|_| Error::msg(format!("expected {} bytes of hex, got {}", N, value)))
+}
+
+fn fixed<const N: usize>(field: &Option<String>) -> Result<Option<[u8; N]>, Error> {
+    field.as_deref().map(fixed_bytes).transpose()
+}
+
+fn data(field: &Option<String>) -> Result<Option<Vec<u8>>, Error> {
+    field.as_deref().map(hex_bytes).transpose()
+}
+
+fn small(field: &Option<String>) -> Result<Option<u64>, Error> {
+    field
+        .as_deref()
+        .map(|value| {
+            let quantity = hex_quantity(value)?;
+            if quantity > U256::from(u64::MAX) {
+                return Err(Error::msg(format!("quantity {} exceeds 64 bits", value)));
+            }
+            Ok(quantity.as_u64())
+        })
+        .transpose()
+}
+
+fn quantity(field: &Option<String>) -> Result<Option<Quantity>, Error> {
+    field
+        .as_deref()
+        .map(|value| hex_quantity(value).map(Quantity))
+        .transpose()
+}
+
+fn hex_data<T: AsRef<[u8]>>(field: &Option<T>) -> Option<String> {
+    field.as_ref().map(|bytes| to_hex(bytes.as_ref()))
+}
+
+fn hex_small(field: &Option<u64>) -> Option<String> {
+    field.map(|value| format!("0x{:x}", value))
+}
+
+fn hex_quantity_of(field: &Option<Quantity>) -> Option<String> {
+    field.map(|quantity| format!("0x{:x}", quantity.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::BrotliCompressor;
+    use crate::utils::test_fixtures::{rpc_block, RPC_BLOCKS};
+
+    #[test]
+    fn compact_roundtrip() {
+        for name in RPC_BLOCKS {
+            let block = rpc_block(name);
+            let compact = CompactBlock::try_from(&block).unwrap();
+            assert_eq!(Block::from(&compact), block, "{}", name);
+
+            let payload = compact
+                .to_verified_payload(&BrotliCompressor::default())
+                .unwrap();
+            assert_eq!(CompactBlock::from_payload(&payload).unwrap(), compact);
+        }
+    }
+}
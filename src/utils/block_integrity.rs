@@ -0,0 +1,115 @@
+use crate::utils::block::{Block, Receipt, Transaction};
+use crate::utils::block_rlp::hex_bytes;
+use crate::utils::trie::ordered_trie_root;
+use anyhow::Error;
+use ethers::types::H256;
+use ethers::utils::keccak256;
+
+/// Self-consistency checks run on a block before it gets archived, so a
+/// faulty RPC can't get a block written to Arweave that doesn't hash to its
+/// own `hash`.
+pub fn verify_block_integrity(block: &Block) -> Result<(), Error> {
+    verify_header_hash(block)?;
+    verify_transactions_root(block)?;
+    verify_withdrawals_root(block)
+}
+
+/// rebuilds the header RLP from the block fields and compares its keccak
+/// hash to `hash`
+pub fn verify_header_hash(block: &Block) -> Result<(), Error> {
+    let block_hash = block
+        .hash
+        .as_deref()
+        .ok_or_else(|| Error::msg("missing block hash"))?;
+    let computed = H256::from(keccak256(block.header_rlp()?));
+
+    compare_root("block hash", computed, block_hash)
+}
+
+/// recomputes every transaction hash and the transactions trie root and
+/// compares them to the block's `transactionsRoot`
+pub fn verify_transactions_root(block: &Block) -> Result<(), Error> {
+    let transactions_root = block
+        .transactions_root
+        .as_deref()
+        .ok_or_else(|| Error::msg("missing transactionsRoot"))?;
+    let transactions: &[Transaction] = block.transactions.as_deref().unwrap_or_default();
+
+    let mut encoded: Vec<Vec<u8>> = Vec::with_capacity(transactions.len());
+    for transaction in transactions {
+        let consensus_bytes = transaction.consensus_bytes()?;
+        let tx_hash = transaction
+            .hash
+            .as_deref()
+            .ok_or_else(|| Error::msg("transaction without hash"))?;
+        compare_root(
+            "transaction hash",
+            H256::from(keccak256(&consensus_bytes)),
+            tx_hash,
+        )?;
+        encoded.push(consensus_bytes);
+    }
+
+    compare_root(
+        "transactionsRoot",
+        ordered_trie_root(&encoded),
+        transactions_root,
+    )
+}
+
+/// recomputes the withdrawals trie root and compares it to `withdrawalsRoot`
+pub fn verify_withdrawals_root(block: &Block) -> Result<(), Error> {
+    let (withdrawals, withdrawals_root) = match (&block.withdrawals, &block.withdrawals_root) {
+        (None, None) => return Ok(()),
+        (Some(withdrawals), Some(root)) => (withdrawals, root),
+        _ => {
+            return Err(Error::msg(
+                "withdrawals and withdrawalsRoot must be both present or both absent",
+            ))
+        }
+    };
+
+    let encoded = withdrawals
+        .iter()
+        .map(|withdrawal| withdrawal.rlp_bytes())
+        .collect::<Result<Vec<Vec<u8>>, Error>>()?;
+
+    compare_root(
+        "withdrawalsRoot",
+        ordered_trie_root(&encoded),
+        withdrawals_root,
+    )
+}
+
+/// recomputes the receipts trie root and compares it to `receiptsRoot`
+pub fn verify_receipts_root(block: &Block, receipts: &[Receipt]) -> Result<(), Error> {
+    let transactions_count = block.transactions.as_ref().map_or(0, Vec::len);
+    if receipts.len() != transactions_count {
+        return Err(Error::msg(format!(
+            "{} receipts for {} transactions",
+            receipts.len(),
+            transactions_count
+        )));
+    }
+
+    let receipts_root = block
+        .receipts_root
+        .as_deref()
+        .ok_or_else(|| Error::msg("missing receiptsRoot"))?;
+    let encoded = receipts
+        .iter()
+        .map(|receipt| receipt.consensus_bytes())
+        .collect::<Result<Vec<Vec<u8>>, Error>>()?;
+
+    compare_root("receiptsRoot", ordered_trie_root(&encoded), receipts_root)
+}
+
+fn compare_root(name: &str, computed: H256, expected: &str) -> Result<(), Error> {
+    if computed.as_bytes() != hex_bytes(expected)?.as_slice() {
+        return Err(Error::msg(format!(
+            "{} mismatch: block has {}, computed {:?}",
+            name, expected, computed
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use crate::utils::block::{Block, DecodeError, ExtraFields, Transaction, Withdrawal};
+use borsh_derive::BorshDeserialize;
+
+/// `Block` Borsh layout of schema version 0, the un-enveloped payloads
+/// archived before the envelope. Only ever decoded, never written.
+#[derive(Debug, BorshDeserialize)]
+pub struct BlockV0 {
+    pub base_fee_per_gas: Option<String>,
+    pub blob_gas_used: Option<String>,
+    pub difficulty: Option<String>,
+    pub excess_blob_gas: Option<String>,
+    pub extra_data: Option<String>,
+    pub gas_limit: Option<String>,
+    pub gas_used: Option<String>,
+    pub hash: Option<String>,
+    pub logs_bloom: Option<String>,
+    pub miner: Option<String>,
+    pub mix_hash: Option<String>,
+    pub nonce: Option<String>,
+    pub number: Option<String>,
+    pub parent_beacon_block_root: Option<String>,
+    pub parent_hash: Option<String>,
+    pub receipts_root: Option<String>,
+    pub sha3_uncles: Option<String>,
+    pub size: Option<String>,
+    pub state_root: Option<String>,
+    pub timestamp: Option<String>,
+    pub total_difficulty: Option<String>,
+    pub transactions: Option<Vec<TransactionV0>>,
+    pub uncles: Option<Vec<String>>,
+    pub withdrawals: Option<Vec<String>>,
+    pub withdrawals_root: Option<String>,
+}
+
+/// `Transaction` Borsh layout of schema version 0
+#[derive(Debug, BorshDeserialize)]
+pub struct TransactionV0 {
+    pub block_hash: Option<String>,
+    pub block_number: Option<String>,
+    pub chain_id: Option<String>,
+    pub from: Option<String>,
+    pub gas: Option<String>,
+    pub gas_price: Option<String>,
+    pub hash: Option<String>,
+    pub input: Option<String>,
+    pub nonce: Option<String>,
+    pub r: Option<String>,
+    pub s: Option<String>,
+    pub to: Option<String>,
+    pub transaction_index: Option<String>,
+    pub transaction_type: Option<String>,
+    pub v: Option<String>,
+    pub value: Option<String>,
+}
+
+impl TryFrom<BlockV0> for Block {
+    type Error = DecodeError;
+
+    fn try_from(block: BlockV0) -> Result<Block, DecodeError> {
+        // version 0 modeled withdrawals as strings, blocks with withdrawals
+        // failed to deserialize from the RPC and were never archived
+        let withdrawals = match block.withdrawals {
+            Some(withdrawals) if !withdrawals.is_empty() => {
+                return Err(DecodeError::InvalidBorsh(
+                    "version 0 block with withdrawals".to_string(),
+                ))
+            }
+            withdrawals => withdrawals.map(|_| Vec::new()),
+        };
+
+        Ok(Block {
+            base_fee_per_gas: block.base_fee_per_gas,
+            blob_gas_used: block.blob_gas_used,
+            difficulty: block.difficulty,
+            excess_blob_gas: block.excess_blob_gas,
+            extra_data: block.extra_data,
+            gas_limit: block.gas_limit,
+            gas_used: block.gas_used,
+            hash: block.hash,
+            logs_bloom: block.logs_bloom,
+            miner: block.miner,
+            mix_hash: block.mix_hash,
+            nonce: block.nonce,
+            number: block.number,
+            parent_beacon_block_root: block.parent_beacon_block_root,
+            parent_hash: block.parent_hash,
+            receipts_root: block.receipts_root,
+            requests_hash: None,
+            sha3_uncles: block.sha3_uncles,
+            size: block.size,
+            state_root: block.state_root,
+            timestamp: block.timestamp,
+            total_difficulty: block.total_difficulty,
+            transactions: block
+                .transactions
+                .map(|transactions| transactions.into_iter().map(Transaction::from).collect()),
+            transactions_root: None,
+            uncles: block.uncles,
+            withdrawals,
+            withdrawals_root: block.withdrawals_root,
+            other: ExtraFields::default(),
+        })
+    }
+}
+
+impl From<TransactionV0> for Transaction {
+    fn from(transaction: TransactionV0) -> Transaction {
+        Transaction {
+            access_list: None,
+            authorization_list: None,
+            blob_versioned_hashes: None,
+            block_hash: transaction.block_hash,
+            block_number: transaction.block_number,
+            chain_id: transaction.chain_id,
+            from: transaction.from,
+            gas: transaction.gas,
+            gas_price: transaction.gas_price,
+            hash: transaction.hash,
+            input: transaction.input,
+            max_fee_per_blob_gas: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: transaction.nonce,
+            r: transaction.r,
+            s: transaction.s,
+            to: transaction.to,
+            transaction_index: transaction.transaction_index,
+            transaction_type: transaction.transaction_type,
+            v: transaction.v,
+            value: transaction.value,
+            y_parity: None,
+            other: ExtraFields::default(),
+        }
+    }
+}
+
+impl Block {
+    /// The block as a schema version 0 payload holds it: the fields the
+    /// version 0 layout lacks are cleared, as decoding such a payload does.
+    /// Archives of version 0 are compared with the RPC block through it.
+    pub fn legacy_view(&self) -> Block {
+        Block {
+            base_fee_per_gas: self.base_fee_per_gas.clone(),
+            blob_gas_used: self.blob_gas_used.clone(),
+            difficulty: self.difficulty.clone(),
+            excess_blob_gas: self.excess_blob_gas.clone(),
+            extra_data: self.extra_data.clone(),
+            gas_limit: self.gas_limit.clone(),
+            gas_used: self.gas_used.clone(),
+            hash: self.hash.clone(),
+            logs_bloom: self.logs_bloom.clone(),
+            miner: self.miner.clone(),
+            mix_hash: self.mix_hash.clone(),
+            nonce: self.nonce.clone(),
+            number: self.number.clone(),
+            parent_beacon_block_root: self.parent_beacon_block_root.clone(),
+            parent_hash: self.parent_hash.clone(),
+            receipts_root: self.receipts_root.clone(),
+            requests_hash: None,
+            sha3_uncles: self.sha3_uncles.clone(),
+            size: self.size.clone(),
+            state_root: self.state_root.clone(),
+            timestamp: self.timestamp.clone(),
+            total_difficulty: self.total_difficulty.clone(),
+            transactions: self
+                .transactions
+                .as_ref()
+                .map(|transactions| transactions.iter().map(Transaction::legacy_view).collect()),
+            transactions_root: None,
+            uncles: self.uncles.clone(),
+            // version 0 can only hold an empty withdrawals list, a block
+            // with withdrawals keeps them and never matches
+            withdrawals: self.withdrawals.as_ref().map(|withdrawals| {
+                withdrawals
+                    .iter()
+                    .map(|withdrawal| Withdrawal {
+                        address: withdrawal.address.clone(),
+                        amount: withdrawal.amount.clone(),
+                        index: withdrawal.index.clone(),
+                        validator_index: withdrawal.validator_index.clone(),
+                    })
+                    .collect()
+            }),
+            withdrawals_root: self.withdrawals_root.clone(),
+            other: ExtraFields::default(),
+        }
+    }
+}
+
+impl Transaction {
+    /// the transaction as a schema version 0 payload holds it
+    pub fn legacy_view(&self) -> Transaction {
+        Transaction {
+            access_list: None,
+            authorization_list: None,
+            blob_versioned_hashes: None,
+            block_hash: self.block_hash.clone(),
+            block_number: self.block_number.clone(),
+            chain_id: self.chain_id.clone(),
+            from: self.from.clone(),
+            gas: self.gas.clone(),
+            gas_price: self.gas_price.clone(),
+            hash: self.hash.clone(),
+            input: self.input.clone(),
+            max_fee_per_blob_gas: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: self.nonce.clone(),
+            r: self.r.clone(),
+            s: self.s.clone(),
+            to: self.to.clone(),
+            transaction_index: self.transaction_index.clone(),
+            transaction_type: self.transaction_type.clone(),
+            v: self.v.clone(),
+            value: self.value.clone(),
+            y_parity: None,
+            other: ExtraFields::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::block::Block;
+    use crate::utils::test_fixtures::{fixture_bytes, rpc_block};
+
+    #[test]
+    fn version_0_payload_decodes_to_the_legacy_view() {
+        let payload = fixture_bytes("blocks/eip1559.v0.bin");
+
+        assert_eq!(
+            Block::from_payload(&payload).unwrap(),
+            rpc_block("eip1559").legacy_view()
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+
+/// inclusive `[start, end]` range of WeaveVM block numbers
+pub type BlockRange = [u32; 2];
+
+/// sorted set of non-overlapping, non-adjacent block ranges
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockRanges {
+    ranges: Vec<BlockRange>,
+}
+
+impl BlockRanges {
+    pub fn new() -> BlockRanges {
+        BlockRanges { ranges: Vec::new() }
+    }
+
+    /// compress an ascending, deduplicated list of block numbers into ranges
+    pub fn from_sorted(blocks: &[u32]) -> BlockRanges {
+        let mut ranges: Vec<BlockRange> = Vec::new();
+
+        for &block in blocks {
+            match ranges.last_mut() {
+                Some(last) if last[1].checked_add(1) == Some(block) => last[1] = block,
+                Some(last) if last[1] >= block => {}
+                _ => ranges.push([block, block]),
+            }
+        }
+
+        BlockRanges { ranges }
+    }
+
+    pub fn insert(&mut self, block: u32) {
+        self.insert_range(block, block);
+    }
+
+    /// insert `[start, end]`, merging it with any overlapping or adjacent range
+    pub fn insert_range(&mut self, start: u32, end: u32) {
+        if start > end {
+            return;
+        }
+
+        // first range that ends at or after `start - 1`, i.e. could touch the new one
+        let first = self
+            .ranges
+            .partition_point(|range| (range[1] as u64) + 1 < start as u64);
+        // first range that starts strictly after `end + 1`, i.e. cannot touch it
+        let last = self
+            .ranges
+            .partition_point(|range| range[0] as u64 <= end as u64 + 1);
+
+        let mut merged = [start, end];
+        if first < last {
+            merged[0] = merged[0].min(self.ranges[first][0]);
+            merged[1] = merged[1].max(self.ranges[last - 1][1]);
+        }
+
+        self.ranges.splice(first..last, [merged]);
+    }
+
+    pub fn extend(&mut self, other: &BlockRanges) {
+        for range in other.ranges.iter() {
+            self.insert_range(range[0], range[1]);
+        }
+    }
+
+    /// ranges inside `[start, end]` that are not covered by this set
+    pub fn complement(&self, start: u32, end: u32) -> BlockRanges {
+        let mut gaps: Vec<BlockRange> = Vec::new();
+        if start > end {
+            return BlockRanges { ranges: gaps };
+        }
+
+        let mut next: u64 = start as u64;
+        for range in self.ranges.iter() {
+            if range[1] < start {
+                continue;
+            }
+            if range[0] > end {
+                break;
+            }
+            if (range[0] as u64) > next {
+                gaps.push([next as u32, range[0] - 1]);
+            }
+            next = range[1] as u64 + 1;
+        }
+
+        if next <= end as u64 {
+            gaps.push([next as u32, end]);
+        }
+
+        BlockRanges { ranges: gaps }
+    }
+
+    pub fn contains(&self, block: u32) -> bool {
+        let idx = self.ranges.partition_point(|range| range[1] < block);
+        self.ranges
+            .get(idx)
+            .map(|range| range[0] <= block)
+            .unwrap_or(false)
+    }
+
+    pub fn ranges(&self) -> &[BlockRange] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// total number of blocks covered by all ranges
+    pub fn block_count(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| (range[1] - range[0]) as u64 + 1)
+            .sum()
+    }
+
+    pub fn iter_blocks(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ranges.iter().flat_map(|range| range[0]..=range[1])
+    }
+
+    /// the ranges split into consecutive ranges of at most `size` blocks
+    pub fn chunks(&self, size: u32) -> Vec<BlockRange> {
+        let size = size.max(1);
+        let mut chunks: Vec<BlockRange> = Vec::new();
+
+        for range in self.ranges.iter() {
+            let mut start = range[0];
+            loop {
+                let end = start.saturating_add(size - 1).min(range[1]);
+                chunks.push([start, end]);
+                if end == range[1] {
+                    break;
+                }
+                start = end + 1;
+            }
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges_of(ranges: &[BlockRange]) -> BlockRanges {
+        let mut set = BlockRanges::new();
+        for range in ranges {
+            set.insert_range(range[0], range[1]);
+        }
+        set
+    }
+
+    #[test]
+    fn insert_range_merges_overlapping_and_adjacent_ranges() {
+        let mut set = ranges_of(&[[10, 20], [30, 40]]);
+        assert_eq!(set.ranges(), &[[10, 20], [30, 40]]);
+
+        // adjacent on both sides
+        set.insert_range(21, 29);
+        assert_eq!(set.ranges(), &[[10, 40]]);
+
+        // one block apart stays separate, then gets bridged
+        set.insert_range(42, 50);
+        assert_eq!(set.ranges(), &[[10, 40], [42, 50]]);
+        set.insert(41);
+        assert_eq!(set.ranges(), &[[10, 50]]);
+
+        // overlapping several ranges, and already covered
+        let mut set = ranges_of(&[[0, 0], [5, 6], [9, 12], [20, 20]]);
+        set.insert_range(4, 10);
+        assert_eq!(set.ranges(), &[[0, 0], [4, 12], [20, 20]]);
+        set.insert_range(5, 11);
+        assert_eq!(set.ranges(), &[[0, 0], [4, 12], [20, 20]]);
+
+        // an empty range is ignored
+        set.insert_range(15, 14);
+        assert_eq!(set.block_count(), 11);
+    }
+
+    #[test]
+    fn insert_range_at_the_u32_bounds() {
+        let mut set = ranges_of(&[[u32::MAX, u32::MAX], [0, 0]]);
+        assert_eq!(set.ranges(), &[[0, 0], [u32::MAX, u32::MAX]]);
+
+        set.insert_range(u32::MAX - 5, u32::MAX - 1);
+        set.insert(1);
+        assert_eq!(set.ranges(), &[[0, 1], [u32::MAX - 5, u32::MAX]]);
+        assert!(set.contains(u32::MAX));
+        assert!(!set.contains(u32::MAX - 6));
+    }
+
+    #[test]
+    fn complement_at_the_bounds() {
+        let set = ranges_of(&[[10, 20], [30, 40]]);
+
+        assert_eq!(set.complement(10, 40).ranges(), &[[21, 29]]);
+        assert_eq!(
+            set.complement(9, 41).ranges(),
+            &[[9, 9], [21, 29], [41, 41]]
+        );
+        assert_eq!(set.complement(15, 35).ranges(), &[[21, 29]]);
+        assert_eq!(set.complement(21, 29).ranges(), &[[21, 29]]);
+        assert!(set.complement(12, 18).is_empty());
+        assert_eq!(set.complement(0, 5).ranges(), &[[0, 5]]);
+        assert_eq!(set.complement(50, 60).ranges(), &[[50, 60]]);
+        assert!(set.complement(6, 5).is_empty());
+
+        let set = ranges_of(&[[0, 10], [u32::MAX - 1, u32::MAX]]);
+        assert_eq!(set.complement(0, u32::MAX).ranges(), &[[11, u32::MAX - 2]]);
+        assert_eq!(
+            BlockRanges::new().complement(0, u32::MAX).ranges(),
+            &[[0, u32::MAX]]
+        );
+    }
+
+    #[test]
+    fn from_sorted_skips_duplicates() {
+        let set = BlockRanges::from_sorted(&[1, 1, 2, 3, 3, 7, 7, 9, u32::MAX, u32::MAX]);
+        assert_eq!(
+            set.ranges(),
+            &[[1, 3], [7, 7], [9, 9], [u32::MAX, u32::MAX]]
+        );
+        assert_eq!(set.block_count(), 6);
+        assert!(BlockRanges::from_sorted(&[]).is_empty());
+    }
+
+    #[test]
+    fn chunks_split_ranges_up_to_u32_max() {
+        let set = ranges_of(&[[0, 4], [10, 10]]);
+        assert_eq!(set.chunks(2), vec![[0, 1], [2, 3], [4, 4], [10, 10]]);
+        // a size of 0 is read as 1
+        assert_eq!(set.chunks(0).len(), 6);
+
+        let set = ranges_of(&[[u32::MAX - 4, u32::MAX]]);
+        assert_eq!(
+            set.chunks(2),
+            vec![
+                [u32::MAX - 4, u32::MAX - 3],
+                [u32::MAX - 2, u32::MAX - 1],
+                [u32::MAX, u32::MAX]
+            ]
+        );
+        assert_eq!(set.chunks(u32::MAX), vec![[u32::MAX - 4, u32::MAX]]);
+        assert_eq!(
+            ranges_of(&[[0, u32::MAX]]).chunks(u32::MAX),
+            vec![[0, u32::MAX - 1], [u32::MAX, u32::MAX]]
+        );
+    }
+}
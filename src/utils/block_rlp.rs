@@ -0,0 +1,394 @@
+use crate::utils::block::{
+    AccessListItem, Authorization, Block, Log, Receipt, Transaction, Withdrawal,
+};
+use anyhow::Error;
+use ethers::types::{H160, H256, U256};
+use ethers::utils::keccak256;
+use ethers::utils::rlp::RlpStream;
+
+/// `0x` prefixed hex data to bytes
+pub fn hex_bytes(value: &str) -> Result<Vec<u8>, Error> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    ethers::utils::hex::decode(digits)
+        .map_err(|e| Error::msg(format!("invalid hex data {}: {}", value, e)))
+}
+
+/// bytes to `0x` prefixed hex data
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", ethers::utils::hex::encode(bytes))
+}
+
+/// `0x` prefixed hex quantity
+pub fn hex_quantity(value: &str) -> Result<U256, Error> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    U256::from_str_radix(digits, 16)
+        .map_err(|e| Error::msg(format!("invalid hex quantity {}: {}", value, e)))
+}
+
+pub fn hex_address(value: &str) -> Result<H160, Error> {
+    let bytes = hex_bytes(value)?;
+    if bytes.len() != 20 {
+        return Err(Error::msg(format!("invalid address {}", value)));
+    }
+    Ok(H160::from_slice(&bytes))
+}
+
+/// value of a field the RLP encoding can't do without
+pub fn required<'a>(field: &'a Option<String>, name: &str) -> Result<&'a str, Error> {
+    field
+        .as_deref()
+        .ok_or_else(|| Error::msg(format!("missing {} field", name)))
+}
+
+fn quantity(field: &Option<String>, name: &str) -> Result<U256, Error> {
+    hex_quantity(required(field, name)?)
+}
+
+fn bytes(field: &Option<String>, name: &str) -> Result<Vec<u8>, Error> {
+    hex_bytes(required(field, name)?)
+}
+
+impl Block {
+    /// RLP of the consensus header, `keccak256` of it is the block hash.
+    /// Fork specific fields are appended in activation order, up to the last
+    /// one the block carries.
+    pub fn header_rlp(&self) -> Result<Vec<u8>, Error> {
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        stream.append(&bytes(&self.parent_hash, "parentHash")?);
+        stream.append(&bytes(&self.sha3_uncles, "sha3Uncles")?);
+        stream.append(&hex_address(required(&self.miner, "miner")?)?);
+        stream.append(&bytes(&self.state_root, "stateRoot")?);
+        stream.append(&bytes(&self.transactions_root, "transactionsRoot")?);
+        stream.append(&bytes(&self.receipts_root, "receiptsRoot")?);
+        stream.append(&bytes(&self.logs_bloom, "logsBloom")?);
+        stream.append(&quantity(&self.difficulty, "difficulty")?);
+        stream.append(&quantity(&self.number, "number")?);
+        stream.append(&quantity(&self.gas_limit, "gasLimit")?);
+        stream.append(&quantity(&self.gas_used, "gasUsed")?);
+        stream.append(&quantity(&self.timestamp, "timestamp")?);
+        stream.append(&bytes(&self.extra_data, "extraData")?);
+        stream.append(&bytes(&self.mix_hash, "mixHash")?);
+        stream.append(&bytes(&self.nonce, "nonce")?);
+
+        // (field, name, is a quantity) in fork order: London, Shanghai,
+        // Cancun, Cancun, Cancun, Prague
+        let fork_fields = [
+            (&self.base_fee_per_gas, "baseFeePerGas", true),
+            (&self.withdrawals_root, "withdrawalsRoot", false),
+            (&self.blob_gas_used, "blobGasUsed", true),
+            (&self.excess_blob_gas, "excessBlobGas", true),
+            (
+                &self.parent_beacon_block_root,
+                "parentBeaconBlockRoot",
+                false,
+            ),
+            (&self.requests_hash, "requestsHash", false),
+        ];
+        let present = fork_fields
+            .iter()
+            .rposition(|(field, _, _)| field.is_some())
+            .map_or(0, |last| last + 1);
+
+        // a later fork field implies every earlier one
+        for (field, name, is_quantity) in fork_fields[..present].iter() {
+            if *is_quantity {
+                stream.append(&quantity(field, name)?);
+            } else {
+                stream.append(&bytes(field, name)?);
+            }
+        }
+
+        stream.finalize_unbounded_list();
+        Ok(stream.out().to_vec())
+    }
+
+    /// Canonical block RLP `[header, transactions, ommers, withdrawals]`,
+    /// the format of a `reth import` chain file (a concatenation of them).
+    /// Withdrawals are only present from Shanghai on.
+    pub fn to_rlp(&self) -> Result<Vec<u8>, Error> {
+        let transactions: &[Transaction] = self.transactions.as_deref().unwrap_or_default();
+        // the RPC only returns uncle hashes, not the headers the body needs
+        if !self.uncles.as_deref().unwrap_or_default().is_empty() {
+            return Err(Error::msg("blocks with uncles can't be RLP encoded"));
+        }
+
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        stream.append_raw(&self.header_rlp()?, 1);
+
+        stream.begin_list(transactions.len());
+        for transaction in transactions {
+            let consensus_bytes = transaction.consensus_bytes()?;
+            // typed transactions are nested as byte strings, legacy ones as lists
+            if transaction.transaction_type()? == 0 {
+                stream.append_raw(&consensus_bytes, 1);
+            } else {
+                stream.append(&consensus_bytes);
+            }
+        }
+
+        stream.begin_list(0);
+
+        if let Some(withdrawals) = &self.withdrawals {
+            stream.begin_list(withdrawals.len());
+            for withdrawal in withdrawals {
+                stream.append_raw(&withdrawal.rlp_bytes()?, 1);
+            }
+        }
+
+        stream.finalize_unbounded_list();
+        Ok(stream.out().to_vec())
+    }
+}
+
+impl Transaction {
+    pub fn transaction_type(&self) -> Result<u64, Error> {
+        match &self.transaction_type {
+            Some(transaction_type) => Ok(hex_quantity(transaction_type)?.low_u64()),
+            None => Ok(0),
+        }
+    }
+
+    /// `yParity` of typed transactions, older nodes only return it as `v`
+    fn y_parity(&self) -> Result<U256, Error> {
+        match (&self.y_parity, &self.v) {
+            (Some(y_parity), _) => hex_quantity(y_parity),
+            (None, Some(v)) => hex_quantity(v),
+            (None, None) => Err(Error::msg("missing yParity field")),
+        }
+    }
+
+    /// signed EIP-2718 encoding, `keccak256` of it is the transaction hash
+    /// and it is the value stored in the transactions trie
+    pub fn consensus_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        self.append_unsigned_fields(&mut stream)?;
+
+        if self.transaction_type()? == 0 {
+            stream.append(&quantity(&self.v, "v")?);
+        } else {
+            stream.append(&self.y_parity()?);
+        }
+        stream.append(&quantity(&self.r, "r")?);
+        stream.append(&quantity(&self.s, "s")?);
+        stream.finalize_unbounded_list();
+
+        typed_envelope(&self.transaction_type, stream.out().to_vec())
+    }
+
+    /// hash signed by the sender, `from` is recovered from it
+    pub fn signing_hash(&self) -> Result<H256, Error> {
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        self.append_unsigned_fields(&mut stream)?;
+
+        // EIP-155 legacy transactions sign over `chainId, 0, 0` too
+        if self.transaction_type()? == 0 {
+            let v = quantity(&self.v, "v")?;
+            if v >= U256::from(35) {
+                stream.append(&((v - 35) / 2));
+                stream.append(&0u8);
+                stream.append(&0u8);
+            }
+        }
+        stream.finalize_unbounded_list();
+
+        let payload = typed_envelope(&self.transaction_type, stream.out().to_vec())?;
+        Ok(H256::from(keccak256(payload)))
+    }
+
+    /// every field but the signature, in the order of the transaction type
+    fn append_unsigned_fields(&self, stream: &mut RlpStream) -> Result<(), Error> {
+        let transaction_type = self.transaction_type()?;
+
+        match transaction_type {
+            0 => {
+                stream.append(&quantity(&self.nonce, "nonce")?);
+                stream.append(&quantity(&self.gas_price, "gasPrice")?);
+                stream.append(&quantity(&self.gas, "gas")?);
+                self.append_to(stream)?;
+                stream.append(&quantity(&self.value, "value")?);
+                stream.append(&bytes(&self.input, "input")?);
+            }
+            1 => {
+                stream.append(&quantity(&self.chain_id, "chainId")?);
+                stream.append(&quantity(&self.nonce, "nonce")?);
+                stream.append(&quantity(&self.gas_price, "gasPrice")?);
+                stream.append(&quantity(&self.gas, "gas")?);
+                self.append_to(stream)?;
+                stream.append(&quantity(&self.value, "value")?);
+                stream.append(&bytes(&self.input, "input")?);
+                self.append_access_list(stream)?;
+            }
+            2..=4 => {
+                stream.append(&quantity(&self.chain_id, "chainId")?);
+                stream.append(&quantity(&self.nonce, "nonce")?);
+                stream.append(&quantity(
+                    &self.max_priority_fee_per_gas,
+                    "maxPriorityFeePerGas",
+                )?);
+                stream.append(&quantity(&self.max_fee_per_gas, "maxFeePerGas")?);
+                stream.append(&quantity(&self.gas, "gas")?);
+                self.append_to(stream)?;
+                stream.append(&quantity(&self.value, "value")?);
+                stream.append(&bytes(&self.input, "input")?);
+                self.append_access_list(stream)?;
+
+                if transaction_type == 3 {
+                    stream.append(&quantity(&self.max_fee_per_blob_gas, "maxFeePerBlobGas")?);
+                    let hashes = self
+                        .blob_versioned_hashes
+                        .as_deref()
+                        .ok_or_else(|| Error::msg("missing blobVersionedHashes field"))?;
+                    stream.begin_list(hashes.len());
+                    for hash in hashes {
+                        stream.append(&hex_bytes(hash)?);
+                    }
+                }
+                if transaction_type == 4 {
+                    let authorizations = self
+                        .authorization_list
+                        .as_deref()
+                        .ok_or_else(|| Error::msg("missing authorizationList field"))?;
+                    stream.begin_list(authorizations.len());
+                    for authorization in authorizations {
+                        authorization.rlp_append(stream)?;
+                    }
+                }
+            }
+            other => {
+                return Err(Error::msg(format!(
+                    "unsupported transaction type {}",
+                    other
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// contract creations have no `to`, encoded as empty bytes
+    fn append_to(&self, stream: &mut RlpStream) -> Result<(), Error> {
+        match &self.to {
+            Some(to) => stream.append(&hex_address(to)?),
+            None => stream.append_empty_data(),
+        };
+        Ok(())
+    }
+
+    fn append_access_list(&self, stream: &mut RlpStream) -> Result<(), Error> {
+        let access_list: &[AccessListItem] = self.access_list.as_deref().unwrap_or_default();
+        stream.begin_list(access_list.len());
+        for item in access_list {
+            let storage_keys: &[String] = item.storage_keys.as_deref().unwrap_or_default();
+            stream.begin_list(2);
+            stream.append(&hex_address(required(&item.address, "address")?)?);
+            stream.begin_list(storage_keys.len());
+            for storage_key in storage_keys {
+                stream.append(&hex_bytes(storage_key)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Authorization {
+    /// appends `[chainId, address, nonce, yParity, r, s]`
+    pub fn rlp_append(&self, stream: &mut RlpStream) -> Result<(), Error> {
+        stream.begin_list(6);
+        stream.append(&quantity(&self.chain_id, "chainId")?);
+        stream.append(&hex_address(required(&self.address, "address")?)?);
+        stream.append(&quantity(&self.nonce, "nonce")?);
+        stream.append(&quantity(&self.y_parity, "yParity")?);
+        stream.append(&quantity(&self.r, "r")?);
+        stream.append(&quantity(&self.s, "s")?);
+        Ok(())
+    }
+}
+
+impl Withdrawal {
+    /// `rlp([index, validatorIndex, address, amount])`
+    pub fn rlp_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&hex_quantity(required(&self.index, "index")?)?);
+        stream.append(&hex_quantity(required(
+            &self.validator_index,
+            "validatorIndex",
+        )?)?);
+        stream.append(&hex_address(required(&self.address, "address")?)?);
+        stream.append(&hex_quantity(required(&self.amount, "amount")?)?);
+        Ok(stream.out().to_vec())
+    }
+}
+
+impl Receipt {
+    /// consensus encoding `type || rlp([status, cumulativeGasUsed, logsBloom,
+    /// logs])`, without the type prefix for legacy receipts
+    pub fn consensus_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut stream = RlpStream::new_list(4);
+        match (&self.status, &self.root) {
+            (Some(status), _) => stream.append(&hex_quantity(status)?),
+            // pre-Byzantium receipts carry the post-state root instead
+            (None, Some(root)) => stream.append(&hex_bytes(root)?),
+            (None, None) => return Err(Error::msg("receipt without status or root")),
+        };
+        stream.append(&hex_quantity(required(
+            &self.cumulative_gas_used,
+            "cumulativeGasUsed",
+        )?)?);
+        stream.append(&hex_bytes(required(&self.logs_bloom, "logsBloom")?)?);
+
+        let logs: &[Log] = self.logs.as_deref().unwrap_or_default();
+        stream.begin_list(logs.len());
+        for log in logs {
+            log.rlp_append(&mut stream)?;
+        }
+
+        typed_envelope(&self.transaction_type, stream.out().to_vec())
+    }
+}
+
+impl Log {
+    /// appends `[address, topics, data]`
+    pub fn rlp_append(&self, stream: &mut RlpStream) -> Result<(), Error> {
+        let topics: &[String] = self.topics.as_deref().unwrap_or_default();
+
+        stream.begin_list(3);
+        stream.append(&hex_address(required(&self.address, "address")?)?);
+        stream.begin_list(topics.len());
+        for topic in topics {
+            stream.append(&hex_bytes(topic)?);
+        }
+        stream.append(&hex_bytes(required(&self.data, "data")?)?);
+        Ok(())
+    }
+}
+
+/// EIP-2718 envelope: typed payloads are prefixed with their type byte,
+/// legacy (type 0) ones are left as is
+pub fn typed_envelope(
+    transaction_type: &Option<String>,
+    payload: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let transaction_type = match transaction_type {
+        Some(transaction_type) => hex_quantity(transaction_type)?,
+        None => U256::zero(),
+    };
+
+    if transaction_type.is_zero() {
+        return Ok(payload);
+    }
+    if transaction_type > U256::from(0x7f) {
+        return Err(Error::msg(format!(
+            "invalid transaction type {}",
+            transaction_type
+        )));
+    }
+
+    let mut enveloped = Vec::with_capacity(payload.len() + 1);
+    enveloped.push(transaction_type.as_u32() as u8);
+    enveloped.extend(payload);
+    Ok(enveloped)
+}
@@ -0,0 +1,367 @@
+use crate::utils::block::{
+    AccessListItem, Authorization, Block, DecodeError, ExtraFields, Transaction, Withdrawal,
+};
+use crate::utils::block_rlp::{hex_quantity, to_hex};
+use anyhow::Error;
+use ethers::types::{Signature, U256};
+use ethers::utils::keccak256;
+use ethers::utils::rlp::{DecoderError, Rlp};
+
+impl Block {
+    /// Decodes a canonical block RLP (see `to_rlp`) into the JSON-RPC shape.
+    /// Fields the RPC derives are rebuilt (hashes, size, senders, effective
+    /// gas prices), RPC only ones like `totalDifficulty` stay empty.
+    pub fn from_rlp(bytes: &[u8]) -> Result<Block, DecodeError> {
+        decode_block(bytes).map_err(|e| match e.downcast_ref::<DecoderError>() {
+            Some(DecoderError::RlpIsTooShort) => DecodeError::Truncated,
+            _ => DecodeError::InvalidRlp(e.to_string()),
+        })
+    }
+}
+
+fn decode_block(bytes: &[u8]) -> Result<Block, Error> {
+    let rlp = Rlp::new(bytes);
+    if rlp.payload_info()?.total() != bytes.len() {
+        return Err(Error::msg("trailing bytes after the block RLP"));
+    }
+    let item_count = rlp.item_count()?;
+    if !(3..=4).contains(&item_count) {
+        return Err(Error::msg(format!("block RLP with {} items", item_count)));
+    }
+    if rlp.at(2)?.item_count()? != 0 {
+        return Err(Error::msg("block RLP with ommers"));
+    }
+
+    let mut block = decode_header(&rlp.at(0)?)?;
+    block.size = Some(format!("0x{:x}", bytes.len()));
+    block.uncles = Some(Vec::new());
+
+    let base_fee_per_gas = block
+        .base_fee_per_gas
+        .as_deref()
+        .map(hex_quantity)
+        .transpose()?;
+    let mut transactions: Vec<Transaction> = Vec::new();
+    for (index, item) in rlp.at(1)?.iter().enumerate() {
+        let mut transaction = decode_transaction(&item)?;
+        transaction.block_hash = block.hash.clone();
+        transaction.block_number = block.number.clone();
+        transaction.transaction_index = Some(format!("0x{:x}", index));
+
+        // the RPC reports the effective gas price of dynamic fee transactions
+        if let (Some(base_fee), Some(max_fee), Some(priority_fee)) = (
+            base_fee_per_gas,
+            transaction.max_fee_per_gas.as_deref(),
+            transaction.max_priority_fee_per_gas.as_deref(),
+        ) {
+            let effective = hex_quantity(max_fee)?.min(base_fee + hex_quantity(priority_fee)?);
+            transaction.gas_price = Some(format!("0x{:x}", effective));
+        }
+        transactions.push(transaction);
+    }
+    block.transactions = Some(transactions);
+
+    if item_count == 4 {
+        block.withdrawals = Some(
+            rlp.at(3)?
+                .iter()
+                .map(|withdrawal| decode_withdrawal(&withdrawal))
+                .collect::<Result<Vec<Withdrawal>, Error>>()?,
+        );
+    }
+
+    Ok(block)
+}
+
+fn decode_header(header: &Rlp) -> Result<Block, Error> {
+    let count = header.item_count()?;
+    if !(15..=21).contains(&count) {
+        return Err(Error::msg(format!("header RLP with {} fields", count)));
+    }
+    // fork specific fields, present up to the last one the block carries
+    let optional_quantity = |index: usize| match index < count {
+        true => quantity_at(header, index),
+        false => Ok(None),
+    };
+    let optional_data = |index: usize| match index < count {
+        true => data_at(header, index),
+        false => Ok(None),
+    };
+
+    Ok(Block {
+        base_fee_per_gas: optional_quantity(15)?,
+        blob_gas_used: optional_quantity(17)?,
+        difficulty: quantity_at(header, 7)?,
+        excess_blob_gas: optional_quantity(18)?,
+        extra_data: data_at(header, 12)?,
+        gas_limit: quantity_at(header, 9)?,
+        gas_used: quantity_at(header, 10)?,
+        hash: Some(to_hex(&keccak256(header.as_raw()))),
+        logs_bloom: data_at(header, 6)?,
+        miner: data_at(header, 2)?,
+        mix_hash: data_at(header, 13)?,
+        nonce: data_at(header, 14)?,
+        number: quantity_at(header, 8)?,
+        parent_beacon_block_root: optional_data(19)?,
+        parent_hash: data_at(header, 0)?,
+        receipts_root: data_at(header, 5)?,
+        requests_hash: optional_data(20)?,
+        sha3_uncles: data_at(header, 1)?,
+        size: None,
+        state_root: data_at(header, 3)?,
+        timestamp: quantity_at(header, 11)?,
+        total_difficulty: None,
+        transactions: None,
+        transactions_root: data_at(header, 4)?,
+        uncles: None,
+        withdrawals: None,
+        withdrawals_root: optional_data(16)?,
+        other: ExtraFields::default(),
+    })
+}
+
+/// a transactions list item, legacy transactions are nested as lists and
+/// typed ones as `type || rlp(fields)` byte strings
+fn decode_transaction(item: &Rlp) -> Result<Transaction, Error> {
+    let (transaction_type, consensus_bytes, fields_bytes) = if item.is_list() {
+        (0u8, item.as_raw(), item.as_raw())
+    } else {
+        let data = item.data()?;
+        let (transaction_type, fields_bytes) = data
+            .split_first()
+            .ok_or_else(|| Error::msg("empty typed transaction"))?;
+        (*transaction_type, data, fields_bytes)
+    };
+
+    let fields = Rlp::new(fields_bytes);
+    if fields.payload_info()?.total() != fields_bytes.len() {
+        return Err(Error::msg("trailing bytes after the transaction RLP"));
+    }
+
+    let mut transaction = Transaction {
+        access_list: None,
+        authorization_list: None,
+        blob_versioned_hashes: None,
+        block_hash: None,
+        block_number: None,
+        chain_id: None,
+        from: None,
+        gas: None,
+        gas_price: None,
+        hash: Some(to_hex(&keccak256(consensus_bytes))),
+        input: None,
+        max_fee_per_blob_gas: None,
+        max_fee_per_gas: None,
+        max_priority_fee_per_gas: None,
+        nonce: None,
+        r: None,
+        s: None,
+        to: None,
+        transaction_index: None,
+        transaction_type: Some(format!("0x{:x}", transaction_type)),
+        v: None,
+        value: None,
+        y_parity: None,
+        other: ExtraFields::default(),
+    };
+
+    // index of the first signature field
+    let signature_index = match transaction_type {
+        0 => {
+            transaction.nonce = quantity_at(&fields, 0)?;
+            transaction.gas_price = quantity_at(&fields, 1)?;
+            transaction.gas = quantity_at(&fields, 2)?;
+            transaction.to = address_at(&fields, 3)?;
+            transaction.value = quantity_at(&fields, 4)?;
+            transaction.input = data_at(&fields, 5)?;
+            6
+        }
+        1 => {
+            transaction.chain_id = quantity_at(&fields, 0)?;
+            transaction.nonce = quantity_at(&fields, 1)?;
+            transaction.gas_price = quantity_at(&fields, 2)?;
+            transaction.gas = quantity_at(&fields, 3)?;
+            transaction.to = address_at(&fields, 4)?;
+            transaction.value = quantity_at(&fields, 5)?;
+            transaction.input = data_at(&fields, 6)?;
+            transaction.access_list = Some(decode_access_list(&fields.at(7)?)?);
+            8
+        }
+        2..=4 => {
+            transaction.chain_id = quantity_at(&fields, 0)?;
+            transaction.nonce = quantity_at(&fields, 1)?;
+            transaction.max_priority_fee_per_gas = quantity_at(&fields, 2)?;
+            transaction.max_fee_per_gas = quantity_at(&fields, 3)?;
+            transaction.gas = quantity_at(&fields, 4)?;
+            transaction.to = address_at(&fields, 5)?;
+            transaction.value = quantity_at(&fields, 6)?;
+            transaction.input = data_at(&fields, 7)?;
+            transaction.access_list = Some(decode_access_list(&fields.at(8)?)?);
+
+            match transaction_type {
+                3 => {
+                    transaction.max_fee_per_blob_gas = quantity_at(&fields, 9)?;
+                    transaction.blob_versioned_hashes = Some(
+                        fields
+                            .at(10)?
+                            .iter()
+                            .map(|hash| Ok(to_hex(hash.data()?)))
+                            .collect::<Result<Vec<String>, Error>>()?,
+                    );
+                    11
+                }
+                4 => {
+                    transaction.authorization_list = Some(
+                        fields
+                            .at(9)?
+                            .iter()
+                            .map(|authorization| decode_authorization(&authorization))
+                            .collect::<Result<Vec<Authorization>, Error>>()?,
+                    );
+                    10
+                }
+                _ => 9,
+            }
+        }
+        other => {
+            return Err(Error::msg(format!(
+                "unsupported transaction type {}",
+                other
+            )))
+        }
+    };
+
+    if fields.item_count()? != signature_index + 3 {
+        return Err(Error::msg(format!(
+            "type {} transaction RLP with {} fields",
+            transaction_type,
+            fields.item_count()?
+        )));
+    }
+    let v: U256 = fields.val_at(signature_index)?;
+    transaction.v = Some(format!("0x{:x}", v));
+    transaction.r = quantity_at(&fields, signature_index + 1)?;
+    transaction.s = quantity_at(&fields, signature_index + 2)?;
+
+    if transaction_type == 0 {
+        // EIP-155 signatures carry the chain id in `v`
+        if v >= U256::from(35) {
+            transaction.chain_id = Some(format!("0x{:x}", (v - 35) / 2));
+        }
+    } else {
+        transaction.y_parity = transaction.v.clone();
+    }
+
+    let signature = Signature {
+        r: fields.val_at(signature_index + 1)?,
+        s: fields.val_at(signature_index + 2)?,
+        v: v.low_u64(),
+    };
+    let sender = signature.recover(transaction.signing_hash()?)?;
+    transaction.from = Some(to_hex(sender.as_bytes()));
+
+    Ok(transaction)
+}
+
+fn decode_access_list(access_list: &Rlp) -> Result<Vec<AccessListItem>, Error> {
+    access_list
+        .iter()
+        .map(|item| {
+            Ok(AccessListItem {
+                address: address_at(&item, 0)?,
+                storage_keys: Some(
+                    item.at(1)?
+                        .iter()
+                        .map(|key| Ok(to_hex(key.data()?)))
+                        .collect::<Result<Vec<String>, Error>>()?,
+                ),
+            })
+        })
+        .collect()
+}
+
+/// `[chainId, address, nonce, yParity, r, s]`
+fn decode_authorization(authorization: &Rlp) -> Result<Authorization, Error> {
+    Ok(Authorization {
+        address: address_at(authorization, 1)?,
+        chain_id: quantity_at(authorization, 0)?,
+        nonce: quantity_at(authorization, 2)?,
+        r: quantity_at(authorization, 4)?,
+        s: quantity_at(authorization, 5)?,
+        y_parity: quantity_at(authorization, 3)?,
+    })
+}
+
+/// `[index, validatorIndex, address, amount]`
+fn decode_withdrawal(withdrawal: &Rlp) -> Result<Withdrawal, Error> {
+    Ok(Withdrawal {
+        address: address_at(withdrawal, 2)?,
+        amount: quantity_at(withdrawal, 3)?,
+        index: quantity_at(withdrawal, 0)?,
+        validator_index: quantity_at(withdrawal, 1)?,
+    })
+}
+
+fn quantity_at(rlp: &Rlp, index: usize) -> Result<Option<String>, Error> {
+    let value: U256 = rlp.val_at(index)?;
+    Ok(Some(format!("0x{:x}", value)))
+}
+
+fn data_at(rlp: &Rlp, index: usize) -> Result<Option<String>, Error> {
+    Ok(Some(to_hex(rlp.at(index)?.data()?)))
+}
+
+/// empty for contract creations
+fn address_at(rlp: &Rlp, index: usize) -> Result<Option<String>, Error> {
+    let address = rlp.at(index)?.data()?;
+    match address.len() {
+        0 => Ok(None),
+        20 => Ok(Some(to_hex(address))),
+        len => Err(Error::msg(format!("address of {} bytes", len))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::block_integrity::verify_block_integrity;
+    use crate::utils::test_fixtures::{rpc_block, RPC_BLOCKS};
+
+    #[test]
+    fn rlp_roundtrip() {
+        for name in RPC_BLOCKS {
+            let block = rpc_block(name);
+            verify_block_integrity(&block).unwrap();
+
+            let rlp = block.to_rlp().unwrap();
+            let decoded = Block::from_rlp(&rlp).unwrap();
+            assert_eq!(decoded.to_rlp().unwrap(), rlp, "{}", name);
+            assert_eq!(decoded.hash, block.hash, "{}", name);
+            assert_eq!(decoded.size, block.size, "{}", name);
+            assert_eq!(decoded.withdrawals, block.withdrawals, "{}", name);
+
+            // everything but the RPC only fields comes back
+            let transactions = decoded.transactions.unwrap();
+            for (decoded, transaction) in transactions.iter().zip(block.transactions.unwrap()) {
+                assert_eq!(decoded.hash, transaction.hash, "{}", name);
+                assert_eq!(decoded.from, transaction.from, "{}", name);
+                assert_eq!(decoded.gas_price, transaction.gas_price, "{}", name);
+                assert_eq!(decoded.authorization_list, transaction.authorization_list);
+                assert_eq!(
+                    decoded.blob_versioned_hashes,
+                    transaction.blob_versioned_hashes
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_rlp() {
+        let rlp = rpc_block("eip1559").to_rlp().unwrap();
+
+        assert_eq!(
+            Block::from_rlp(&rlp[..rlp.len() - 1]),
+            Err(DecodeError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use crate::utils::archive_encoding::ArchiveEncoding;
+use crate::utils::block_integrity::verify_receipts_root;
+use crate::utils::block_ranges::BlockRange;
+use crate::utils::compression::{
+    parse_compressor, save_dictionary, train_dictionary, Compressor, ZstdDictionaryCompressor,
+};
+use crate::utils::env_var::{get_env_flag, get_env_var};
+use crate::utils::wvm_client::{retrieve_block_receipts, retrieve_block_with_txs};
+use anyhow::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DEFAULT_BENCHMARK_CODECS: &str = "brotli:11:22,brotli:6:22,zstd:3,zstd:19";
+const DICTIONARY_MAX_SIZE: usize = 112_640;
+const DICTIONARY_LEVEL: i32 = 19;
+
+/// Parses the `codec_benchmark` env var, `<start>-<end>`. When set the
+/// service benchmarks codecs over that range instead of backfilling
+pub fn benchmark_range_from_env() -> Result<Option<BlockRange>, Error> {
+    let config = match get_env_var("codec_benchmark") {
+        Ok(config) => config,
+        Err(_) => return Ok(None),
+    };
+    let invalid = || Error::msg(format!("invalid codec_benchmark value: {}", config));
+
+    let (start, end) = config.trim().split_once('-').ok_or_else(invalid)?;
+    let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+    let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok(Some([start, end]))
+}
+
+struct CodecReport {
+    compressed_bytes: usize,
+    compress_time: Duration,
+    decompress_time: Duration,
+}
+
+/// Compresses the payload bodies of the blocks in `range` with every codec of
+/// the `benchmark_codecs` env var, and with a zstd dictionary trained on the
+/// first half of the range, then prints size and CPU time per codec.
+/// Codecs are measured on the second half, the dictionary never sees it.
+pub async fn benchmark_codecs(range: BlockRange) -> Result<(), Error> {
+    let encoding = ArchiveEncoding::from_env(get_env_flag("archive_receipts"))?;
+
+    let mut bodies: Vec<Vec<u8>> = Vec::new();
+    for block_number in range[0]..=range[1] {
+        let block = retrieve_block_with_txs(block_number).await?;
+        let receipts = if encoding.includes_receipts() {
+            let receipts = retrieve_block_receipts(&block).await?;
+            verify_receipts_root(&block, &receipts)?;
+            receipts
+        } else {
+            Vec::new()
+        };
+        bodies.push(encoding.body(block, receipts)?);
+    }
+
+    let (training, measured) = bodies.split_at(bodies.len() / 2);
+    if measured.is_empty() {
+        return Err(Error::msg("codec benchmark range holds no blocks"));
+    }
+
+    let codecs =
+        get_env_var("benchmark_codecs").unwrap_or_else(|_| DEFAULT_BENCHMARK_CODECS.to_string());
+    let mut compressors: Vec<Box<dyn Compressor>> = codecs
+        .split(',')
+        .map(parse_compressor)
+        .collect::<Result<_, _>>()?;
+
+    // zstd refuses to train on too few samples, the other codecs still run
+    match train_dictionary(training, DICTIONARY_MAX_SIZE) {
+        Ok(dictionary) => {
+            let dictionary_id = save_dictionary(&dictionary)?;
+            println!(
+                "trained zstd dictionary {} ({} bytes) on {} blocks",
+                dictionary_id,
+                dictionary.len(),
+                training.len()
+            );
+            compressors.push(Box::new(ZstdDictionaryCompressor::new(
+                Arc::new(dictionary),
+                DICTIONARY_LEVEL,
+            )?));
+        }
+        Err(e) => eprintln!("skipping zstd dictionary, training failed: {}", e),
+    }
+
+    let raw_bytes: usize = measured.iter().map(|body| body.len()).sum();
+    println!(
+        "codec benchmark of blocks {}-{} ({}), {} blocks measured, {} raw bytes",
+        range[0],
+        range[1],
+        encoding.tag(),
+        measured.len(),
+        raw_bytes
+    );
+
+    for compressor in compressors.iter() {
+        let report = benchmark_codec(compressor.as_ref(), measured)?;
+        println!(
+            "{:<24} {:>12} bytes  ratio {:>6.3}  compress {:>10.1} ms  decompress {:>8.1} ms",
+            compressor.tag(),
+            report.compressed_bytes,
+            report.compressed_bytes as f64 / raw_bytes as f64,
+            report.compress_time.as_secs_f64() * 1000.0,
+            report.decompress_time.as_secs_f64() * 1000.0
+        );
+    }
+
+    Ok(())
+}
+
+fn benchmark_codec(compressor: &dyn Compressor, bodies: &[Vec<u8>]) -> Result<CodecReport, Error> {
+    let mut report = CodecReport {
+        compressed_bytes: 0,
+        compress_time: Duration::ZERO,
+        decompress_time: Duration::ZERO,
+    };
+
+    for body in bodies {
+        let started = Instant::now();
+        let compressed = compressor.compress(body)?;
+        report.compress_time += started.elapsed();
+
+        let started = Instant::now();
+        let decompressed = compressor.codec().decompress(&compressed)?;
+        report.decompress_time += started.elapsed();
+
+        if &decompressed != body {
+            return Err(Error::msg(format!(
+                "{} does not decompress back to the payload body",
+                compressor.tag()
+            )));
+        }
+        report.compressed_bytes += compressed.len();
+    }
+
+    Ok(report)
+}
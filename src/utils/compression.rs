@@ -0,0 +1,281 @@
+use crate::utils::archive_envelope::Codec;
+use crate::utils::block::{Block, DecodeError};
+use crate::utils::env_var::get_env_var;
+use anyhow::Error;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Compression of archive payload bodies. Decoding only needs the envelope
+/// codec id (and the dictionary id a zstd frame carries), so compressor
+/// settings can change between uploads.
+pub trait Compressor: Send + Sync {
+    fn codec(&self) -> Codec;
+
+    /// `WeaveVM:Compression` tag value, e.g. `brotli-q11-w22`
+    fn tag(&self) -> String;
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+pub struct BrotliCompressor {
+    quality: u32,
+    window: u32,
+}
+
+impl BrotliCompressor {
+    pub fn new(quality: u32, window: u32) -> Result<BrotliCompressor, Error> {
+        if quality > 11 || !(10..=24).contains(&window) {
+            return Err(Error::msg(format!(
+                "invalid brotli settings quality {} window {}",
+                quality, window
+            )));
+        }
+        Ok(BrotliCompressor { quality, window })
+    }
+}
+
+impl Default for BrotliCompressor {
+    /// the settings every archive used before codecs became configurable
+    fn default() -> Self {
+        BrotliCompressor {
+            quality: 11,
+            window: 22,
+        }
+    }
+}
+
+impl Compressor for BrotliCompressor {
+    fn codec(&self) -> Codec {
+        Codec::Brotli
+    }
+
+    fn tag(&self) -> String {
+        format!("brotli-q{}-w{}", self.quality, self.window)
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(Block::brotli_compress(input, self.quality, self.window))
+    }
+}
+
+pub struct ZstdCompressor {
+    level: i32,
+}
+
+impl ZstdCompressor {
+    pub fn new(level: i32) -> Result<ZstdCompressor, Error> {
+        check_zstd_level(level)?;
+        Ok(ZstdCompressor { level })
+    }
+}
+
+impl Compressor for ZstdCompressor {
+    fn codec(&self) -> Codec {
+        Codec::Zstd
+    }
+
+    fn tag(&self) -> String {
+        format!("zstd-l{}", self.level)
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(zstd::bulk::compress(input, self.level)?)
+    }
+}
+
+/// zstd with a dictionary trained on blocks, which pays off on small
+/// payloads. Frames record the dictionary id, the dictionary itself has to
+/// stay available in the dictionary directory to decode them.
+pub struct ZstdDictionaryCompressor {
+    level: i32,
+    dictionary_id: u32,
+    dictionary: Arc<Vec<u8>>,
+}
+
+impl ZstdDictionaryCompressor {
+    pub fn new(dictionary: Arc<Vec<u8>>, level: i32) -> Result<ZstdDictionaryCompressor, Error> {
+        check_zstd_level(level)?;
+        let dictionary_id = zstd::zstd_safe::get_dict_id_from_dict(&dictionary);
+        // raw content dictionaries have no id, frames couldn't reference them
+        if dictionary_id == 0 {
+            return Err(Error::msg("zstd dictionary without a dictionary id"));
+        }
+        Ok(ZstdDictionaryCompressor {
+            level,
+            dictionary_id,
+            dictionary,
+        })
+    }
+}
+
+impl Compressor for ZstdDictionaryCompressor {
+    fn codec(&self) -> Codec {
+        Codec::ZstdDictionary
+    }
+
+    fn tag(&self) -> String {
+        format!("zstd-dict{}-l{}", self.dictionary_id, self.level)
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut compressor = zstd::bulk::Compressor::with_dictionary(self.level, &self.dictionary)?;
+        Ok(compressor.compress(input)?)
+    }
+}
+
+fn check_zstd_level(level: i32) -> Result<(), Error> {
+    if !zstd::compression_level_range().contains(&level) {
+        return Err(Error::msg(format!("invalid zstd level {}", level)));
+    }
+    Ok(())
+}
+
+/// Compressor new archives are written with, from the `archive_compression`
+/// env var. Defaults to brotli quality 11, window 22. `zstd-dict` is refused
+/// outside dry runs: the dictionary only exists locally, so nobody else
+/// could decode the archives.
+pub fn compressor_from_env(dry_run: bool) -> Result<Box<dyn Compressor>, Error> {
+    let spec = match get_env_var("archive_compression") {
+        Ok(spec) => spec,
+        Err(_) => return Ok(Box::new(BrotliCompressor::default())),
+    };
+    if !dry_run && spec.trim().split(':').next() == Some("zstd-dict") {
+        return Err(Error::msg(
+            "archive_compression zstd-dict is only supported for dry runs, its dictionary is not uploaded",
+        ));
+    }
+
+    parse_compressor(&spec)
+}
+
+/// Parses `brotli[:<quality>[:<window>]]`, `zstd[:<level>]` or
+/// `zstd-dict:<dictionary id>[:<level>]`
+pub fn parse_compressor(spec: &str) -> Result<Box<dyn Compressor>, Error> {
+    let invalid = || Error::msg(format!("invalid compression setting: {}", spec));
+    let mut parts = spec.trim().split(':');
+    let name = parts.next().unwrap_or_default();
+    let params: Vec<&str> = parts.collect();
+
+    let compressor: Box<dyn Compressor> = match (name, params.as_slice()) {
+        ("brotli", []) => Box::new(BrotliCompressor::default()),
+        ("brotli", [quality]) => Box::new(BrotliCompressor::new(
+            quality.parse().map_err(|_| invalid())?,
+            22,
+        )?),
+        ("brotli", [quality, window]) => Box::new(BrotliCompressor::new(
+            quality.parse().map_err(|_| invalid())?,
+            window.parse().map_err(|_| invalid())?,
+        )?),
+        ("zstd", []) => Box::new(ZstdCompressor::new(19)?),
+        ("zstd", [level]) => Box::new(ZstdCompressor::new(level.parse().map_err(|_| invalid())?)?),
+        ("zstd-dict", [dictionary_id]) => Box::new(ZstdDictionaryCompressor::new(
+            load_dictionary(dictionary_id.parse().map_err(|_| invalid())?)?,
+            19,
+        )?),
+        ("zstd-dict", [dictionary_id, level]) => Box::new(ZstdDictionaryCompressor::new(
+            load_dictionary(dictionary_id.parse().map_err(|_| invalid())?)?,
+            level.parse().map_err(|_| invalid())?,
+        )?),
+        _ => return Err(invalid()),
+    };
+
+    Ok(compressor)
+}
+
+/// Decompresses a single zstd frame, with the dictionary it references if any
+pub fn zstd_decompress(input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let invalid = |e: io::Error| match e.kind() {
+        io::ErrorKind::UnexpectedEof => DecodeError::Truncated,
+        _ => DecodeError::InvalidCompression(e.to_string()),
+    };
+
+    let dictionary_id = zstd::zstd_safe::get_dict_id_from_frame(input);
+    let dictionary = match dictionary_id {
+        0 => None,
+        id => Some(load_dictionary(id).map_err(|e| {
+            DecodeError::InvalidCompression(format!("zstd dictionary {}: {}", id, e))
+        })?),
+    };
+
+    let mut decoder = match &dictionary {
+        Some(dictionary) => zstd::stream::read::Decoder::with_dictionary(input, dictionary),
+        None => zstd::stream::read::Decoder::with_buffer(input),
+    }
+    .map_err(invalid)?
+    .single_frame();
+
+    let mut output: Vec<u8> = Vec::new();
+    decoder.read_to_end(&mut output).map_err(invalid)?;
+
+    let trailing = decoder.finish().len();
+    if trailing > 0 {
+        return Err(DecodeError::InvalidCompression(format!(
+            "{} trailing bytes after the zstd frame",
+            trailing
+        )));
+    }
+
+    Ok(output)
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes on payload bodies
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>, Error> {
+    Ok(zstd::dict::from_samples(samples, max_size)?)
+}
+
+/// directory of `<dictionary id>.dict` files, from the `zstd_dictionary_dir`
+/// env var. Defaults to `dictionaries`
+fn dictionary_path(dictionary_id: u32) -> PathBuf {
+    let dir = get_env_var("zstd_dictionary_dir").unwrap_or_else(|_| "dictionaries".to_string());
+    PathBuf::from(dir).join(format!("{}.dict", dictionary_id))
+}
+
+fn loaded_dictionaries() -> &'static Mutex<HashMap<u32, Arc<Vec<u8>>>> {
+    static DICTIONARIES: OnceLock<Mutex<HashMap<u32, Arc<Vec<u8>>>>> = OnceLock::new();
+    DICTIONARIES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn load_dictionary(dictionary_id: u32) -> Result<Arc<Vec<u8>>, Error> {
+    if let Some(dictionary) = loaded_dictionaries().lock().unwrap().get(&dictionary_id) {
+        return Ok(dictionary.clone());
+    }
+
+    let path = dictionary_path(dictionary_id);
+    let dictionary = Arc::new(std::fs::read(&path).map_err(|e| {
+        Error::msg(format!(
+            "failed to read zstd dictionary {}: {}",
+            path.display(),
+            e
+        ))
+    })?);
+    if zstd::zstd_safe::get_dict_id_from_dict(&dictionary) != dictionary_id {
+        return Err(Error::msg(format!(
+            "dictionary file of id {} holds another dictionary",
+            dictionary_id
+        )));
+    }
+
+    loaded_dictionaries()
+        .lock()
+        .unwrap()
+        .insert(dictionary_id, dictionary.clone());
+    Ok(dictionary)
+}
+
+/// Writes the dictionary to the dictionary directory, returns its id
+pub fn save_dictionary(dictionary: &[u8]) -> Result<u32, Error> {
+    let dictionary_id = zstd::zstd_safe::get_dict_id_from_dict(dictionary);
+    if dictionary_id == 0 {
+        return Err(Error::msg("zstd dictionary without a dictionary id"));
+    }
+
+    let path = dictionary_path(dictionary_id);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, dictionary)?;
+
+    Ok(dictionary_id)
+}
@@ -1,6 +1,12 @@
 pub const WVM_RPC_URL: &str = "https://testnet-rpc.wvm.dev";
+// max JSON-RPC calls sent in a single batch request
+pub const RPC_BATCH_SIZE: usize = 100;
 pub const IRYS_GQL_GATEWAY: &str = "https://arweave.mainnet.irys.xyz";
 pub const ARWEAVE_GQL_GATEWAY: &str = "https://arweave.net";
+// Irys GQL timestamp filter lower bound (ms), before the first ExEx upload
+pub const GQL_START_TIMESTAMP: u64 = 1722470400000;
+// node receiving native arweave transactions and chunks
+pub const ARWEAVE_NODE_URL: &str = "https://arweave.net";
 pub const IRYS_UPLOADER_URL: &str = "https://node1.bundlr.network";
 pub const RETH_CLIENT_VERSION: &str = "reth/v1.0.6";
 pub const WVM_NETWORK_TAG: &str = "Alphanet v0.1.0";
@@ -8,3 +8,11 @@ pub fn get_env_var(key: &str) -> Result<String, env::VarError> {
         Err(e) => Err(e),
     }
 }
+
+/// boolean switch, `true` or `1` turns it on
+pub fn get_env_flag(key: &str) -> bool {
+    matches!(
+        get_env_var(key).as_deref().map(str::trim),
+        Ok("true") | Ok("1")
+    )
+}
@@ -0,0 +1,202 @@
+use crate::utils::constants::{ARWEAVE_GQL_GATEWAY, GQL_START_TIMESTAMP, IRYS_GQL_GATEWAY};
+use crate::utils::env_var::get_env_var;
+use anyhow::Error;
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A GraphQL endpoint indexing `WeaveVM-ExEx` archives. Implementations only
+/// differ in their query dialect and in how the next page cursor is exposed.
+pub trait GqlSource: Send + Sync {
+    /// short identifier, used in logs and checkpoint keys
+    fn name(&self) -> &str;
+
+    fn gateway(&self) -> &str;
+
+    /// query for one page of `owner` archives, oldest first, after `cursor`
+    fn build_query(&self, owner: &str, cursor: Option<&str>, page_size: u32) -> Value;
+
+    /// cursor to resume after the given `transactions` page
+    fn page_cursor(&self, transactions: &Value) -> Option<String>;
+
+    fn scan_key(&self, owner: &str) -> String {
+        format!("{}:{}", self.name(), owner)
+    }
+}
+
+/// arweave.net style gateway (goldsky indexer), paginated by edge cursors
+pub struct ArweaveGateway {
+    gateway: String,
+}
+
+impl ArweaveGateway {
+    pub fn new(gateway: &str) -> ArweaveGateway {
+        ArweaveGateway {
+            gateway: gateway.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl GqlSource for ArweaveGateway {
+    fn name(&self) -> &str {
+        "arweave"
+    }
+
+    fn gateway(&self) -> &str {
+        &self.gateway
+    }
+
+    fn build_query(&self, owner: &str, cursor: Option<&str>, page_size: u32) -> Value {
+        json!({
+            "query": r#"
+            query GetTransactions($cursor: String, $address: String!, $pageSize: Int!) {
+                transactions(
+                    first: $pageSize,
+                    after: $cursor,
+                    sort: HEIGHT_ASC,
+                    owners: [$address],
+                    tags: [
+                        { name: "Protocol", values: ["WeaveVM-ExEx"] }
+                    ],
+                ) {
+                    edges {
+                        node {
+                            id
+                            tags {
+                                name
+                                value
+                            }
+                        }
+                        cursor
+                    }
+                    pageInfo {
+                        hasNextPage
+                    }
+                }
+            }
+            "#,
+            "variables": {
+                "cursor": cursor,
+                "pageSize": page_size,
+                "address": owner
+            }
+        })
+    }
+
+    fn page_cursor(&self, transactions: &Value) -> Option<String> {
+        transactions
+            .get("edges")
+            .and_then(|edges| edges.as_array())
+            .and_then(|edges| edges.last())
+            .and_then(|last_edge| last_edge.get("cursor"))
+            .and_then(|cursor_val| cursor_val.as_str())
+            .map(String::from)
+    }
+}
+
+/// Irys bundler GQL, indexes uploads within seconds of their submission.
+/// Filtered by timestamp range and paginated by `pageInfo.endCursor`
+pub struct IrysGateway {
+    gateway: String,
+}
+
+impl IrysGateway {
+    pub fn new(gateway: &str) -> IrysGateway {
+        IrysGateway {
+            gateway: gateway.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl GqlSource for IrysGateway {
+    fn name(&self) -> &str {
+        "irys"
+    }
+
+    fn gateway(&self) -> &str {
+        &self.gateway
+    }
+
+    fn build_query(&self, owner: &str, cursor: Option<&str>, page_size: u32) -> Value {
+        json!({
+            "query": r#"
+            query GetTransactions($cursor: String, $address: String!, $pageSize: Int!, $startTimestamp: BigInt!, $endTimestamp: BigInt!) {
+                transactions(
+                    first: $pageSize,
+                    after: $cursor,
+                    timestamp: {from: $startTimestamp, to: $endTimestamp},
+                    order: ASC,
+                    tags: [
+                        { name: "Protocol", values: ["WeaveVM-ExEx"] }
+                    ],
+                    owners: [$address]
+                ) {
+                    edges {
+                        node {
+                            id
+                            tags {
+                                name
+                                value
+                            }
+                        }
+                        cursor
+                    }
+                    pageInfo {
+                        hasNextPage
+                        endCursor
+                    }
+                }
+            }
+            "#,
+            "variables": {
+                "cursor": cursor,
+                "pageSize": page_size,
+                "address": owner,
+                "startTimestamp": GQL_START_TIMESTAMP,
+                "endTimestamp": get_timestamp()
+            }
+        })
+    }
+
+    fn page_cursor(&self, transactions: &Value) -> Option<String> {
+        transactions
+            .get("pageInfo")
+            .and_then(|page_info| page_info.get("endCursor"))
+            .and_then(|end_cursor| end_cursor.as_str())
+            .map(String::from)
+    }
+}
+
+/// Builds the sources listed in the `gql_sources` env var, a comma separated
+/// list of `arweave` / `irys` entries with an optional `=<gateway url>`
+/// override, e.g. `irys,arweave=https://arweave.net`. Defaults to arweave.net
+pub fn gql_sources_from_env() -> Result<Vec<Box<dyn GqlSource>>, Error> {
+    let config = get_env_var("gql_sources").unwrap_or_else(|_| "arweave".to_string());
+    let mut sources: Vec<Box<dyn GqlSource>> = Vec::new();
+
+    for entry in config.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, gateway) = match entry.split_once('=') {
+            Some((name, gateway)) => (name.trim(), Some(gateway.trim())),
+            None => (entry, None),
+        };
+
+        let source: Box<dyn GqlSource> = match name {
+            "arweave" => Box::new(ArweaveGateway::new(gateway.unwrap_or(ARWEAVE_GQL_GATEWAY))),
+            "irys" => Box::new(IrysGateway::new(gateway.unwrap_or(IRYS_GQL_GATEWAY))),
+            other => return Err(Error::msg(format!("unknown gql source: {}", other))),
+        };
+        sources.push(source);
+    }
+
+    if sources.is_empty() {
+        return Err(Error::msg("no gql source configured"));
+    }
+
+    Ok(sources)
+}
+
+fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
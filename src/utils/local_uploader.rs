@@ -0,0 +1,75 @@
+use crate::utils::arweave_upload::{Upload, Uploader};
+use async_trait::async_trait;
+use bundlr_sdk::tags::Tag;
+use ethers::utils::{hex, keccak256};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Writes every data item to a local directory instead of Arweave: the
+/// payload as `<id>` and its tags as the `<id>.json` sidecar. Ids are derived
+/// from the payload and tags, so the same data item always gets the same id.
+pub struct LocalUploader {
+    dir: PathBuf,
+}
+
+impl LocalUploader {
+    pub fn new(dir: &str) -> Result<LocalUploader, anyhow::Error> {
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            anyhow::Error::msg(format!(
+                "failed to create upload directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+
+        Ok(LocalUploader { dir })
+    }
+}
+
+/// hex keccak256 over the length-prefixed tags and payload
+fn pseudo_id(data: &[u8], tags: &[Tag]) -> String {
+    let mut preimage: Vec<u8> = Vec::with_capacity(data.len() + 64 * tags.len());
+    for field in tags
+        .iter()
+        .flat_map(|tag| [tag.name.as_bytes(), tag.value.as_bytes()])
+        .chain([data])
+    {
+        preimage.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        preimage.extend_from_slice(field);
+    }
+
+    hex::encode(keccak256(preimage))
+}
+
+#[async_trait]
+impl Uploader for LocalUploader {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn dry_run(&self) -> bool {
+        true
+    }
+
+    async fn upload(&self, data: Vec<u8>, tags: Vec<Tag>) -> eyre::Result<Upload> {
+        let id = pseudo_id(&data, &tags);
+        let sidecar = json!({
+            "id": id,
+            "size": data.len(),
+            "tags": tags
+                .iter()
+                .map(|tag| json!({ "name": tag.name, "value": tag.value }))
+                .collect::<Vec<_>>(),
+        });
+
+        tokio::fs::write(self.dir.join(&id), data).await?;
+        tokio::fs::write(
+            self.dir.join(format!("{}.json", id)),
+            serde_json::to_vec_pretty(&sidecar)?,
+        )
+        .await?;
+
+        eyre::Ok(Upload { id, receipt: None })
+    }
+}
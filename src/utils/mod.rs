@@ -1,9 +1,33 @@
+pub mod archive_encoding;
+pub mod archive_envelope;
+pub mod archive_range;
+pub mod archive_verifier;
 pub mod arweave_gql;
+pub mod arweave_native;
+pub mod arweave_tx;
 pub mod arweave_upload;
 pub mod backfill;
+pub mod backfill_pipeline;
 pub mod block;
+pub mod block_compact;
+pub mod block_integrity;
+pub mod block_legacy;
+pub mod block_ranges;
+pub mod block_rlp;
+pub mod block_rlp_decode;
+pub mod codec_benchmark;
+pub mod compression;
 pub mod constants;
 pub mod env_var;
+pub mod gql_sources;
+pub mod local_uploader;
 pub mod planetscale;
+pub mod retry_queue;
 pub mod server_handlers;
+#[cfg(test)]
+pub mod test_fixtures;
+pub mod trie;
+pub mod upload_budget;
+pub mod upload_jobs;
+pub mod upload_receipt;
 pub mod wvm_client;
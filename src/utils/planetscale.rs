@@ -1,17 +1,21 @@
-use crate::utils::block::PlanetScaleBlock;
+use crate::utils::block::{
+    PlanetScaleBlock, PlanetScaleCheckpoint, PlanetScaleDeadLetter, PlanetScaleReceipt,
+    PlanetScaleRetry, PlanetScaleUpload,
+};
 use crate::utils::env_var::get_env_var;
+use crate::utils::upload_receipt::UploadReceipt;
 use anyhow::Error;
 use planetscale_driver::{query, PSConnection};
-use serde_json::Value;
+use serde_json::{json, Value};
 
-pub async fn ps_init() -> PSConnection {
-    let host = get_env_var("DATABASE_HOST").unwrap();
-    let username = get_env_var("DATABASE_USERNAME").unwrap();
-    let password = get_env_var("DATABASE_PASSWORD").unwrap();
+pub async fn ps_init() -> Result<PSConnection, Error> {
+    let host = get_env_var("DATABASE_HOST")?;
+    let username = get_env_var("DATABASE_USERNAME")?;
+    let password = get_env_var("DATABASE_PASSWORD")?;
 
     let conn: PSConnection = PSConnection::new(&host, &username, &password);
 
-    conn
+    Ok(conn)
 }
 
 pub async fn ps_insert_block(
@@ -27,9 +31,13 @@ pub async fn ps_insert_block(
         block_hash, block_number, arweave_hash
     );
 
+    // a re-backfilled (previously non-canonical) block replaces its old row
     let insert_query = format!(
-        "INSERT INTO ExExBackfill(BlockHash, BlockNumber, ArweaveHash) VALUES ('{}', {}, '{}')",
-        block_hash, block_number, arweave_hash
+        "INSERT INTO ExExBackfill(BlockHash, BlockNumber, ArweaveHash) VALUES ('{}', {}, '{}') \
+         ON DUPLICATE KEY UPDATE BlockHash = VALUES(BlockHash), ArweaveHash = VALUES(ArweaveHash)",
+        escape_string(block_hash),
+        block_number,
+        escape_string(arweave_hash)
     );
 
     match query(&insert_query).execute(conn).await {
@@ -46,7 +54,7 @@ pub async fn ps_insert_block(
 }
 
 pub async fn ps_get_block_by_id(block_number: u64) -> Result<Value, Error> {
-    let conn = ps_init().await;
+    let conn = ps_init().await?;
     let select_query = format!(
         "SELECT BlockHash, BlockNumber, ArweaveHash FROM ExExBackfill WHERE BlockNumber = {}",
         block_number
@@ -65,7 +73,7 @@ pub async fn ps_get_block_by_hash(block_hash: &str) -> Result<Value, Error> {
         .strip_prefix("0x")
         .unwrap_or(&block_hash)
         .to_string();
-    let conn = ps_init().await;
+    let conn = ps_init().await?;
     let select_query = format!(
         "SELECT BlockHash, BlockNumber, ArweaveHash FROM ExExBackfill WHERE BlockHash = '{}'",
         block_hash
@@ -78,3 +86,242 @@ pub async fn ps_get_block_by_hash(block_hash: &str) -> Result<Value, Error> {
     let res = serde_json::json!(block);
     Ok(res)
 }
+
+/// receipt of the data item archiving the block
+pub async fn ps_get_receipt_by_block_id(block_number: u64) -> Result<Value, Error> {
+    let conn = ps_init().await?;
+    let select_query = format!(
+        "SELECT r.ArweaveHash, r.DeadlineHeight, r.Timestamp, r.Receipt FROM ExExBackfillReceipts r \
+         JOIN ExExBackfill b ON b.ArweaveHash = r.ArweaveHash WHERE b.BlockNumber = {}",
+        block_number
+    );
+
+    let res = match query(&select_query).fetch_one(&conn).await {
+        Ok(PlanetScaleReceipt {
+            arweave_hash,
+            deadline_height,
+            timestamp,
+            receipt,
+        }) => json!({
+            "arweave_hash": arweave_hash,
+            "deadline_height": deadline_height,
+            "timestamp": timestamp,
+            "receipt": serde_json::from_str::<Value>(&receipt)?,
+        }),
+        Err(_) => json!({"error": "block not backfilled or without receipt"}),
+    };
+    Ok(res)
+}
+
+pub async fn ps_insert_receipt(conn: &PSConnection, receipt: &UploadReceipt) -> Result<(), Error> {
+    let insert_query = format!(
+        "INSERT IGNORE INTO ExExBackfillReceipts(ArweaveHash, DeadlineHeight, Timestamp, Receipt) \
+         VALUES ('{}', {}, {}, '{}')",
+        escape_string(&receipt.id),
+        receipt.deadline_height,
+        receipt.timestamp,
+        escape_string(&receipt.response.to_string())
+    );
+
+    query(&insert_query).execute(conn).await?;
+    Ok(())
+}
+
+pub async fn ps_insert_conflict(
+    conn: &PSConnection,
+    block_number: u64,
+    arweave_hash: &str,
+    archived_block_hash: &str,
+    canonical_block_hash: &str,
+) -> Result<(), Error> {
+    let archived_block_hash = archived_block_hash
+        .strip_prefix("0x")
+        .unwrap_or(archived_block_hash);
+    let canonical_block_hash = canonical_block_hash
+        .strip_prefix("0x")
+        .unwrap_or(canonical_block_hash);
+
+    let insert_query = format!(
+        "INSERT IGNORE INTO ExExBackfillConflicts(BlockNumber, ArweaveHash, ArchivedBlockHash, CanonicalBlockHash) \
+         VALUES ({}, '{}', '{}', '{}')",
+        block_number,
+        escape_string(arweave_hash),
+        escape_string(archived_block_hash),
+        escape_string(canonical_block_hash)
+    );
+
+    query(&insert_query).execute(conn).await?;
+    println!(
+        "Recorded non-canonical archive {} for block {}",
+        arweave_hash, block_number
+    );
+    Ok(())
+}
+
+pub async fn ps_get_checkpoints(conn: &PSConnection) -> Result<Vec<PlanetScaleCheckpoint>, Error> {
+    let select_query = "SELECT ScanKey, BlockNumber, Cursor, COALESCE(Archived, '') AS Archived FROM ExExBackfillCheckpoint";
+
+    let checkpoints: Vec<PlanetScaleCheckpoint> = query(select_query).fetch_all(conn).await?;
+    Ok(checkpoints)
+}
+
+pub async fn ps_upsert_checkpoint(
+    conn: &PSConnection,
+    scan_key: &str,
+    block_number: i64,
+    cursor: &str,
+    archived: &str,
+) -> Result<(), Error> {
+    let upsert_query = format!(
+        "INSERT INTO ExExBackfillCheckpoint(ScanKey, BlockNumber, Cursor, Archived) VALUES ('{}', {}, '{}', '{}') \
+         ON DUPLICATE KEY UPDATE BlockNumber = VALUES(BlockNumber), Cursor = VALUES(Cursor), Archived = VALUES(Archived)",
+        escape_string(scan_key),
+        block_number,
+        escape_string(cursor),
+        escape_string(archived)
+    );
+
+    query(&upsert_query).execute(conn).await?;
+    println!(
+        "Checkpoint {} saved at block {} (cursor: {})",
+        scan_key, block_number, cursor
+    );
+    Ok(())
+}
+
+pub async fn ps_get_retries(conn: &PSConnection) -> Result<Vec<PlanetScaleRetry>, Error> {
+    let select_query =
+        "SELECT BlockNumber, Attempts, LastError, NextAttemptAt FROM ExExBackfillRetries";
+
+    let retries: Vec<PlanetScaleRetry> = query(select_query).fetch_all(conn).await?;
+    Ok(retries)
+}
+
+pub async fn ps_upsert_retry(
+    conn: &PSConnection,
+    block_number: u64,
+    attempts: u64,
+    last_error: &str,
+    next_attempt_at: u64,
+) -> Result<(), Error> {
+    let upsert_query = format!(
+        "INSERT INTO ExExBackfillRetries(BlockNumber, Attempts, LastError, NextAttemptAt) VALUES ({}, {}, '{}', {}) \
+         ON DUPLICATE KEY UPDATE Attempts = VALUES(Attempts), LastError = VALUES(LastError), NextAttemptAt = VALUES(NextAttemptAt)",
+        block_number,
+        attempts,
+        escape_string(last_error),
+        next_attempt_at
+    );
+
+    query(&upsert_query).execute(conn).await?;
+    Ok(())
+}
+
+pub async fn ps_delete_retry(conn: &PSConnection, block_number: u64) -> Result<(), Error> {
+    let delete_query = format!(
+        "DELETE FROM ExExBackfillRetries WHERE BlockNumber = {}",
+        block_number
+    );
+
+    query(&delete_query).execute(conn).await?;
+    Ok(())
+}
+
+pub async fn ps_get_dead_letters(conn: &PSConnection) -> Result<Vec<PlanetScaleDeadLetter>, Error> {
+    let select_query =
+        "SELECT BlockNumber, Attempts, LastError, FailedAt FROM ExExBackfillDeadLetters";
+
+    let dead_letters: Vec<PlanetScaleDeadLetter> = query(select_query).fetch_all(conn).await?;
+    Ok(dead_letters)
+}
+
+/// moves the block from the retry queue to the dead-letter list. The dead
+/// letter is written first and both statements are idempotent, so a run
+/// stopped in between leaves the block dead-lettered, and `RetryQueue::load`
+/// deletes the leftover retry
+pub async fn ps_insert_dead_letter(
+    conn: &PSConnection,
+    block_number: u64,
+    attempts: u64,
+    last_error: &str,
+    failed_at: u64,
+) -> Result<(), Error> {
+    let insert_query = format!(
+        "INSERT INTO ExExBackfillDeadLetters(BlockNumber, Attempts, LastError, FailedAt) VALUES ({}, {}, '{}', {}) \
+         ON DUPLICATE KEY UPDATE Attempts = VALUES(Attempts), LastError = VALUES(LastError), FailedAt = VALUES(FailedAt)",
+        block_number,
+        attempts,
+        escape_string(last_error),
+        failed_at
+    );
+
+    query(&insert_query).execute(conn).await?;
+    ps_delete_retry(conn, block_number).await?;
+    println!(
+        "Block {} moved to the dead-letter list after {} attempts: {}",
+        block_number, attempts, last_error
+    );
+    Ok(())
+}
+
+pub async fn ps_get_upload(
+    conn: &PSConnection,
+    upload_key: &str,
+) -> Result<Option<PlanetScaleUpload>, Error> {
+    let select_query = format!(
+        "SELECT UploadKey, ArweaveHash, ResumeState FROM ExExBackfillUploads WHERE UploadKey = '{}'",
+        escape_string(upload_key)
+    );
+
+    let uploads: Vec<PlanetScaleUpload> = query(&select_query).fetch_all(conn).await?;
+    Ok(uploads.into_iter().next())
+}
+
+/// records the upload as pending, before the data item is sent
+pub async fn ps_begin_upload(conn: &PSConnection, upload_key: &str) -> Result<(), Error> {
+    let insert_query = format!(
+        "INSERT IGNORE INTO ExExBackfillUploads(UploadKey, ArweaveHash, ResumeState) VALUES ('{}', '', '')",
+        escape_string(upload_key)
+    );
+
+    query(&insert_query).execute(conn).await?;
+    Ok(())
+}
+
+pub async fn ps_complete_upload(
+    conn: &PSConnection,
+    upload_key: &str,
+    arweave_hash: &str,
+) -> Result<(), Error> {
+    let update_query = format!(
+        "UPDATE ExExBackfillUploads SET ArweaveHash = '{}' WHERE UploadKey = '{}'",
+        escape_string(arweave_hash),
+        escape_string(upload_key)
+    );
+
+    query(&update_query).execute(conn).await?;
+    Ok(())
+}
+
+/// keeps what the uploader needs to finish a paid upload whose data did not
+/// all arrive, see `PartialUpload`
+pub async fn ps_save_resume_state(
+    conn: &PSConnection,
+    upload_key: &str,
+    resume_state: &str,
+) -> Result<(), Error> {
+    let update_query = format!(
+        "UPDATE ExExBackfillUploads SET ResumeState = '{}' WHERE UploadKey = '{}'",
+        escape_string(resume_state),
+        escape_string(upload_key)
+    );
+
+    query(&update_query).execute(conn).await?;
+    Ok(())
+}
+
+/// quotes free text (error messages, gateway cursors, ids returned by the
+/// uploader or a gateway) for a SQL string literal
+fn escape_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "''")
+}
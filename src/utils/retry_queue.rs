@@ -0,0 +1,218 @@
+use crate::utils::backfill_pipeline::PipelineReport;
+use crate::utils::block::PlanetScaleRetry;
+use crate::utils::block_ranges::BlockRanges;
+use crate::utils::env_var::get_env_var;
+use crate::utils::planetscale::{
+    ps_delete_retry, ps_get_dead_letters, ps_get_retries, ps_insert_dead_letter, ps_upsert_retry,
+};
+use anyhow::Error;
+use planetscale_driver::PSConnection;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_MAX_ATTEMPTS: u64 = 5;
+const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_RETRY_MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Blocks that failed to archive, persisted in `ExExBackfillRetries` and
+/// retried with exponential backoff. After `max_attempts` failures a block
+/// moves to `ExExBackfillDeadLetters` and is no longer retried.
+pub struct RetryQueue {
+    base_delay: Duration,
+    dead_letters: BlockRanges,
+    max_attempts: u64,
+    /// longest a run waits for its next queued block to be due
+    max_wait: Duration,
+    pending: HashMap<u32, PlanetScaleRetry>,
+}
+
+impl RetryQueue {
+    /// Queue without any retry, configured by the `retry_max_attempts`,
+    /// `retry_base_delay_secs` and `retry_max_wait_secs` env vars (defaults
+    /// 5, 60 and 600). Dry runs without PlanetScale start from it.
+    pub fn empty() -> Result<RetryQueue, Error> {
+        Ok(RetryQueue {
+            base_delay: Duration::from_secs(env_number(
+                "retry_base_delay_secs",
+                DEFAULT_RETRY_BASE_DELAY.as_secs(),
+            )?),
+            dead_letters: BlockRanges::new(),
+            max_attempts: env_number("retry_max_attempts", DEFAULT_MAX_ATTEMPTS)?,
+            max_wait: Duration::from_secs(env_number(
+                "retry_max_wait_secs",
+                DEFAULT_RETRY_MAX_WAIT.as_secs(),
+            )?),
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Loads the queued retries and dead letters of PlanetScale, dry runs
+    /// skip the dead-lettered retries without deleting them
+    pub async fn load(conn: &PSConnection, dry_run: bool) -> Result<RetryQueue, Error> {
+        let mut queue = RetryQueue::empty()?;
+
+        for dead_letter in ps_get_dead_letters(conn).await? {
+            queue.dead_letters.insert(dead_letter.block_number as u32);
+        }
+        for retry in ps_get_retries(conn).await? {
+            let block_number = retry.block_number as u32;
+            // dead-lettering inserts the dead letter before deleting the
+            // retry, a run stopped in between left both
+            if queue.dead_letters.contains(block_number) {
+                if !dry_run {
+                    ps_delete_retry(conn, retry.block_number).await?;
+                }
+                continue;
+            }
+            queue.pending.insert(block_number, retry);
+        }
+
+        println!(
+            "retry queue: {} pending blocks, {} dead letters",
+            queue.pending.len(),
+            queue.dead_letters.block_count()
+        );
+
+        Ok(queue)
+    }
+
+    /// blocks gap detection may report but that must not be archived now:
+    /// dead letters and queued blocks still backing off
+    pub fn held_back(&self) -> BlockRanges {
+        let now = unix_now();
+        let mut held_back = self.dead_letters.clone();
+        for retry in self.pending.values() {
+            if retry.next_attempt_at > now {
+                held_back.insert(retry.block_number as u32);
+            }
+        }
+        held_back
+    }
+
+    /// queued blocks whose backoff has elapsed
+    pub fn due(&self) -> BlockRanges {
+        let now = unix_now();
+        let mut due: Vec<u32> = self
+            .pending
+            .values()
+            .filter(|retry| retry.next_attempt_at <= now)
+            .map(|retry| retry.block_number as u32)
+            .collect();
+        due.sort_unstable();
+        BlockRanges::from_sorted(&due)
+    }
+
+    /// time left until the next queued block is due, `None` once the queue
+    /// is empty or when it is due after `retry_max_wait_secs`: the run ends
+    /// and leaves it to a later run instead of blocking on it
+    pub fn next_due_in(&self) -> Option<Duration> {
+        let next_attempt_at = self
+            .pending
+            .values()
+            .map(|retry| retry.next_attempt_at)
+            .min()?;
+        let delay = Duration::from_secs(next_attempt_at.saturating_sub(unix_now()));
+        if delay > self.max_wait {
+            println!(
+                "{} queued blocks left for a later run, the next one is due in {:?}",
+                self.pending.len(),
+                delay
+            );
+            return None;
+        }
+
+        Some(delay)
+    }
+
+    /// removes the given archived blocks from the queue
+    pub async fn clear(
+        &mut self,
+        conn: &PSConnection,
+        archived: &BlockRanges,
+    ) -> Result<(), Error> {
+        let cleared: Vec<u32> = self
+            .pending
+            .keys()
+            .copied()
+            .filter(|block_number| archived.contains(*block_number))
+            .collect();
+        for block_number in cleared {
+            ps_delete_retry(conn, block_number as u64).await?;
+            self.pending.remove(&block_number);
+        }
+
+        Ok(())
+    }
+
+    /// Clears archived blocks from the queue, and queues or dead-letters the
+    /// blocks of every failed range
+    pub async fn record(
+        &mut self,
+        conn: &PSConnection,
+        report: &PipelineReport,
+    ) -> Result<(), Error> {
+        self.clear(conn, &report.archived).await?;
+
+        let now = unix_now();
+        for (range, error) in report.failures.iter() {
+            for block_number in range[0]..=range[1] {
+                let attempts = self
+                    .pending
+                    .get(&block_number)
+                    .map(|retry| retry.attempts)
+                    .unwrap_or(0)
+                    + 1;
+
+                if attempts >= self.max_attempts {
+                    ps_insert_dead_letter(conn, block_number as u64, attempts, error, now).await?;
+                    self.pending.remove(&block_number);
+                    self.dead_letters.insert(block_number);
+                    continue;
+                }
+
+                let next_attempt_at = now + self.backoff(attempts).as_secs();
+                ps_upsert_retry(conn, block_number as u64, attempts, error, next_attempt_at)
+                    .await?;
+                self.pending.insert(
+                    block_number,
+                    PlanetScaleRetry {
+                        block_number: block_number as u64,
+                        attempts,
+                        last_error: error.clone(),
+                        next_attempt_at,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// delay after the given number of failed attempts, doubling each time
+    fn backoff(&self, attempts: u64) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(32) as u32;
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(RETRY_MAX_DELAY)
+    }
+}
+
+fn env_number(key: &str, default: u64) -> Result<u64, Error> {
+    let value = match get_env_var(key).ok() {
+        Some(value) => value,
+        None => return Ok(default),
+    };
+
+    match value.trim().parse::<u64>().ok() {
+        Some(number) if number > 0 => Ok(number),
+        _ => Err(Error::msg(format!("invalid {} value: {}", key, value))),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
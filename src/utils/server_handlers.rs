@@ -1,4 +1,6 @@
-use crate::utils::planetscale::{ps_get_block_by_hash, ps_get_block_by_id};
+use crate::utils::planetscale::{
+    ps_get_block_by_hash, ps_get_block_by_id, ps_get_receipt_by_block_id,
+};
 use axum::{extract::Path, response::Json};
 use serde_json::{json, Value};
 
@@ -19,3 +21,10 @@ pub async fn handle_get_block_by_hash(Path(hash): Path<String>) -> Json<Value> {
         .unwrap_or(json!({"error": "error fetching block from cloud"}));
     Json(tx_object)
 }
+
+pub async fn handle_get_receipt_by_block_id(Path(id): Path<u64>) -> Json<Value> {
+    let receipt = ps_get_receipt_by_block_id(id)
+        .await
+        .unwrap_or(json!({"error": "error fetching receipt from cloud"}));
+    Json(receipt)
+}
@@ -0,0 +1,20 @@
+use crate::utils::block::Block;
+use std::path::PathBuf;
+
+/// blocks under `tests/fixtures/blocks`, one per transaction type plus a
+/// block with withdrawals
+pub const RPC_BLOCKS: [&str; 5] = ["legacy", "eip1559", "eip4844", "eip7702", "withdrawals"];
+
+/// contents of a file under `tests/fixtures`
+pub fn fixture_bytes(path: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+}
+
+/// `eth_getBlockByNumber` response of `tests/fixtures/blocks/<name>.json`
+pub fn rpc_block(name: &str) -> Block {
+    let json = fixture_bytes(&format!("blocks/{}.json", name));
+    serde_json::from_slice(&json).unwrap_or_else(|e| panic!("invalid fixture {}: {}", name, e))
+}
@@ -0,0 +1,120 @@
+use ethers::types::H256;
+use ethers::utils::keccak256;
+use ethers::utils::rlp::RlpStream;
+
+/// Root of the Merkle-Patricia trie keyed by `rlp(index)` for each value, as
+/// used for a block's `transactionsRoot`, `receiptsRoot` and `withdrawalsRoot`.
+pub fn ordered_trie_root(values: &[Vec<u8>]) -> H256 {
+    let mut entries: Vec<(Vec<u8>, &[u8])> = values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let key = ethers::utils::rlp::encode(&(index as u64));
+            (to_nibbles(&key), value.as_slice())
+        })
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    H256::from(keccak256(encode_node(&entries, 0)))
+}
+
+fn to_nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// compact (hex-prefix) encoding of a nibble path
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag: u8 = if leaf { 2 } else { 0 };
+    let mut encoded: Vec<u8> = Vec::with_capacity(nibbles.len() / 2 + 1);
+
+    let rest = if nibbles.len() % 2 == 1 {
+        encoded.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        encoded.push(flag << 4);
+        nibbles
+    };
+
+    for pair in rest.chunks(2) {
+        encoded.push((pair[0] << 4) | pair[1]);
+    }
+
+    encoded
+}
+
+/// RLP of the node holding `entries` (sorted, sharing their first `depth`
+/// nibbles)
+fn encode_node(entries: &[(Vec<u8>, &[u8])], depth: usize) -> Vec<u8> {
+    if entries.is_empty() {
+        let mut stream = RlpStream::new();
+        stream.append_empty_data();
+        return stream.out().to_vec();
+    }
+
+    if entries.len() == 1 {
+        let (key, value) = &entries[0];
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&hex_prefix(&key[depth..], true));
+        stream.append(value);
+        return stream.out().to_vec();
+    }
+
+    // nibbles shared by every key past `depth`, entries are sorted so the
+    // first and last keys bound it
+    let first = &entries[0].0;
+    let last = &entries[entries.len() - 1].0;
+    let shared = first[depth..]
+        .iter()
+        .zip(last[depth..].iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    if shared > 0 {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&hex_prefix(&first[depth..depth + shared], false));
+        append_child(&mut stream, encode_node(entries, depth + shared));
+        return stream.out().to_vec();
+    }
+
+    let mut stream = RlpStream::new_list(17);
+    // a key ending at this node holds the branch value, it sorts first
+    let (value, children) = match entries.first() {
+        Some((key, value)) if key.len() == depth => (Some(*value), &entries[1..]),
+        _ => (None, entries),
+    };
+
+    let mut start = 0;
+    for nibble in 0..16u8 {
+        let end = start
+            + children[start..]
+                .iter()
+                .take_while(|(key, _)| key[depth] == nibble)
+                .count();
+
+        if start == end {
+            stream.append_empty_data();
+        } else {
+            append_child(&mut stream, encode_node(&children[start..end], depth + 1));
+        }
+        start = end;
+    }
+
+    match value {
+        Some(value) => stream.append(&value),
+        None => stream.append_empty_data(),
+    };
+
+    stream.out().to_vec()
+}
+
+/// nodes shorter than a hash are inlined, others referenced by their hash
+fn append_child(stream: &mut RlpStream, node: Vec<u8>) {
+    if node.len() < 32 {
+        stream.append_raw(&node, 1);
+    } else {
+        stream.append(&keccak256(&node).as_slice());
+    }
+}
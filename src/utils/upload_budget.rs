@@ -0,0 +1,334 @@
+use crate::utils::arweave_tx::MAX_CHUNK_SIZE;
+use crate::utils::arweave_upload::{ArweaveRequest, PartialUpload, Upload, Uploader};
+use crate::utils::backfill_pipeline::{fetch_and_encode, ArchiveSettings, EncodedRange};
+use crate::utils::block_ranges::BlockRange;
+use crate::utils::env_var::get_env_var;
+use anyhow::Error;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_PREFLIGHT_SAMPLES: usize = 5;
+
+/// An upload refused because it would overspend, its blocks are left for a
+/// later run
+#[derive(Debug)]
+pub struct BudgetExhausted;
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spend budget exhausted")
+    }
+}
+
+impl std::error::Error for BudgetExhausted {}
+
+/// Spending cap of a backfill run, the lower of the `spend_budget` env var
+/// and the uploader balance. Once an upload is refused every later one is
+/// refused too, so the run stops instead of picking the cheapest items.
+pub struct SpendBudget {
+    exhausted: AtomicBool,
+    limit: Option<u128>,
+    /// uploader prices of the run, per size bucket
+    prices: Mutex<HashMap<usize, u128>>,
+    spent: Mutex<u128>,
+}
+
+impl SpendBudget {
+    pub fn new(limit: Option<u128>) -> SpendBudget {
+        SpendBudget {
+            exhausted: AtomicBool::new(false),
+            limit,
+            prices: Mutex::new(HashMap::new()),
+            spent: Mutex::new(0),
+        }
+    }
+
+    /// Price of a data item of `bytes`. The uploader is asked once per run
+    /// for each 256 KiB bucket, priced at its upper bound so that a data item
+    /// never costs more than reserved
+    pub async fn price(&self, uploader: &dyn Uploader, bytes: usize) -> Result<u128, Error> {
+        let bucket = bytes.div_ceil(MAX_CHUNK_SIZE).max(1) * MAX_CHUNK_SIZE;
+        if let Some(price) = self.prices.lock().unwrap().get(&bucket) {
+            return Ok(*price);
+        }
+
+        let price = uploader
+            .price(bucket)
+            .await
+            .map_err(|e| Error::msg(format!("failed to price upload: {}", e)))?;
+        self.prices.lock().unwrap().insert(bucket, price);
+        Ok(price)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::SeqCst)
+    }
+
+    pub fn spent(&self) -> u128 {
+        *self.spent.lock().unwrap()
+    }
+
+    /// reserves `price` for an upload, refused past the limit
+    pub fn reserve(&self, price: u128) -> Result<(), BudgetExhausted> {
+        let mut spent = self.spent.lock().unwrap();
+        if self.is_exhausted() {
+            return Err(BudgetExhausted);
+        }
+        if self.limit.is_some_and(|limit| *spent + price > limit) {
+            self.exhausted.store(true, Ordering::SeqCst);
+            return Err(BudgetExhausted);
+        }
+
+        *spent += price;
+        Ok(())
+    }
+
+    /// gives back the reservation of an upload that failed
+    pub fn refund(&self, price: u128) {
+        let mut spent = self.spent.lock().unwrap();
+        *spent = spent.saturating_sub(price);
+    }
+}
+
+/// Sends the data item if the budget still covers its price
+pub async fn send_within_budget(
+    budget: &SpendBudget,
+    uploader: &dyn Uploader,
+    request: &ArweaveRequest,
+) -> Result<Upload, Error> {
+    let price = reserve_upload(budget, uploader, request).await?;
+    send_reserved(budget, uploader, request, price).await
+}
+
+/// reserves the price of the data item, refused once the budget is spent
+pub async fn reserve_upload(
+    budget: &SpendBudget,
+    uploader: &dyn Uploader,
+    request: &ArweaveRequest,
+) -> Result<u128, Error> {
+    let price = budget.price(uploader, request.data_size()).await?;
+    budget.reserve(price)?;
+    Ok(price)
+}
+
+/// Sends a data item whose `price` is reserved. A failed upload gives the
+/// reservation back, unless it is partial and so paid for
+pub async fn send_reserved(
+    budget: &SpendBudget,
+    uploader: &dyn Uploader,
+    request: &ArweaveRequest,
+    price: u128,
+) -> Result<Upload, Error> {
+    match request.send_with(uploader).await {
+        Ok(upload) => Ok(upload),
+        Err(e) => match e.downcast::<PartialUpload>() {
+            Ok(partial) => Err(partial.into()),
+            Err(e) => {
+                budget.refund(price);
+                Err(Error::msg(e.to_string()))
+            }
+        },
+    }
+}
+
+/// Budget a run spends under, and the sample data items encoded to estimate
+/// its cost, uploaded by the run as they are
+pub struct Preflight {
+    pub budget: SpendBudget,
+    pub encoded: HashMap<BlockRange, EncodedRange>,
+}
+
+/// Estimates the cost of archiving `ranges` from the encoded size of a few
+/// evenly spaced sample ranges, and returns the budget the run spends under.
+/// Refuses the run when the estimate exceeds the balance or `spend_budget`.
+pub async fn preflight(
+    ranges: &[BlockRange],
+    settings: Arc<ArchiveSettings>,
+    uploader: &dyn Uploader,
+) -> Result<Preflight, Error> {
+    let spend_budget = spend_budget_from_env()?;
+    let balance = uploader.balance().await.map_err(|e| {
+        Error::msg(format!(
+            "failed to fetch {} balance: {}",
+            uploader.name(),
+            e
+        ))
+    })?;
+    let limit = match (spend_budget, balance) {
+        (Some(budget), Some(balance)) => Some(budget.min(balance)),
+        (budget, balance) => budget.or(balance),
+    };
+    let budget = SpendBudget::new(limit);
+    let mut encoded: HashMap<BlockRange, EncodedRange> = HashMap::new();
+    if ranges.is_empty() {
+        return Ok(Preflight { budget, encoded });
+    }
+
+    let samples = preflight_samples_from_env()?;
+    let step = (ranges.len() / samples).max(1);
+    let mut sampled: u128 = 0;
+    let mut sample_bytes: u128 = 0;
+    let mut sample_cost: u128 = 0;
+    for range in ranges.iter().step_by(step).take(samples) {
+        // a sample failing to fetch will most likely fail the upload too
+        let sample = match fetch_and_encode(*range, settings.clone()).await {
+            Ok(sample) => sample,
+            Err(e) => {
+                eprintln!("cost sample {}-{} failed: {}", range[0], range[1], e);
+                continue;
+            }
+        };
+        let bytes = sample.data_size();
+        sample_cost += budget.price(uploader, bytes).await?;
+        sample_bytes += bytes as u128;
+        sampled += 1;
+        encoded.insert(*range, sample);
+    }
+    if sampled == 0 {
+        return Err(Error::msg("no cost sample could be encoded"));
+    }
+
+    let data_items = ranges.len() as u128;
+    let estimated_bytes = sample_bytes * data_items / sampled;
+    let estimated_cost = sample_cost * data_items / sampled;
+    let unit = uploader.unit();
+    println!(
+        "cost estimate: {} data items, ~{} bytes, ~{} {} (balance: {}, budget: {})",
+        data_items,
+        estimated_bytes,
+        estimated_cost,
+        unit,
+        format_amount(balance, unit),
+        format_amount(spend_budget, unit)
+    );
+
+    if let Some(limit) = limit.filter(|limit| estimated_cost > *limit) {
+        return Err(Error::msg(format!(
+            "estimated cost {} {} exceeds the {} {} available",
+            estimated_cost, unit, limit, unit
+        )));
+    }
+
+    Ok(Preflight { budget, encoded })
+}
+
+fn format_amount(amount: Option<u128>, unit: &str) -> String {
+    match amount {
+        Some(amount) => format!("{} {}", amount, unit),
+        None => "unlimited".to_string(),
+    }
+}
+
+/// `spend_budget` env var, the most a run may spend in the uploader unit
+fn spend_budget_from_env() -> Result<Option<u128>, Error> {
+    let budget = match get_env_var("spend_budget").ok() {
+        Some(budget) => budget,
+        None => return Ok(None),
+    };
+
+    match budget.trim().parse::<u128>().ok() {
+        Some(budget) => Ok(Some(budget)),
+        None => Err(Error::msg(format!(
+            "invalid spend_budget value: {}",
+            budget
+        ))),
+    }
+}
+
+fn preflight_samples_from_env() -> Result<usize, Error> {
+    let samples = match get_env_var("cost_preflight_samples").ok() {
+        Some(samples) => samples,
+        None => return Ok(DEFAULT_PREFLIGHT_SAMPLES),
+    };
+
+    match samples.trim().parse::<usize>().ok() {
+        Some(samples) if samples > 0 => Ok(samples),
+        _ => Err(Error::msg(format!(
+            "invalid cost_preflight_samples value: {}",
+            samples
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use bundlr_sdk::tags::Tag;
+    use std::sync::atomic::AtomicUsize;
+
+    /// prices every data item at 10, and fails its uploads
+    struct FailingUploader {
+        partial: bool,
+        priced: AtomicUsize,
+    }
+
+    impl FailingUploader {
+        fn new(partial: bool) -> FailingUploader {
+            FailingUploader {
+                partial,
+                priced: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Uploader for FailingUploader {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        async fn price(&self, _bytes: usize) -> eyre::Result<u128> {
+            self.priced.fetch_add(1, Ordering::SeqCst);
+            eyre::Ok(10)
+        }
+
+        async fn upload(&self, _data: Vec<u8>, _tags: Vec<Tag>) -> eyre::Result<Upload> {
+            if self.partial {
+                return Err(eyre::Report::new(PartialUpload {
+                    id: "tx".to_string(),
+                    state: "{}".to_string(),
+                    message: "chunk 0 rejected".to_string(),
+                }));
+            }
+            Err(eyre::eyre!("rejected"))
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_uploads_keep_their_reservation() {
+        let request = ArweaveRequest::new();
+
+        let budget = SpendBudget::new(Some(100));
+        let e = send_within_budget(&budget, &FailingUploader::new(false), &request)
+            .await
+            .unwrap_err();
+        assert!(!e.is::<PartialUpload>());
+        assert_eq!(budget.spent(), 0);
+
+        let e = send_within_budget(&budget, &FailingUploader::new(true), &request)
+            .await
+            .unwrap_err();
+        assert_eq!(e.downcast_ref::<PartialUpload>().unwrap().id, "tx");
+        assert_eq!(budget.spent(), 10);
+    }
+
+    #[tokio::test]
+    async fn prices_are_fetched_once_per_bucket() {
+        let uploader = FailingUploader::new(false);
+        let budget = SpendBudget::new(None);
+
+        for bytes in [
+            0,
+            1000,
+            MAX_CHUNK_SIZE,
+            MAX_CHUNK_SIZE + 1,
+            2 * MAX_CHUNK_SIZE,
+        ] {
+            assert_eq!(budget.price(&uploader, bytes).await.unwrap(), 10);
+        }
+        assert_eq!(uploader.priced.load(Ordering::SeqCst), 2);
+    }
+}
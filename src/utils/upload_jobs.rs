@@ -0,0 +1,135 @@
+use crate::utils::arweave_gql::{backfiller_address, find_backfill_upload};
+use crate::utils::arweave_upload::{ArweaveRequest, PartialUpload, Uploader};
+use crate::utils::block_ranges::BlockRange;
+use crate::utils::planetscale::{
+    ps_begin_upload, ps_complete_upload, ps_get_upload, ps_insert_receipt, ps_save_resume_state,
+};
+use crate::utils::upload_budget::{reserve_upload, send_reserved, SpendBudget};
+use anyhow::Error;
+use planetscale_driver::PSConnection;
+
+/// Identity of a data item: the blocks it archives and the hash they had.
+/// At most one backfill upload exists per key.
+#[derive(Clone, Debug)]
+pub struct UploadKey {
+    key: String,
+    /// tags identifying the data item on GQL
+    tags: Vec<(&'static str, String)>,
+}
+
+impl UploadKey {
+    pub fn block(block_number: u32, block_hash: &str) -> UploadKey {
+        UploadKey {
+            key: format!("block:{}:{}", block_number, block_hash),
+            tags: vec![
+                ("Block-Number", block_number.to_string()),
+                ("Block-Hash", block_hash.to_string()),
+            ],
+        }
+    }
+
+    /// the hash of the last block commits to every block of the range
+    /// through the parent hashes
+    pub fn range(range: BlockRange, end_block_hash: &str) -> UploadKey {
+        UploadKey {
+            key: format!("range:{}-{}:{}", range[0], range[1], end_block_hash),
+            tags: vec![
+                ("Block-Range-Start", range[0].to_string()),
+                ("Block-Range-End", range[1].to_string()),
+                ("Block-Range-End-Hash", end_block_hash.to_string()),
+            ],
+        }
+    }
+}
+
+/// Sends the data item unless an upload with the same key already exists.
+/// Every upload is recorded as pending in `ExExBackfillUploads` before it is
+/// sent, so after a crash or a failed send the pending key is first looked up
+/// on the GQL of the uploader, which may already hold the data item. A paid upload whose
+/// data did not all arrive, or found on GQL without all of it, is resumed
+/// instead. The bundler receipt, of a new
+/// or of a recovered upload, is stored in `ExExBackfillReceipts` before the
+/// upload is marked complete.
+pub async fn upload_once(
+    conn: &PSConnection,
+    uploader: &dyn Uploader,
+    key: &UploadKey,
+    request: &ArweaveRequest,
+    budget: &SpendBudget,
+) -> Result<String, Error> {
+    if let Some(job) = ps_get_upload(conn, &key.key).await? {
+        if !job.arweave_hash.is_empty() {
+            println!("{} already uploaded as {}", key.key, job.arweave_hash);
+            return Ok(job.arweave_hash);
+        }
+
+        // sending again would pay a second time for the same data item
+        if !job.resume_state.is_empty() {
+            let upload = request
+                .resume_with(uploader, &job.resume_state)
+                .await
+                .map_err(|e| Error::msg(format!("failed to resume {}: {}", key.key, e)))?;
+            ps_complete_upload(conn, &key.key, &upload.id).await?;
+            return Ok(upload.id);
+        }
+
+        // pending: an earlier attempt may have reached the bundler
+        let owner = uploader.owner().unwrap_or_else(backfiller_address);
+        if let Some(arweave_id) =
+            find_backfill_upload(uploader.gql_gateway(), &owner, &key.tags).await?
+        {
+            println!("{} found on GQL as {}", key.key, arweave_id);
+            // found with only part of its data, e.g. a native transaction
+            // missing chunks, it is resumed rather than completed
+            let state = uploader
+                .unconfirmed_state(&arweave_id, request.data_size())
+                .await
+                .map_err(|e| {
+                    Error::msg(format!(
+                        "failed to confirm {} as {}: {}",
+                        key.key, arweave_id, e
+                    ))
+                })?;
+            if let Some(state) = state {
+                ps_save_resume_state(conn, &key.key, &state).await?;
+                let upload = request
+                    .resume_with(uploader, &state)
+                    .await
+                    .map_err(|e| Error::msg(format!("failed to resume {}: {}", key.key, e)))?;
+                ps_complete_upload(conn, &key.key, &upload.id).await?;
+                return Ok(upload.id);
+            }
+            // the receipt was lost with the response of the earlier attempt
+            match uploader.receipt(&arweave_id).await {
+                Ok(Some(receipt)) => ps_insert_receipt(conn, &receipt).await?,
+                Ok(None) => {}
+                Err(e) => eprintln!("failed to fetch the receipt of {}: {}", arweave_id, e),
+            }
+            ps_complete_upload(conn, &key.key, &arweave_id).await?;
+            return Ok(arweave_id);
+        }
+    }
+
+    // a data item over budget is left for a later run without a job
+    let price = reserve_upload(budget, uploader, request).await?;
+    if let Err(e) = ps_begin_upload(conn, &key.key).await {
+        budget.refund(price);
+        return Err(e);
+    }
+    let upload = match send_reserved(budget, uploader, request, price).await {
+        Ok(upload) => upload,
+        Err(e) => {
+            if let Some(partial) = e.downcast_ref::<PartialUpload>() {
+                ps_save_resume_state(conn, &key.key, &partial.state).await?;
+            }
+            return Err(e);
+        }
+    };
+    // a completed upload is never looked at again, its receipt goes first
+    if let Some(receipt) = upload.receipt.as_ref() {
+        ps_insert_receipt(conn, receipt).await?;
+    }
+    ps_complete_upload(conn, &key.key, &upload.id).await?;
+
+    Ok(upload.id)
+}
@@ -0,0 +1,84 @@
+use crate::utils::arweave_tx::{b64url_decode, deep_hash, DeepHashItem};
+use anyhow::Error;
+use rsa::pss::{Signature, VerifyingKey};
+use rsa::signature::Verifier;
+use rsa::{BigUint, RsaPublicKey};
+use serde_json::Value;
+use sha2::Sha256;
+
+// every arweave wallet key uses the 65537 public exponent
+const ARWEAVE_PUBLIC_EXPONENT: u32 = 65537;
+
+/// Signed promise of the bundler to settle a data item on Arweave before
+/// `deadline_height`, kept with the full bundler response
+#[derive(Clone, Debug)]
+pub struct UploadReceipt {
+    pub id: String,
+    pub deadline_height: u64,
+    /// RSA modulus of the bundler key
+    pub public: String,
+    pub signature: String,
+    /// unix milliseconds
+    pub timestamp: u64,
+    pub version: String,
+    pub response: Value,
+}
+
+impl UploadReceipt {
+    /// receipt of an upload response, `None` when the bundler sent none
+    pub fn from_response(response: &Value) -> Option<UploadReceipt> {
+        let text = |field: &str| response[field].as_str().map(String::from);
+        // numbers are sent as JSON numbers or strings depending on the node
+        let number = |field: &str| match &response[field] {
+            Value::String(number) => number.parse::<u64>().ok(),
+            number => number.as_u64(),
+        };
+
+        Some(UploadReceipt {
+            id: text("id")?,
+            deadline_height: number("deadlineHeight")?,
+            public: text("public")?,
+            signature: text("signature")?,
+            timestamp: number("timestamp")?,
+            version: text("version")?,
+            response: response.clone(),
+        })
+    }
+
+    /// deep hash of the receipt fields, the message the bundler signs
+    pub fn signature_data(&self) -> [u8; 48] {
+        let blob = |text: &str| DeepHashItem::Blob(text.as_bytes().to_vec());
+        deep_hash(&DeepHashItem::List(vec![
+            blob("Bundlr"),
+            blob(&self.version),
+            blob(&self.id),
+            blob(&self.deadline_height.to_string()),
+            blob(&self.timestamp.to_string()),
+        ]))
+    }
+
+    /// Checks the receipt was signed by `bundler_public`, the RSA-PSS
+    /// public key the bundler publishes
+    pub fn verify(&self, bundler_public: &str) -> Result<(), Error> {
+        if self.public != bundler_public {
+            return Err(Error::msg(format!(
+                "receipt of {} is signed by another key than the bundler's",
+                self.id
+            )));
+        }
+
+        let key = RsaPublicKey::new(
+            BigUint::from_bytes_be(&b64url_decode(&self.public)?),
+            BigUint::from(ARWEAVE_PUBLIC_EXPONENT),
+        )?;
+        let signature = Signature::try_from(b64url_decode(&self.signature)?.as_slice())?;
+        VerifyingKey::<Sha256>::new(key)
+            .verify(&self.signature_data(), &signature)
+            .map_err(|_| {
+                Error::msg(format!(
+                    "invalid bundler signature on receipt of {}",
+                    self.id
+                ))
+            })
+    }
+}
@@ -1,10 +1,34 @@
-use crate::utils::block::Block;
-use crate::utils::constants::WVM_RPC_URL;
+use crate::utils::block::{Block, Receipt};
+use crate::utils::constants::{RPC_BATCH_SIZE, WVM_RPC_URL};
 use anyhow::Error;
+use async_trait::async_trait;
 use reqwest::Client;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 
-pub async fn retrieve_block_with_txs(block_number: u32) -> Block {
+/// Where the backfill pipeline reads the blocks it archives from
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    async fn block_with_txs(&self, block_number: u32) -> Result<Block, Error>;
+
+    async fn block_receipts(&self, block: &Block) -> Result<Vec<Receipt>, Error>;
+}
+
+/// Blocks and receipts of the WeaveVM RPC
+pub struct RpcBlockSource;
+
+#[async_trait]
+impl BlockSource for RpcBlockSource {
+    async fn block_with_txs(&self, block_number: u32) -> Result<Block, Error> {
+        retrieve_block_with_txs(block_number).await
+    }
+
+    async fn block_receipts(&self, block: &Block) -> Result<Vec<Receipt>, Error> {
+        retrieve_block_receipts(block).await
+    }
+}
+
+pub async fn retrieve_block_with_txs(block_number: u32) -> Result<Block, Error> {
     let block_number_hex = format!("0x{:x}", block_number);
 
     // JSON-RPC request payload
@@ -17,18 +41,96 @@ pub async fn retrieve_block_with_txs(block_number: u32) -> Block {
 
     // Create an HTTP client
     let client = Client::new();
-    let res = client
-        .post(WVM_RPC_URL)
-        .json(&request_body)
-        .send()
-        .await
-        .unwrap();
-
-    let result = res.json::<serde_json::Value>().await.unwrap();
-    let wvm_block_fmt = Block::load_block_from_value(result["result"].clone()).unwrap();
-    println!("{:#?}", wvm_block_fmt);
-
-    wvm_block_fmt
+    let res = client.post(WVM_RPC_URL).json(&request_body).send().await?;
+
+    let result = res.json::<serde_json::Value>().await?;
+    let wvm_block_fmt = Block::load_block_from_value(result["result"].clone())?;
+    println!(
+        "fetched block {}: {}",
+        block_number,
+        wvm_block_fmt.hash.as_deref().unwrap_or_default()
+    );
+
+    Ok(wvm_block_fmt)
+}
+
+/// Receipts of every transaction of the block, in transaction order. Uses
+/// `eth_getBlockReceipts` and falls back to one `eth_getTransactionReceipt` per
+/// transaction on nodes that don't serve it
+pub async fn retrieve_block_receipts(block: &Block) -> Result<Vec<Receipt>, Error> {
+    let block_number = block
+        .number
+        .as_deref()
+        .ok_or_else(|| Error::msg("block without number"))?;
+
+    let request_body = json!({
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockReceipts",
+        "params": [block_number],
+        "id": 1
+    });
+
+    let client = Client::new();
+    let res = client.post(WVM_RPC_URL).json(&request_body).send().await?;
+    let result = res.json::<Value>().await?;
+
+    if result["result"].is_array() {
+        let receipts: Vec<Receipt> = serde_json::from_value(result["result"].clone())?;
+        return Ok(receipts);
+    }
+
+    println!(
+        "eth_getBlockReceipts unavailable for block {} ({}), fetching receipts per transaction",
+        block_number, result["error"]
+    );
+
+    let tx_hashes: Vec<&str> = block
+        .transactions
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(|tx| {
+            tx.hash
+                .as_deref()
+                .ok_or_else(|| Error::msg("transaction without hash"))
+        })
+        .collect::<Result<Vec<&str>, Error>>()?;
+    let mut receipts: Vec<Receipt> = Vec::with_capacity(tx_hashes.len());
+
+    for chunk in tx_hashes.chunks(RPC_BATCH_SIZE) {
+        let batch: Vec<Value> = chunk
+            .iter()
+            .enumerate()
+            .map(|(id, tx_hash)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "eth_getTransactionReceipt",
+                    "params": [tx_hash],
+                    "id": id
+                })
+            })
+            .collect();
+
+        let res = client.post(WVM_RPC_URL).json(&batch).send().await?;
+        let mut results = res.json::<Vec<Value>>().await?;
+        // batch responses may come back in any order
+        results.sort_by_key(|result| result["id"].as_u64());
+
+        if results.len() != chunk.len() {
+            return Err(Error::msg("incomplete receipts batch response"));
+        }
+        for (result, tx_hash) in results.iter().zip(chunk.iter()) {
+            if result["result"].is_null() {
+                return Err(Error::msg(format!(
+                    "no receipt for transaction {}",
+                    tx_hash
+                )));
+            }
+            receipts.push(serde_json::from_value(result["result"].clone())?);
+        }
+    }
+
+    Ok(receipts)
 }
 
 pub async fn get_latest_block_number() -> Result<u64, Error> {
@@ -59,8 +161,39 @@ pub async fn get_latest_block_number() -> Result<u64, Error> {
     Ok(block_number)
 }
 
-pub fn block_hex_to_decimal(hex_str: &str) -> String {
-    let decimal_value =
-        u64::from_str_radix(&hex_str[2..], 16).expect("Failed to convert hex to decimal");
-    decimal_value.to_string()
+/// Canonical block hashes for `block_numbers`, fetched with batched
+/// `eth_getBlockByNumber` calls (header only)
+pub async fn get_block_hashes(block_numbers: &[u32]) -> Result<HashMap<u32, String>, Error> {
+    let client = Client::new();
+    let mut hashes: HashMap<u32, String> = HashMap::with_capacity(block_numbers.len());
+
+    for chunk in block_numbers.chunks(RPC_BATCH_SIZE) {
+        let batch: Vec<Value> = chunk
+            .iter()
+            .map(|block_number| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "eth_getBlockByNumber",
+                    "params": [format!("0x{:x}", block_number), false],
+                    "id": block_number
+                })
+            })
+            .collect();
+
+        let res = client.post(WVM_RPC_URL).json(&batch).send().await?;
+        let results = res.json::<Vec<Value>>().await?;
+
+        for result in results.iter() {
+            let block_number = result["id"]
+                .as_u64()
+                .ok_or_else(|| Error::msg("batch response without id"))?
+                as u32;
+            let hash = result["result"]["hash"].as_str().ok_or_else(|| {
+                Error::msg(format!("no canonical hash for block {}", block_number))
+            })?;
+            hashes.insert(block_number, hash.to_string());
+        }
+    }
+
+    Ok(hashes)
 }
@@ -0,0 +1,98 @@
+# Independent Arweave vectors, following the arweave-js algorithms
+# (common/lib/deepHash.ts, merkle.ts, transaction.ts), written from scratch.
+import base64, hashlib, json, os
+from cryptography.hazmat.primitives.asymmetric import rsa, padding
+from cryptography.hazmat.primitives import hashes
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+def b64(b): return base64.urlsafe_b64encode(b).rstrip(b"=").decode()
+def b64d(s): return base64.urlsafe_b64decode(s + "=" * (-len(s) % 4))
+def sha256(b): return hashlib.sha256(b).digest()
+def sha384(b): return hashlib.sha384(b).digest()
+
+def deep_hash(x):
+    if isinstance(x, list):
+        acc = sha384(b"list" + str(len(x)).encode())
+        for item in x:
+            acc = sha384(acc + deep_hash(item))
+        return acc
+    return sha384(sha384(b"blob" + str(len(x)).encode()) + sha384(x))
+
+MAX, MIN = 256 * 1024, 32 * 1024
+def note(n): return n.to_bytes(32, "big")
+
+def chunk_data(data):
+    chunks, rest, cursor = [], data, 0
+    while len(rest) >= MAX:
+        size = MAX
+        nxt = len(rest) - MAX
+        if 0 < nxt < MIN:
+            size = -(-len(rest) // 2)
+        chunks.append((sha256(rest[:size]), cursor, cursor + size))
+        cursor += size
+        rest = rest[size:]
+    chunks.append((sha256(rest), cursor, cursor + len(rest)))
+    return chunks
+
+def merkle(data):
+    chunks = chunk_data(data)
+    layer = [{"id": sha256(sha256(h) + sha256(note(mx))), "hash": h, "max": mx, "leaf": True}
+             for h, mn, mx in chunks]
+    while len(layer) > 1:
+        nxt = []
+        for i in range(0, len(layer), 2):
+            if i + 1 == len(layer):
+                nxt.append(layer[i]); continue
+            l, r = layer[i], layer[i + 1]
+            nxt.append({"id": sha256(sha256(l["id"]) + sha256(r["id"]) + sha256(note(l["max"]))),
+                        "range": l["max"], "max": r["max"], "l": l, "r": r, "leaf": False})
+        layer = nxt
+    root = layer[0]
+    proofs = []
+    def walk(node, path):
+        if node["leaf"]:
+            proofs.append((node["max"] - 1, path + node["hash"] + note(node["max"])))
+        else:
+            p = path + node["l"]["id"] + node["r"]["id"] + note(node["range"])
+            walk(node["l"], p); walk(node["r"], p)
+    walk(root, b"")
+    if chunks[-1][1] == chunks[-1][2]:
+        chunks, proofs = chunks[:-1], proofs[:-1]
+    return {
+        "data_root": b64(root["id"]),
+        "chunks": [[mn, mx, b64(h)] for h, mn, mx in chunks],
+        "proofs": [[off, b64(p)] for off, p in proofs],
+    }
+
+def data_of(size): return bytes(i % 251 for i in range(size))
+
+out = {}
+out["deep_hash"] = b64(deep_hash([b"hello", [b"", bytes([0, 1, 2])], b"x" * 300]))
+out["merkle"] = {str(size): merkle(data_of(size)) for size in
+                 [1000, 2 * MAX, MAX + 10000, 3 * MAX + 100000]}
+
+key = rsa.generate_private_key(public_exponent=65537, key_size=4096)
+nums = key.private_numbers()
+def ib(n): return n.to_bytes((n.bit_length() + 7) // 8, "big")
+jwk = {"kty": "RSA", "e": b64(ib(nums.public_numbers.e)), "n": b64(ib(nums.public_numbers.n)),
+       "d": b64(ib(nums.d)), "p": b64(ib(nums.p)), "q": b64(ib(nums.q)),
+       "dp": b64(ib(nums.dmp1)), "dq": b64(ib(nums.dmq1)), "qi": b64(ib(nums.iqmp))}
+owner = ib(nums.public_numbers.n)
+
+tx_data = data_of(MAX + 10000)
+last_tx = bytes(range(48))
+tags = [("Content-Type", "application/octet-stream"), ("Block-Number", "1200")]
+reward = "123456789"
+sig_data = deep_hash([b"2", owner, b"", b"0", reward.encode(), last_tx,
+                      [[n.encode(), v.encode()] for n, v in tags],
+                      str(len(tx_data)).encode(), b64d(out["merkle"][str(MAX + 10000)]["data_root"])])
+signature = key.sign(sig_data, padding.PSS(mgf=padding.MGF1(hashes.SHA256()), salt_length=32), hashes.SHA256())
+out["transaction"] = {
+    "data_size": len(tx_data), "last_tx": b64(last_tx), "reward": reward,
+    "tags": [[n, v] for n, v in tags],
+    "signature_data": b64(sig_data), "signature": b64(signature), "id": b64(sha256(signature)),
+    "owner_address": b64(sha256(owner)),
+}
+json.dump(jwk, open(os.path.join(HERE, "wallet.json"), "w"), indent=2)
+json.dump(out, open(os.path.join(HERE, "vectors.json"), "w"), indent=2)